@@ -0,0 +1,269 @@
+//! 同步下载接口，内部持有 tokio 运行时，适用于没有异步运行时的程序（类似 `reqwest::blocking`）
+//!
+//! 不能在异步运行时中调用，否则会 panic
+
+use std::io;
+use std::num::NonZeroU64;
+use std::path::PathBuf;
+
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+
+use crate::{DownloadError, DownloadExtensionBuilder, DownloadingEndCause, DownloadStartError, ExtendedHttpFileDownloader, HttpDownloaderBuilder};
+
+#[derive(Error, Debug)]
+pub enum BlockingDownloadError {
+    #[error("runtime build failed，{:?}", .0)]
+    RuntimeBuildFailed(io::Error),
+    #[error("{:?}", .0)]
+    DownloadStartError(#[from] DownloadStartError),
+    #[error("{:?}", .0)]
+    DownloadError(Box<DownloadError>),
+}
+
+impl From<DownloadError> for BlockingDownloadError {
+    fn from(value: DownloadError) -> Self {
+        BlockingDownloadError::DownloadError(Box::new(value))
+    }
+}
+
+pub type Result<T> = std::result::Result<T, BlockingDownloadError>;
+
+/// 下载进度
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    pub downloaded_len: u64,
+    pub total_size: Option<NonZeroU64>,
+}
+
+/// 下载结束后的结果
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub end_cause: DownloadingEndCause,
+    pub file_path: PathBuf,
+    pub downloaded_len: u64,
+    pub total_size: Option<NonZeroU64>,
+}
+
+/// 取消句柄，可以在其他线程中取消下载
+#[derive(Debug, Clone)]
+pub struct CancelHandle {
+    token: CancellationToken,
+}
+
+impl CancelHandle {
+    /// 取消下载，`download_to_end` 会在扩展处理完取消（例如保存断点续传数据）后返回 `DownloadingEndCause::Cancelled`
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    /// 是否已经取消
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+}
+
+pub struct BlockingDownloader {
+    runtime: tokio::runtime::Runtime,
+    downloader: ExtendedHttpFileDownloader,
+    cancel_token: CancellationToken,
+    progress: Option<Box<dyn FnMut(Progress)>>,
+}
+
+impl BlockingDownloader {
+    /// 不开启扩展
+    pub fn new(builder: HttpDownloaderBuilder) -> Result<Self> {
+        Ok(Self::with_extensions(builder, ())?.0)
+    }
+
+    /// 参数与 `HttpDownloaderBuilder::build` 相同，扩展在内部运行时中构建
+    pub fn with_extensions<DEB: DownloadExtensionBuilder>(builder: HttpDownloaderBuilder, extension_builder: DEB) -> Result<(Self, DEB::ExtensionState)> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(BlockingDownloadError::RuntimeBuildFailed)?;
+        let (downloader, extension_state) = {
+            let _guard = runtime.enter();
+            builder.build(extension_builder)
+        };
+        Ok((Self {
+            runtime,
+            downloader,
+            cancel_token: CancellationToken::new(),
+            progress: None,
+        }, extension_state))
+    }
+
+    /// 下载长度变化时在调用 `download_to_end` 的线程中调用，间隔由 `downloaded_len_send_interval` 决定
+    pub fn on_progress(mut self, progress: impl FnMut(Progress) + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// 取消后再次下载需要重新获取
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle { token: self.cancel_token.clone() }
+    }
+
+    pub fn downloader(&self) -> &ExtendedHttpFileDownloader {
+        &self.downloader
+    }
+
+    /// 开始下载，阻塞直到下载完成、取消或出错，可以多次调用（例如取消后继续下载）
+    pub fn download_to_end(&mut self) -> Result<Outcome> {
+        let Self { runtime, downloader, cancel_token, progress } = self;
+        let end_cause = runtime.block_on(async {
+            // 需要在开始下载前获取
+            let mut downloading_state_receiver = Some(downloader.downloading_state_receiver());
+            let mut download_future = downloader.prepare_download()?;
+            let mut downloaded_len_receiver = downloader.downloaded_len_receiver().clone();
+            let end_cause = loop {
+                tokio::select! {
+                    result = &mut download_future => break result,
+                    _ = cancel_token.cancelled(), if downloading_state_receiver.is_some() => {
+                        // 首次请求结束前还没有下载状态，此时取消不会生效，等到下载状态存在后再取消；
+                        // 下载状态出现前下载就结束（比如出错）时不需要取消
+                        let downloading_state_receiver = downloading_state_receiver.take().unwrap();
+                        let cancel = downloader.cancel();
+                        tokio::spawn(async move {
+                            if downloading_state_receiver.await.is_ok() {
+                                cancel.await;
+                            }
+                        });
+                    }
+                    Ok(()) = downloaded_len_receiver.changed() => {
+                        if let Some(progress) = progress.as_mut() {
+                            progress(Progress {
+                                downloaded_len: *downloaded_len_receiver.borrow_and_update(),
+                                total_size: downloader.current_total_size(),
+                            });
+                        }
+                    }
+                }
+            }?;
+            Result::Ok(end_cause)
+        })?;
+        // 取消后可以再次下载
+        if cancel_token.is_cancelled() {
+            *cancel_token = CancellationToken::new();
+        }
+        let outcome = Outcome {
+            end_cause,
+            file_path: downloader.get_file_path(),
+            downloaded_len: downloader.downloaded_len(),
+            total_size: downloader.current_total_size(),
+        };
+        if let Some(progress) = progress.as_mut() {
+            progress(Progress { downloaded_len: outcome.downloaded_len, total_size: outcome.total_size });
+        }
+        Ok(outcome)
+    }
+}
+
+/// 不开启扩展，下载到结束
+pub fn download_to_end(builder: HttpDownloaderBuilder) -> Result<Outcome> {
+    BlockingDownloader::new(builder)?.download_to_end()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::{NonZeroU8, NonZeroUsize};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    use crate::test_server::{TestResponse, TestServer};
+
+    use super::*;
+
+    // 测试服务器运行在另一个运行时中，阻塞接口不能在异步运行时中调用
+    fn start_server(data: Arc<Vec<u8>>, slow: Arc<AtomicBool>) -> (tokio::runtime::Runtime, TestServer) {
+        let runtime = tokio::runtime::Builder::new_multi_thread().worker_threads(4).enable_all().build().unwrap();
+        let server = runtime.block_on(TestServer::start(move |request| {
+            // 首次请求和第一个 chunk 之后的响应变慢，下载过程中有时间取消
+            if slow.load(Ordering::SeqCst) && !request.header("range").is_some_and(|n| n.starts_with("bytes=0-")) {
+                std::thread::sleep(Duration::from_millis(200));
+            }
+            TestResponse::ranged(request, &data)
+        }));
+        (runtime, server)
+    }
+
+    fn builder(server: &TestServer, save_dir: &std::path::Path) -> HttpDownloaderBuilder {
+        HttpDownloaderBuilder::new(server.url("/file.bin"), save_dir.to_path_buf())
+            .chunk_size(NonZeroUsize::new(64 * 1024).unwrap())
+            .download_connection_count(NonZeroU8::new(2).unwrap())
+            .downloaded_len_send_interval(Some(Duration::from_millis(10)))
+    }
+
+    #[test]
+    fn download_with_progress() {
+        let data: Arc<Vec<u8>> = Arc::new((0..1024 * 1024u32).map(|n| (n % 251) as u8).collect());
+        let (_runtime, server) = start_server(data.clone(), Default::default());
+        let save_dir = std::env::temp_dir().join(format!("http-downloader-blocking-{}", std::process::id()));
+
+        let progress = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let mut downloader = BlockingDownloader::new(builder(&server, &save_dir)).unwrap().on_progress({
+            let progress = progress.clone();
+            move |n| progress.lock().push(n.downloaded_len)
+        });
+        let outcome = downloader.download_to_end().unwrap();
+        assert_eq!(outcome.end_cause, DownloadingEndCause::DownloadFinished);
+        assert_eq!((outcome.downloaded_len, outcome.total_size.map(|n| n.get())), (data.len() as u64, Some(data.len() as u64)));
+        assert_eq!(std::fs::read(&outcome.file_path).unwrap(), *data);
+        let progress = progress.lock();
+        assert!(progress.windows(2).all(|n| n[0] <= n[1]));
+        assert_eq!(progress.last().copied(), Some(data.len() as u64));
+
+        std::fs::remove_file(&outcome.file_path).unwrap();
+        let outcome = download_to_end(builder(&server, &save_dir)).unwrap();
+        assert_eq!(std::fs::read(&outcome.file_path).unwrap(), *data);
+        std::fs::remove_dir_all(&save_dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "bson-file-archiver")]
+    fn cancel_before_and_after_probe() {
+        use crate::breakpoint_resume::DownloadBreakpointResumeExtension;
+        use crate::bson_file_archiver::{ArchiveFilePath, BsonFileArchiverBuilder};
+
+        let data: Arc<Vec<u8>> = Arc::new((0..1024 * 1024u32).map(|n| (n % 241) as u8).collect());
+        let slow = Arc::new(AtomicBool::new(true));
+        let (_runtime, server) = start_server(data.clone(), slow.clone());
+        let save_dir = std::env::temp_dir().join(format!("http-downloader-blocking-cancel-{}", std::process::id()));
+
+        // 开始下载前取消，首次请求结束后才有下载状态，取消不能丢失
+        let extension = DownloadBreakpointResumeExtension::new(BsonFileArchiverBuilder::new(ArchiveFilePath::Suffix("bson".to_string())));
+        let (mut downloader, _) = BlockingDownloader::with_extensions(builder(&server, &save_dir), extension).unwrap();
+        downloader.cancel_handle().cancel();
+        assert_eq!(downloader.download_to_end().unwrap().end_cause, DownloadingEndCause::Cancelled);
+        assert!(!downloader.cancel_handle().is_cancelled());
+
+        // 收到数据后取消
+        let cancel_handle = downloader.cancel_handle();
+        let mut downloader = downloader.on_progress(move |n| {
+            if n.downloaded_len >= 64 * 1024 {
+                cancel_handle.cancel();
+            }
+        });
+        let outcome = downloader.download_to_end().unwrap();
+        assert_eq!(outcome.end_cause, DownloadingEndCause::Cancelled);
+        assert!(outcome.downloaded_len < data.len() as u64);
+
+        // 再次下载时从断点继续，已下载的部分不再请求
+        slow.store(false, Ordering::SeqCst);
+        // 被取消的慢请求在处理结束后才记录，等它们记录完
+        std::thread::sleep(Duration::from_millis(300));
+        let request_count = server.requests().len();
+        let mut downloader = downloader.on_progress(|_| {});
+        let outcome = downloader.download_to_end().unwrap();
+        assert_eq!(outcome.end_cause, DownloadingEndCause::DownloadFinished);
+        assert_eq!(std::fs::read(&outcome.file_path).unwrap(), *data);
+        let requested_len: u64 = server.requests()[request_count..]
+            .iter()
+            .filter_map(|n| n.header("range")?.strip_prefix("bytes=")?.split_once('-').map(|(start, end)| end.parse::<u64>().unwrap() - start.parse::<u64>().unwrap() + 1))
+            .sum();
+        assert!(requested_len <= data.len() as u64 - 64 * 1024);
+        std::fs::remove_dir_all(&save_dir).unwrap();
+    }
+}
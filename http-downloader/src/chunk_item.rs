@@ -1,6 +1,6 @@
 use std::io::SeekFrom;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 use anyhow::Result;
 use bytes::Bytes;
@@ -16,42 +16,198 @@ use tokio_util::sync::CancellationToken;
 #[cfg(feature = "tracing")]
 use tracing::Instrument;
 
-use crate::{ChunkInfo, ChunkManager, ChunkRange, DownloadError, DownloadingEndCause};
+use crate::{ChunkData, ChunkHash, ChunkInfo, ClientConfig, ChunkManager, ChunkRange, LenChangedNotify, DownloadError, DownloadingEndCause, HostConnectionLimiter, HttpResponseInvalidCause, ProgressMap, RequestInterceptor, SharedDownloaderWrapper};
+use crate::local_source;
+use crate::request_interceptor;
+use crate::url_refresher::{MAX_URL_REFRESH_TIMES, UrlRefresh};
 
 pub trait DownloadedLenChangeNotify: Send + Sync {
     fn receive_len(&self, len: usize) -> OptionFuture<BoxFuture<()>>;
 }
 
+/// 尾段竞速中的一方
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ChunkRaceSide {
+    /// 原 chunk
+    Original,
+    /// 重复请求原 chunk 未完成尾部的竞速连接
+    Racer,
+}
+
+/// 尾段竞速（endgame）状态，由原 chunk 与竞速连接共享
+///
+/// 先完成的一方胜出并取消另一方，写入文件时：
+/// - 竞速连接只有胜出时才写入
+/// - 原 chunk 落败时只写入 `split_at` 之前的数据
+pub struct ChunkRace {
+    pub split_at: u64,
+    winner: parking_lot::Mutex<Option<ChunkRaceSide>>,
+    original_cancel_token: CancellationToken,
+    racer_cancel_token: CancellationToken,
+}
+
+impl ChunkRace {
+    pub fn winner(&self) -> Option<ChunkRaceSide> {
+        *self.winner.lock()
+    }
+
+    fn try_win(&self, side: ChunkRaceSide) -> bool {
+        let mut winner = self.winner.lock();
+        match *winner {
+            Some(winner) => winner == side,
+            None => {
+                *winner = Some(side);
+                match side {
+                    ChunkRaceSide::Original => self.racer_cancel_token.cancel(),
+                    ChunkRaceSide::Racer => self.original_cancel_token.cancel(),
+                }
+                true
+            }
+        }
+    }
+}
+
 pub struct ChunkItem {
     pub chunk_info: ChunkInfo,
     pub downloaded_len: AtomicU64,
     cancel_token: CancellationToken,
     client: reqwest::Client,
+    // 本地发送的 ftp:// 请求使用
+    client_config: Arc<ClientConfig>,
     file: Arc<Mutex<File>>,
     etag: Option<headers::ETag>,
+    // 写入文件后记录校验和
+    chunk_data: Arc<parking_lot::RwLock<ChunkData>>,
+    // 接收到数据后更新，竞速连接不更新
+    progress_map: Arc<parking_lot::RwLock<ProgressMap>>,
+    // 发出请求前获取主机的连接许可
+    host_connection_limiter: Option<Arc<HostConnectionLimiter>>,
+    request_interceptors: Arc<[Arc<dyn RequestInterceptor>]>,
+    url_refresh: Option<Arc<UrlRefresh>>,
+    // 扩展的 chunk 级别钩子
+    chunk_hooks: Option<SharedDownloaderWrapper>,
+    // 远程资源中的偏移，chunk 的范围为文件中的位置，请求时需要加上此偏移
+    range_offset: u64,
+    race: parking_lot::RwLock<Option<(Arc<ChunkRace>, ChunkRaceSide)>>,
+    // 每个 chunk 只创建一次竞速连接，避免服务器拒绝额外的连接时不断重新连接
+    raced: AtomicBool,
 }
 
 impl ChunkItem {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         chunk_info: ChunkInfo,
         cancel_token: CancellationToken,
         client: reqwest::Client,
+        client_config: Arc<ClientConfig>,
         file: Arc<Mutex<File>>,
         etag: Option<headers::ETag>,
+        chunk_data: Arc<parking_lot::RwLock<ChunkData>>,
+        progress_map: Arc<parking_lot::RwLock<ProgressMap>>,
+        host_connection_limiter: Option<Arc<HostConnectionLimiter>>,
+        request_interceptors: Arc<[Arc<dyn RequestInterceptor>]>,
+        url_refresh: Option<Arc<UrlRefresh>>,
+        chunk_hooks: Option<SharedDownloaderWrapper>,
+        range_offset: u64,
     ) -> Self {
         Self {
             downloaded_len: AtomicU64::new(0),
             cancel_token,
             client,
+            client_config,
             chunk_info,
             file,
             etag,
+            chunk_data,
+            progress_map,
+            host_connection_limiter,
+            request_interceptors,
+            url_refresh,
+            chunk_hooks,
+            range_offset,
+            race: Default::default(),
+            raced: AtomicBool::new(false),
+        }
+    }
+
+    /// 当前所在的尾段竞速
+    pub fn race(&self) -> Option<Arc<ChunkRace>> {
+        self.race.read().as_ref().map(|(race, _)| race.clone())
+    }
+
+    pub fn race_side(&self) -> Option<ChunkRaceSide> {
+        self.race.read().as_ref().map(|(_, side)| *side)
+    }
+
+    /// 是否可以创建竞速连接：不在竞速中，且之前没有创建过
+    pub(crate) fn can_race(&self) -> bool {
+        self.race.read().is_none() && !self.raced.load(Ordering::SeqCst)
+    }
+
+    /// 为未完成的尾部创建竞速连接，已在竞速中、已创建过或已接收完毕时返回 None
+    pub(crate) fn start_race(&self, racer_cancel_token: CancellationToken) -> Option<ChunkItem> {
+        let mut race = self.race.write();
+        if race.is_some() || self.raced.load(Ordering::SeqCst) {
+            return None;
+        }
+        let downloaded_len = self.downloaded_len.load(Ordering::SeqCst);
+        if downloaded_len >= self.chunk_info.range.len() {
+            return None;
+        }
+        self.raced.store(true, Ordering::SeqCst);
+        let split_at = self.chunk_info.range.start + downloaded_len;
+        let chunk_race = Arc::new(ChunkRace {
+            split_at,
+            winner: Default::default(),
+            original_cancel_token: self.cancel_token.clone(),
+            racer_cancel_token: racer_cancel_token.clone(),
+        });
+        *race = Some((chunk_race.clone(), ChunkRaceSide::Original));
+        Some(ChunkItem {
+            chunk_info: ChunkInfo {
+                index: self.chunk_info.index,
+                range: ChunkRange::new(split_at, self.chunk_info.range.end),
+            },
+            downloaded_len: AtomicU64::new(0),
+            cancel_token: racer_cancel_token,
+            client: self.client.clone(),
+            client_config: self.client_config.clone(),
+            file: self.file.clone(),
+            etag: self.etag.clone(),
+            chunk_data: self.chunk_data.clone(),
+            progress_map: self.progress_map.clone(),
+            host_connection_limiter: self.host_connection_limiter.clone(),
+            request_interceptors: self.request_interceptors.clone(),
+            url_refresh: self.url_refresh.clone(),
+            chunk_hooks: self.chunk_hooks.clone(),
+            range_offset: self.range_offset,
+            race: parking_lot::RwLock::new(Some((chunk_race, ChunkRaceSide::Racer))),
+            raced: AtomicBool::new(true),
+        })
+    }
+
+    /// 取消这个 chunk 的下载，已接收的数据会写入文件
+    pub(crate) fn cancel(&self) {
+        self.cancel_token.cancel();
+    }
+
+    /// 竞速连接未胜出就结束时，解除原 chunk 的竞速状态，使其可以再次参与竞速
+    pub(crate) fn end_unsettled_race(&self) {
+        let mut race = self.race.write();
+        if matches!(race.as_ref(), Some((chunk_race, _)) if chunk_race.winner().is_none()) {
+            *race = None;
         }
     }
 
     #[inline]
     fn add_downloaded_len(&self, len: usize) {
-        self.downloaded_len.fetch_add(len as u64, Ordering::Relaxed);
+        let downloaded_len = self.downloaded_len.fetch_add(len as u64, Ordering::Relaxed);
+        if len != 0 && self.race_side() != Some(ChunkRaceSide::Racer) {
+            self.progress_map.write().add_range(ChunkRange::from_len(
+                self.chunk_info.range.start + downloaded_len,
+                len as u64,
+            ));
+        }
         debug_assert!(
             self.downloaded_len.load(Ordering::SeqCst) <= self.chunk_info.range.len(),
             "downloaded_len:{},chunk_info.range.len():{}",
@@ -60,31 +216,152 @@ impl ChunkItem {
         );
     }
 
+    /// 将接收到的数据写入文件，返回 `true` 表示在尾段竞速中落败
+    async fn save_chunk_bytes(&self, chunk_bytes: &[u8], is_finished: bool) -> Result<bool, DownloadError> {
+        debug_assert!(
+            chunk_bytes.len() as u64 <= self.chunk_info.range.len(),
+            "chunk_bytes.len() = {}, self.chunk_info.range.len() = {}",
+            chunk_bytes.len(),
+            self.chunk_info.range.len()
+        );
+        let mut file = self.file.lock().await;
+        // 在文件锁内决定胜负，保证竞速双方的写入不会重叠
+        let race = self.race.read().clone();
+        let (chunk_bytes, lost) = match race {
+            None => (chunk_bytes, false),
+            Some((race, ChunkRaceSide::Racer)) => {
+                if is_finished && race.try_win(ChunkRaceSide::Racer) {
+                    (chunk_bytes, false)
+                } else {
+                    (&chunk_bytes[..0], true)
+                }
+            }
+            Some((race, ChunkRaceSide::Original)) => {
+                if is_finished && race.try_win(ChunkRaceSide::Original) {
+                    (chunk_bytes, false)
+                } else if race.winner() == Some(ChunkRaceSide::Racer) {
+                    let len = (race.split_at - self.chunk_info.range.start) as usize;
+                    (&chunk_bytes[..len.min(chunk_bytes.len())], true)
+                } else {
+                    (chunk_bytes, false)
+                }
+            }
+        };
+        if !chunk_bytes.is_empty() {
+            file.seek(SeekFrom::Start(self.chunk_info.range.start)).await?;
+            file.write_all(chunk_bytes).await?;
+            file.flush().await?;
+            file.sync_all().await?;
+            self.chunk_data.write().chunk_hashes.push(ChunkHash {
+                range: ChunkRange::from_len(self.chunk_info.range.start, chunk_bytes.len() as u64),
+                crc32: crc32fast::hash(chunk_bytes),
+            });
+        }
+        Ok(lost)
+    }
+
+    /// 竞速落败：原 chunk 的剩余部分已由竞速连接写入，视为完成；竞速连接视为取消
+    ///
+    /// 竞速连接接收数据时已经通知过扩展（比如限速），这里只补上下载长度
+    fn end_lost_race(&self, chunk_bytes: &[u8], downloaded_len_receiver: Option<&LenChangedNotify>) -> DownloadingEndCause {
+        match self.race() {
+            Some(race) if self.race_side() == Some(ChunkRaceSide::Original) => {
+                let len = (self.chunk_info.range.len() - self.downloaded_len.load(Ordering::SeqCst)) as usize;
+                self.add_downloaded_len(len);
+                if let Some(downloaded_len_receiver) = downloaded_len_receiver {
+                    downloaded_len_receiver.add_downloaded_len(len);
+                }
+                // 只写入了竞速开始之前的部分
+                let written_len = (race.split_at - self.chunk_info.range.start) as usize;
+                self.on_chunk_finished(&chunk_bytes[..written_len.min(chunk_bytes.len())]);
+                DownloadingEndCause::DownloadFinished
+            }
+            _ => DownloadingEndCause::Cancelled,
+        }
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(name = "download chunk", skip_all, fields(chunk_index = self.chunk_info.index)))]
     pub(crate) async fn download_chunk(
         self: Arc<Self>,
         mut request: Box<Request>,
         retry_count: u8,
-        downloaded_len_receiver: Option<impl DownloadedLenChangeNotify>,
+        downloaded_len_receiver: Option<LenChangedNotify>,
     ) -> Result<DownloadingEndCause, DownloadError> {
         let cancel_token = self.cancel_token.clone();
         let mut chunk_bytes = Vec::with_capacity(self.chunk_info.range.len() as usize);
 
         let mut cur_retry_count = 0;
+        let mut interceptor_retry_count = 0;
+        let mut url_refresh_count = 0;
+        // chunk 开始前地址可能已经刷新过
+        let mut url_generation = match self.url_refresh.as_ref() {
+            None => 0,
+            Some(url_refresh) => {
+                let (generation, refreshed_url) = url_refresh.current();
+                if let Some(refreshed_url) = refreshed_url {
+                    refreshed_url.apply(&mut request);
+                }
+                generation
+            }
+        };
+        // Range 偏移对应原始数据，不能接受压缩后的响应
+        request.headers_mut().insert(reqwest::header::ACCEPT_ENCODING, headers::HeaderValue::from_static("identity"));
         let future = async {
             'r: loop {
                 request.headers_mut().typed_insert(
                     ChunkRange::new(
-                        self.chunk_info.range.start + chunk_bytes.len() as u64,
-                        self.chunk_info.range.end,
+                        self.range_offset + self.chunk_info.range.start + chunk_bytes.len() as u64,
+                        self.range_offset + self.chunk_info.range.end,
                     )
                         .to_range_header(),
                 );
                 // 避免 clone request ?
-                let response = self.client.execute(*ChunkManager::clone_request(&request));
+                let mut chunk_request = ChunkManager::clone_request(&request);
+                request_interceptor::intercept_request(&self.request_interceptors, &mut chunk_request);
+                if let Some(chunk_hooks) = self.chunk_hooks.as_ref() {
+                    chunk_hooks.read().before_chunk_request(&self, &mut chunk_request);
+                }
+                // 拦截器与钩子可能把请求换到镜像等其他主机，按最终的地址获取许可，在本次响应内容接收完毕或重试时释放
+                let _permit = match self.host_connection_limiter.as_ref() {
+                    None => None,
+                    Some(host_connection_limiter) => Some(host_connection_limiter.acquire(chunk_request.url()).await),
+                };
+                let response = local_source::execute(&self.client, &self.client_config, *chunk_request);
                 #[cfg(feature = "tracing")]
                     let response = response.instrument(tracing::info_span!("chunk's http request"));
                 let response = match response.await {
+                    Ok(response) => response,
+                    Err(err) => {
+                        cur_retry_count += 1;
+                        #[cfg(feature = "tracing")]
+                        tracing::trace!(
+                            "Request error! {:?},retry_info: {}/{}",
+                            err,
+                            cur_retry_count,
+                            retry_count
+                        );
+                        if cur_retry_count > retry_count {
+                            return Err(DownloadError::HttpRequestFailed(err));
+                        }
+                        continue 'r;
+                    }
+                };
+                if request_interceptor::intercept_response(&self.request_interceptors, &response)
+                    && interceptor_retry_count < request_interceptor::MAX_INTERCEPTOR_RETRY_TIMES {
+                    interceptor_retry_count += 1;
+                    continue 'r;
+                }
+                if let Some(url_refresh) = self.url_refresh.as_ref() {
+                    if UrlRefresh::is_expired_status(response.status()) && url_refresh_count < MAX_URL_REFRESH_TIMES {
+                        url_refresh_count += 1;
+                        let (generation, refreshed_url) = url_refresh.refresh(request.url(), url_generation).await?;
+                        refreshed_url.apply(&mut request);
+                        url_generation = generation;
+                        continue 'r;
+                    }
+                }
+                // 错误状态码按失败重试，超过重试次数后结束下载，不会把错误页面写入文件
+                let response = match response.error_for_status() {
                     Ok(response) => {
                         cur_retry_count = 0;
                         response
@@ -93,7 +370,7 @@ impl ChunkItem {
                         cur_retry_count += 1;
                         #[cfg(feature = "tracing")]
                         tracing::trace!(
-                            "Request error! {:?},retry_info: {}/{}",
+                            "Response status error! {:?},retry_info: {}/{}",
                             err,
                             cur_retry_count,
                             retry_count
@@ -104,6 +381,16 @@ impl ChunkItem {
                         continue 'r;
                     }
                 };
+                // 返回的不是请求的范围，写入文件会损坏数据
+                if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+                    return Err(DownloadError::HttpRequestResponseInvalid(HttpResponseInvalidCause::ChunkRangeNotSatisfied, response));
+                }
+                if let Some(url_refresh) = self.url_refresh.as_ref() {
+                    if !url_refresh.is_same_file(&response) {
+                        return Err(DownloadError::ServerFileAlreadyChanged);
+                    }
+                    url_refresh_count = 0;
+                }
                 if self.etag.is_some() {
                     let etag = response.headers().typed_get::<headers::ETag>();
                     if etag != self.etag {
@@ -138,19 +425,6 @@ impl ChunkItem {
                                     retry_count
                                 );
                                 if cur_retry_count > retry_count {
-                                    // 出错后与取消一样处理：将缓冲中的数据写入磁盘并持久化数据
-                                    let mut file = self.file.lock().await;
-                                    file.seek(SeekFrom::Start(self.chunk_info.range.start))
-                                        .await?;
-                                    debug_assert!(
-                                        chunk_bytes.len() as u64 <= self.chunk_info.range.len(),
-                                        "chunk_bytes.len() = {}, self.chunk_info.range.len() = {}",
-                                        chunk_bytes.len(),
-                                        self.chunk_info.range.len()
-                                    );
-                                    file.write_all(chunk_bytes.as_ref()).await?;
-                                    file.flush().await?;
-                                    file.sync_all().await?;
                                     return Err(DownloadError::HttpRequestFailed(err));
                                 }
                                 continue 'r;
@@ -158,6 +432,9 @@ impl ChunkItem {
                         }
                     };
                     let len = bytes.len();
+                    if let Some(chunk_hooks) = self.chunk_hooks.as_ref() {
+                        chunk_hooks.read().on_chunk_bytes(&self, self.chunk_info.range.start + chunk_bytes.len() as u64, &bytes);
+                    }
                     chunk_bytes.extend(bytes);
                     self.add_downloaded_len(len);
                     if let Some(downloaded_len_receiver) = downloaded_len_receiver.as_ref() {
@@ -169,26 +446,47 @@ impl ChunkItem {
             Result::<(), DownloadError>::Ok(())
         };
 
-        select! {
+        let result = select! {
             r = future => {
-                r?;
-                let mut file = self.file.lock().await;
-                file.seek(SeekFrom::Start(self.chunk_info.range.start)).await?;
-                debug_assert_eq!(chunk_bytes.len() as u64,self.chunk_info.range.len());
-                file.write_all(chunk_bytes.as_ref()).await?;
-                file.flush().await?;
-                file.sync_all().await?;
-                Ok(DownloadingEndCause::DownloadFinished)
+                match r {
+                    Ok(()) => {
+                        debug_assert_eq!(chunk_bytes.len() as u64,self.chunk_info.range.len());
+                        match self.save_chunk_bytes(&chunk_bytes, true).await {
+                            Ok(false) => {
+                                self.on_chunk_finished(&chunk_bytes);
+                                Ok(DownloadingEndCause::DownloadFinished)
+                            }
+                            Ok(true) => Ok(self.end_lost_race(&chunk_bytes, downloaded_len_receiver.as_ref())),
+                            Err(err) => Err(err),
+                        }
+                    }
+                    Err(err) => {
+                        // 出错后与取消一样处理：将缓冲中的数据写入磁盘并持久化数据
+                        match self.save_chunk_bytes(&chunk_bytes, false).await {
+                            Ok(false) => Err(err),
+                            Ok(true) => Ok(self.end_lost_race(&chunk_bytes, downloaded_len_receiver.as_ref())),
+                            Err(save_err) => Err(save_err),
+                        }
+                    }
+                }
             }
             _ = cancel_token.cancelled() => {
-                let mut file = self.file.lock().await;
-                file.seek(SeekFrom::Start(self.chunk_info.range.start)).await?;
-                debug_assert!(chunk_bytes.len() as u64 <= self.chunk_info.range.len(),"chunk_bytes.len() = {}, self.chunk_info.range.len() = {}", chunk_bytes.len(), self.chunk_info.range.len());
-                file.write_all(chunk_bytes.as_ref()).await?;
-                file.flush().await?;
-                file.sync_all().await?;
-                Ok(DownloadingEndCause::Cancelled)
+                match self.save_chunk_bytes(&chunk_bytes, false).await {
+                    Ok(false) => Ok(DownloadingEndCause::Cancelled),
+                    Ok(true) => Ok(self.end_lost_race(&chunk_bytes, downloaded_len_receiver.as_ref())),
+                    Err(err) => Err(err),
+                }
             }
+        };
+        if let (Err(err), Some(chunk_hooks)) = (result.as_ref(), self.chunk_hooks.as_ref()) {
+            chunk_hooks.read().on_chunk_failed(&self, err);
+        }
+        result
+    }
+
+    fn on_chunk_finished(&self, chunk_bytes: &[u8]) {
+        if let Some(chunk_hooks) = self.chunk_hooks.as_ref() {
+            chunk_hooks.read().on_chunk_finished(self, self.chunk_info.range.start, chunk_bytes);
         }
     }
     /*
@@ -227,6 +525,94 @@ impl ChunkItem {
         }*/
 }
 
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use crate::RemainingChunks;
+
+    use super::*;
+
+    fn chunk_item(file: Arc<Mutex<File>>, chunk_data: Arc<parking_lot::RwLock<ChunkData>>, range: ChunkRange) -> ChunkItem {
+        ChunkItem::new(
+            ChunkInfo { index: 1, range },
+            CancellationToken::new(),
+            reqwest::Client::new(),
+            Default::default(),
+            file,
+            None,
+            chunk_data,
+            Arc::new(parking_lot::RwLock::new(ProgressMap::new(100, NonZeroUsize::new(4).unwrap()))),
+            None,
+            Vec::new().into(),
+            None,
+            None,
+            0,
+        )
+    }
+
+    #[tokio::test]
+    async fn race_split_and_loser_accounting() {
+        let file_path = std::env::temp_dir().join(format!("http-downloader-race-{}", std::process::id()));
+        let file = Arc::new(Mutex::new(File::create(&file_path).await.unwrap()));
+        let chunk_data = Arc::new(parking_lot::RwLock::new(ChunkData {
+            iter_count: 1,
+            remaining: RemainingChunks { chunk_size: 100, ranges: vec![] },
+            last_incomplete_chunks: vec![],
+            chunk_hashes: vec![],
+        }));
+        let data: Vec<u8> = (0..100).collect();
+        let original = chunk_item(file, chunk_data.clone(), ChunkRange::new(0, 99));
+        original.downloaded_len.store(40, Ordering::SeqCst);
+
+        let racer = original.start_race(CancellationToken::new()).unwrap();
+        assert_eq!(racer.chunk_info.range, ChunkRange::new(40, 99));
+        assert_eq!(original.race().unwrap().split_at, 40);
+        assert!(!original.can_race());
+        assert!(original.start_race(CancellationToken::new()).is_none());
+
+        // 竞速连接先完成，原 chunk 被取消，只写入竞速开始前的部分
+        assert!(!racer.save_chunk_bytes(&data[40..], true).await.unwrap());
+        assert!(original.cancel_token.is_cancelled());
+        assert!(original.save_chunk_bytes(&data[..70], false).await.unwrap());
+
+        let (downloaded_len_sender, downloaded_len_receiver) = tokio::sync::watch::channel(70);
+        let notify = LenChangedNotify::new(Some(Arc::new(downloaded_len_sender)), None);
+        original.downloaded_len.store(70, Ordering::SeqCst);
+        assert_eq!(original.end_lost_race(&data[..70], Some(&notify)), DownloadingEndCause::DownloadFinished);
+        assert_eq!(original.downloaded_len.load(Ordering::SeqCst), 100);
+        assert_eq!(*downloaded_len_receiver.borrow(), 100);
+        assert_eq!(racer.end_lost_race(&data[40..], None), DownloadingEndCause::Cancelled);
+
+        assert_eq!(tokio::fs::read(&file_path).await.unwrap(), data);
+        let mut written: Vec<_> = chunk_data.read().chunk_hashes.iter().map(|n| (n.range.start, n.range.end)).collect();
+        written.sort();
+        assert_eq!(written, vec![(0, 39), (40, 99)]);
+        tokio::fs::remove_file(&file_path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn unsettled_race_is_not_retried() {
+        let file_path = std::env::temp_dir().join(format!("http-downloader-unsettled-race-{}", std::process::id()));
+        let file = Arc::new(Mutex::new(File::create(&file_path).await.unwrap()));
+        let chunk_data = Arc::new(parking_lot::RwLock::new(ChunkData {
+            iter_count: 1,
+            remaining: RemainingChunks { chunk_size: 100, ranges: vec![] },
+            last_incomplete_chunks: vec![],
+            chunk_hashes: vec![],
+        }));
+        let original = chunk_item(file, chunk_data, ChunkRange::new(0, 99));
+        assert!(original.can_race());
+        assert!(original.start_race(CancellationToken::new()).is_some());
+        // 竞速连接失败后原 chunk 不再处于竞速中，但不会再次创建竞速连接
+        original.end_unsettled_race();
+        assert!(original.race().is_none());
+        assert!(!original.can_race());
+        assert!(original.start_race(CancellationToken::new()).is_none());
+        tokio::fs::remove_file(&file_path).await.unwrap();
+    }
+}
+
 /*pub struct DownloadedChunkItem {
     pub chunk_item: Arc<ChunkItem>,
     pub join_handle: JoinHandle<()>,
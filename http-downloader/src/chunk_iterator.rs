@@ -1,6 +1,9 @@
 use std::collections::Bound;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
 use std::num::NonZeroUsize;
 use std::ops::RangeBounds;
+use std::path::Path;
 use std::sync::Arc;
 
 #[cfg(feature = "serde")]
@@ -64,6 +67,28 @@ impl RemainingChunks {
             }
         }
     }
+
+    /// 将与 `range` 重叠的部分拆分出来并移到最前面，其余部分保持原有顺序
+    pub fn prioritize(&mut self, range: ChunkRange) {
+        let mut prioritized = Vec::new();
+        let mut others = Vec::with_capacity(self.ranges.len() + 1);
+        for item in self.ranges.drain(..) {
+            if item.end < range.start || item.start > range.end {
+                others.push(item);
+                continue;
+            }
+            if item.start < range.start {
+                others.push(ChunkRange::new(item.start, range.start - 1));
+            }
+            prioritized.push(ChunkRange::new(item.start.max(range.start), item.end.min(range.end)));
+            if item.end > range.end {
+                others.push(ChunkRange::new(range.end + 1, item.end));
+            }
+        }
+        prioritized.extend(others);
+        self.ranges = prioritized;
+    }
+
     /*    pub fn remove(&mut self, index: usize) -> bool {
             let r = 'r: {
                 for (i, range) in self.0.iter().enumerate() {
@@ -130,6 +155,31 @@ pub struct ChunkData {
     pub iter_count: usize,
     pub remaining: RemainingChunks,
     pub last_incomplete_chunks: Vec<ChunkInfo>,
+    // 已写入文件的各段数据的校验和，用于断点续传时检查文件是否被修改
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub chunk_hashes: Vec<ChunkHash>,
+}
+
+/// 已写入文件的一段数据的 CRC32 校验和
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Copy, Clone)]
+pub struct ChunkHash {
+    pub range: ChunkRange,
+    pub crc32: u32,
+}
+
+/// 断点续传时校验已下载数据的方式
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum ChunkHashVerify {
+    /// 不校验
+    #[default]
+    Disabled,
+    /// 校验全部已下载的数据
+    All,
+    /// 均匀抽取指定数量的段进行校验，位置最靠后的段总会被校验，以发现文件被截断
+    Sample(NonZeroUsize),
 }
 
 impl ChunkData {
@@ -144,6 +194,95 @@ impl ChunkData {
         len
     }
 
+    /// 按 `verify` 校验文件中已下载的数据，不匹配的部分放回剩余部分中，返回不匹配的总长度
+    pub fn verify_chunk_hashes(&mut self, file_path: &Path, verify: ChunkHashVerify) -> io::Result<u64> {
+        let indexes: Vec<usize> = match verify {
+            ChunkHashVerify::Disabled => return Ok(0),
+            ChunkHashVerify::All => (0..self.chunk_hashes.len()).collect(),
+            ChunkHashVerify::Sample(count) => {
+                let len = self.chunk_hashes.len();
+                let step = (len / count.get()).max(1);
+                let mut indexes: Vec<usize> = (0..len).step_by(step).take(count.get()).collect();
+                if let Some((last, _)) = self.chunk_hashes.iter().enumerate().max_by_key(|(_, n)| n.range.end) {
+                    if !indexes.contains(&last) {
+                        indexes.push(last);
+                    }
+                }
+                indexes
+            }
+        };
+        if indexes.is_empty() {
+            return Ok(0);
+        }
+
+        let mut file = match fs::File::open(file_path) {
+            Ok(file) => Some(file),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err),
+        };
+        let mut buffer = Vec::new();
+        let mut mismatched = Vec::new();
+        for index in indexes {
+            let chunk_hash = self.chunk_hashes[index];
+            let is_match = match file.as_mut() {
+                None => false,
+                Some(file) => {
+                    buffer.resize(chunk_hash.range.len() as usize, 0);
+                    file.seek(SeekFrom::Start(chunk_hash.range.start))?;
+                    match file.read_exact(&mut buffer) {
+                        Ok(()) => crc32fast::hash(&buffer) == chunk_hash.crc32,
+                        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => false,
+                        Err(err) => return Err(err),
+                    }
+                }
+            };
+            if !is_match {
+                mismatched.push(index);
+            }
+        }
+
+        let mut mismatched_len = 0;
+        for index in mismatched.into_iter().rev() {
+            let chunk_hash = self.chunk_hashes.remove(index);
+            mismatched_len += chunk_hash.range.len();
+            self.remaining.ranges.push(chunk_hash.range);
+        }
+        self.remaining.ranges.sort_by_key(|n| n.start);
+        Ok(mismatched_len)
+    }
+
+    /// 已完成部分的最大结束位置（不包含），没有已完成的部分时返回 0
+    pub fn completed_end(&self, content_length: u64) -> u64 {
+        let mut pending: Vec<ChunkRange> = self
+            .remaining
+            .ranges
+            .iter()
+            .copied()
+            .chain(self.last_incomplete_chunks.iter().map(|n| n.range))
+            .collect();
+        pending.sort_by_key(|n| std::cmp::Reverse(n.end));
+        let mut end = content_length;
+        for range in pending {
+            if range.end + 1 < end {
+                break;
+            }
+            end = end.min(range.start);
+        }
+        end
+    }
+
+    /// 从文件开头起连续完成的长度，`downloading_start` 为正在下载的 chunk 中最靠前的起始位置
+    pub fn contiguous_len(&self, content_length: u64, downloading_start: Option<u64>) -> u64 {
+        self.remaining
+            .ranges
+            .iter()
+            .map(|n| n.start)
+            .chain(self.last_incomplete_chunks.iter().map(|n| n.range.start))
+            .chain(downloading_start)
+            .min()
+            .unwrap_or(content_length)
+    }
+
     pub fn no_chunk_remaining(&self) -> bool {
         self.remaining.ranges.is_empty()
     }
@@ -152,6 +291,36 @@ impl ChunkData {
         if let Some(chunk) = self.last_incomplete_chunks.pop() {
             return Some(chunk);
         }
+        self.take_remaining_chunk()
+    }
+
+    /// 总是优先返回位置最靠前的 chunk，用于顺序下载
+    pub fn next_lowest_chunk_range(&mut self) -> Option<ChunkInfo> {
+        let lowest_incomplete = self
+            .last_incomplete_chunks
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, n)| n.range.start)
+            .map(|(index, n)| (index, n.range.start));
+        match (lowest_incomplete, self.remaining.ranges.first()) {
+            (Some((_, start)), Some(range)) if range.start < start => self.take_remaining_chunk(),
+            (Some((index, _)), _) => Some(self.last_incomplete_chunks.remove(index)),
+            (None, _) => self.take_remaining_chunk(),
+        }
+    }
+
+    /// 优先下载 `range` 范围内（文件中的位置）还未下载的部分，后设置的优先级更高
+    ///
+    /// 上次未完成的 chunk 会放回剩余部分中，以便与优先范围一起重新排序
+    pub fn prioritize(&mut self, range: ChunkRange) {
+        let mut last_incomplete_chunks = std::mem::take(&mut self.last_incomplete_chunks);
+        last_incomplete_chunks.sort_by_key(|n| n.range.start);
+        let ranges = last_incomplete_chunks.into_iter().map(|n| n.range);
+        self.remaining.ranges.splice(0..0, ranges);
+        self.remaining.prioritize(range);
+    }
+
+    fn take_remaining_chunk(&mut self) -> Option<ChunkInfo> {
         let range = self.remaining.take_first();
         if let Some(range) = range {
             self.iter_count += 1;
@@ -170,20 +339,30 @@ impl ChunkData {
 pub struct ChunkIterator {
     pub content_length: u64,
     pub data: Arc<parking_lot::RwLock<ChunkData>>,
+    // 顺序下载：总是优先下载位置最靠前的部分
+    pub sequential: bool,
 }
 
 impl ChunkIterator {
-    pub fn new(content_length: u64, data: ChunkData) -> Self {
+    pub fn new(content_length: u64, mut data: ChunkData, sequential: bool) -> Self {
+        if sequential {
+            data.remaining.ranges.sort_by_key(|n| n.start);
+        }
         Self {
             content_length,
             data: Arc::new(parking_lot::RwLock::new(data)),
+            sequential,
         }
     }
 
 
     pub fn next(&self) -> Option<ChunkInfo> {
         let mut data = self.data.write();
-        data.next_chunk_range()
+        if self.sequential {
+            data.next_lowest_chunk_range()
+        } else {
+            data.next_chunk_range()
+        }
     }
 }
 
@@ -198,7 +377,7 @@ pub struct ChunkInfo {
 // like RangeInclusive
 #[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject), graphql(complex))]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct ChunkRange {
     pub start: u64,
     pub end: u64,
@@ -248,3 +427,105 @@ impl<'a> RangeBounds<u64> for &'a ChunkRange {
         Bound::Included(&self.end)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk_data(remaining: &[(u64, u64)], last_incomplete_chunks: &[(u64, u64)]) -> ChunkData {
+        ChunkData {
+            iter_count: 0,
+            remaining: RemainingChunks {
+                chunk_size: 10,
+                ranges: remaining.iter().map(|(start, end)| ChunkRange::new(*start, *end)).collect(),
+            },
+            last_incomplete_chunks: last_incomplete_chunks
+                .iter()
+                .enumerate()
+                .map(|(index, (start, end))| ChunkInfo { index, range: ChunkRange::new(*start, *end) })
+                .collect(),
+            chunk_hashes: vec![],
+        }
+    }
+
+    #[test]
+    fn contiguous_len() {
+        assert_eq!(chunk_data(&[], &[]).contiguous_len(100, None), 100);
+        assert_eq!(chunk_data(&[], &[]).contiguous_len(100, Some(30)), 30);
+        assert_eq!(chunk_data(&[(60, 99)], &[(40, 49)]).contiguous_len(100, Some(50)), 40);
+        assert_eq!(chunk_data(&[(0, 99)], &[]).contiguous_len(100, None), 0);
+    }
+
+    fn ranges(ranges: &[ChunkRange]) -> Vec<(u64, u64)> {
+        ranges.iter().map(|n| (n.start, n.end)).collect()
+    }
+
+    #[test]
+    fn prioritize_remaining_chunks() {
+        let mut remaining = chunk_data(&[(0, 99), (200, 299)], &[]).remaining;
+        remaining.prioritize(ChunkRange::new(50, 249));
+        assert_eq!(ranges(&remaining.ranges), vec![(50, 99), (200, 249), (0, 49), (250, 299)]);
+
+        // 后设置的优先级更高，不重叠的部分保持原有顺序
+        remaining.prioritize(ChunkRange::new(280, 289));
+        assert_eq!(ranges(&remaining.ranges), vec![(280, 289), (50, 99), (200, 249), (0, 49), (250, 279), (290, 299)]);
+
+        // 与剩余部分不重叠时不变
+        remaining.prioritize(ChunkRange::new(120, 130));
+        assert_eq!(ranges(&remaining.ranges), vec![(280, 289), (50, 99), (200, 249), (0, 49), (250, 279), (290, 299)]);
+
+        let mut remaining = chunk_data(&[(10, 10)], &[]).remaining;
+        remaining.prioritize(ChunkRange::new(10, 10));
+        assert_eq!(ranges(&remaining.ranges), vec![(10, 10)]);
+    }
+
+    #[test]
+    fn prioritize_includes_last_incomplete_chunks() {
+        let mut data = chunk_data(&[(60, 99)], &[(0, 9), (30, 39)]);
+        data.prioritize(ChunkRange::new(35, 70));
+        assert!(data.last_incomplete_chunks.is_empty());
+        assert_eq!(ranges(&data.remaining.ranges), vec![(35, 39), (60, 70), (0, 9), (30, 34), (71, 99)]);
+        assert_eq!(data.remaining_len(), 60);
+    }
+
+    #[test]
+    fn chunk_hash_mismatch_is_requeued() {
+        let content: Vec<u8> = (0..100).collect();
+        let file_path = std::env::temp_dir().join(format!("http-downloader-chunk-hash-{}", std::process::id()));
+        fs::write(&file_path, &content).unwrap();
+        let chunk_hash = |start: u64, end: u64| ChunkHash {
+            range: ChunkRange::new(start, end),
+            crc32: crc32fast::hash(&content[start as usize..=end as usize]),
+        };
+        let mut data = chunk_data(&[(80, 99)], &[]);
+        data.chunk_hashes = vec![chunk_hash(0, 19), chunk_hash(20, 39), chunk_hash(40, 59), chunk_hash(60, 79)];
+        assert_eq!(data.verify_chunk_hashes(&file_path, ChunkHashVerify::All).unwrap(), 0);
+        assert_eq!(data.chunk_hashes.len(), 4);
+
+        // 修改第二段，截断最后一段
+        let mut modified = content.clone();
+        modified[25] ^= 0xff;
+        modified.truncate(70);
+        fs::write(&file_path, &modified).unwrap();
+        assert_eq!(data.verify_chunk_hashes(&file_path, ChunkHashVerify::Disabled).unwrap(), 0);
+        // 抽样总会校验最后一段
+        let mut sampled = data.clone();
+        assert_eq!(sampled.verify_chunk_hashes(&file_path, ChunkHashVerify::Sample(NonZeroUsize::new(1).unwrap())).unwrap(), 20);
+        assert_eq!(ranges(&sampled.remaining.ranges), vec![(60, 79), (80, 99)]);
+
+        assert_eq!(data.verify_chunk_hashes(&file_path, ChunkHashVerify::All).unwrap(), 40);
+        assert_eq!(ranges(&data.remaining.ranges), vec![(20, 39), (60, 79), (80, 99)]);
+        assert_eq!(data.chunk_hashes.iter().map(|n| n.range.start).collect::<Vec<_>>(), vec![0, 40]);
+
+        fs::remove_file(&file_path).unwrap();
+        assert_eq!(data.verify_chunk_hashes(&file_path, ChunkHashVerify::All).unwrap(), 40);
+        assert_eq!(data.remaining_len(), 100);
+    }
+
+    #[test]
+    fn sequential_takes_lowest_chunk() {
+        let iterator = ChunkIterator::new(100, chunk_data(&[(50, 99), (20, 29)], &[(35, 39), (0, 4)]), true);
+        let starts: Vec<u64> = std::iter::from_fn(|| iterator.next()).map(|n| n.range.start).collect();
+        assert_eq!(starts, vec![0, 20, 35, 50, 60, 70, 80, 90]);
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::future::Future;
 use std::num::{NonZeroU8, NonZeroUsize};
+use std::ops::RangeInclusive;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU8, Ordering};
@@ -15,7 +16,9 @@ use tokio::sync;
 use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
 
-use crate::{chunk_item::ChunkItem, ChunkIterator, ChunkRange, DownloadError};
+use crate::{chunk_item::ChunkItem, ChunkControlError, ClientConfig, PrioritizeRangeError, ChunkIterator, ChunkRange, DownloadError, HostConnectionLimiter, ProgressMap, RequestInterceptor, SharedDownloaderWrapper};
+use crate::progress_map::complement_ranges;
+use crate::url_refresher::UrlRefresh;
 use crate::{DownloadedLenChangeNotify, DownloadingEndCause};
 
 #[allow(dead_code)]
@@ -29,19 +32,43 @@ pub struct ChunksInfo {
     #[cfg_attr(feature = "async-graphql", graphql(skip))]
     downloading_chunks: Vec<Arc<ChunkItem>>,
     no_chunk_remaining: bool,
+    progress_map: ProgressMap,
+}
+
+/// 对一个正在下载的 chunk 的控制，chunk 中未下载的部分会放回剩余的 chunk 中
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ChunkControl {
+    /// 取消，未下载的部分放到剩余 chunk 的末尾
+    Cancel,
+    /// 重新连接，未下载的部分放到剩余 chunk 的开头，由空出的连接立即重新下载
+    Restart,
+    /// 将未下载的部分拆分为 N 段放到剩余 chunk 的开头，由空出的连接依次下载
+    Split(NonZeroUsize),
 }
 
 pub struct ChunkManager {
     downloaded_len_sender: Arc<sync::watch::Sender<u64>>,
+    contiguous_len_sender: sync::watch::Sender<u64>,
     pub chunk_iterator: ChunkIterator,
+    progress_map: Arc<parking_lot::RwLock<ProgressMap>>,
     downloading_chunks: Mutex<HashMap<usize, Arc<ChunkItem>>>,
+    endgame_chunks: Mutex<HashMap<usize, Arc<ChunkItem>>>,
+    // 等待结束的被控制的 chunk
+    chunk_controls: parking_lot::Mutex<HashMap<usize, ChunkControl>>,
     download_connection_count_sender: sync::watch::Sender<u8>,
     pub download_connection_count_receiver: sync::watch::Receiver<u8>,
     client: reqwest::Client,
+    client_config: Arc<ClientConfig>,
     cancel_token: CancellationToken,
     pub superfluities_connection_count: AtomicU8,
     pub etag: Option<headers::ETag>,
     pub retry_count: u8,
+    pub endgame: bool,
+    pub range_offset: u64,
+    pub host_connection_limiter: Option<Arc<HostConnectionLimiter>>,
+    request_interceptors: Arc<[Arc<dyn RequestInterceptor>]>,
+    url_refresh: Option<Arc<UrlRefresh>>,
+    chunk_hooks: Option<SharedDownloaderWrapper>,
 }
 
 impl ChunkManager {
@@ -49,26 +76,51 @@ impl ChunkManager {
     pub fn new(
         download_connection_count: NonZeroU8,
         client: reqwest::Client,
+        client_config: Arc<ClientConfig>,
         cancel_token: CancellationToken,
         downloaded_len_sender: Arc<sync::watch::Sender<u64>>,
         chunk_iterator: ChunkIterator,
         etag: Option<headers::ETag>,
         retry_count: u8,
+        endgame: bool,
+        range_offset: u64,
+        progress_map_pieces: NonZeroUsize,
+        host_connection_limiter: Option<Arc<HostConnectionLimiter>>,
+        request_interceptors: Arc<[Arc<dyn RequestInterceptor>]>,
+        url_refresh: Option<Arc<UrlRefresh>>,
+        chunk_hooks: Option<SharedDownloaderWrapper>,
     ) -> Self {
         let (download_connection_count_sender, download_connection_count_receiver) =
             sync::watch::channel(download_connection_count.get());
+        let (contiguous_len_sender, _) = sync::watch::channel(0);
+        let progress_map = ProgressMap::from_chunk_data(
+            chunk_iterator.content_length,
+            progress_map_pieces,
+            &chunk_iterator.data.read(),
+        );
 
         Self {
             downloaded_len_sender,
+            contiguous_len_sender,
             chunk_iterator,
+            progress_map: Arc::new(parking_lot::RwLock::new(progress_map)),
             downloading_chunks: Mutex::new(HashMap::new()),
+            endgame_chunks: Mutex::new(HashMap::new()),
+            chunk_controls: Default::default(),
             download_connection_count_sender,
             download_connection_count_receiver,
             client,
+            client_config,
             cancel_token,
             superfluities_connection_count: AtomicU8::new(0),
             etag,
             retry_count,
+            endgame,
+            range_offset,
+            host_connection_limiter,
+            request_interceptors,
+            url_refresh,
+            chunk_hooks,
         }
     }
 
@@ -84,6 +136,60 @@ impl ChunkManager {
         guard.remaining.chunk_size = chunk_size.get();
     }
 
+    /// 优先下载文件中 `range` 范围内还未下载的部分，正在下载的 chunk 不受影响
+    pub fn prioritize_range(&self, range: RangeInclusive<u64>) -> Result<(), PrioritizeRangeError> {
+        let content_length = self.chunk_iterator.content_length;
+        let (start, end) = (*range.start(), *range.end());
+        if start > end || end >= content_length {
+            return Err(PrioritizeRangeError::InvalidRange { start, end, content_length });
+        }
+        let mut guard = self.chunk_iterator.data.write();
+        guard.prioritize(ChunkRange::new(start, end));
+        Ok(())
+    }
+
+    /// 控制一个正在下载的 chunk，chunk 结束后未下载的部分按 `control` 放回剩余的 chunk 中，空出的连接会继续下载
+    pub async fn control_chunk(&self, index: usize, control: ChunkControl) -> Result<(), ChunkControlError> {
+        // 持有竞速的锁，避免在此期间为这个 chunk 创建竞速连接
+        let endgame_chunks = self.endgame_chunks.lock().await;
+        let chunk_item = self
+            .downloading_chunks
+            .lock()
+            .await
+            .get(&index)
+            .cloned()
+            .ok_or(ChunkControlError::ChunkNotFound(index))?;
+        if chunk_item.race().is_some() || endgame_chunks.contains_key(&index) {
+            return Err(ChunkControlError::ChunkRacing(index));
+        }
+        self.chunk_controls.lock().insert(index, control);
+        chunk_item.cancel();
+        Ok(())
+    }
+
+    /// 将被控制而结束的 chunk 中未下载的部分放回剩余的 chunk 中，没有未下载的部分时返回 false
+    fn return_chunk_tail(&self, chunk_item: &ChunkItem, control: ChunkControl) -> bool {
+        let range = chunk_item.chunk_info.range;
+        let downloaded_len = chunk_item.downloaded_len.load(Ordering::SeqCst);
+        if downloaded_len >= range.len() {
+            return false;
+        }
+        let tail = ChunkRange::new(range.start + downloaded_len, range.end);
+        let mut data = self.chunk_iterator.data.write();
+        match control {
+            ChunkControl::Cancel => data.remaining.ranges.push(tail),
+            ChunkControl::Restart => data.remaining.ranges.insert(0, tail),
+            ChunkControl::Split(count) => {
+                let part_len = tail.len().div_ceil((count.get() as u64).min(tail.len()));
+                let parts = (tail.start..=tail.end)
+                    .step_by(part_len as usize)
+                    .map(|start| ChunkRange::new(start, (start + part_len - 1).min(tail.end)));
+                data.remaining.ranges.splice(0..0, parts);
+            }
+        }
+        true
+    }
+
     pub fn downloaded_len(&self) -> u64 {
         *self.downloaded_len_sender.borrow()
     }
@@ -92,7 +198,48 @@ impl ChunkManager {
         *self.download_connection_count_sender.borrow()
     }
 
+    /// 从文件开头起连续写入磁盘的长度
+    pub fn contiguous_len(&self) -> u64 {
+        *self.contiguous_len_sender.borrow()
+    }
+
+    pub fn contiguous_len_receiver(&self) -> sync::watch::Receiver<u64> {
+        self.contiguous_len_sender.subscribe()
+    }
+
+    /// 各块的接收进度
+    pub fn progress_map(&self) -> ProgressMap {
+        self.progress_map.read().clone()
+    }
+
+    async fn update_contiguous_len(&self) {
+        let downloading_start = self
+            .downloading_chunks
+            .lock()
+            .await
+            .values()
+            .map(|n| n.chunk_info.range.start)
+            .min();
+        let contiguous_len = self
+            .chunk_iterator
+            .data
+            .read()
+            .contiguous_len(self.chunk_iterator.content_length, downloading_start);
+        self.contiguous_len_sender.send_if_modified(|n| {
+            if *n == contiguous_len {
+                false
+            } else {
+                *n = contiguous_len;
+                true
+            }
+        });
+    }
+
     pub fn clone_request(request: &Request) -> Box<Request> {
+        // 请求体为 Bytes 时可以直接复制
+        if let Some(req) = request.try_clone() {
+            return Box::new(req);
+        }
         let mut req = Request::new(request.method().clone(), request.url().clone());
         *req.headers_mut() = request.headers().clone();
         *req.version_mut() = request.version();
@@ -114,6 +261,10 @@ impl ChunkManager {
                 chunk_index: usize,
                 future: BoxFuture<'a, Result<DownloadingEndCause, DownloadError>>,
             },
+            EndgameChunkDownloadEnd {
+                chunk_index: usize,
+                future: BoxFuture<'a, Result<DownloadingEndCause, DownloadError>>,
+            },
         }
 
         #[derive(Debug)]
@@ -126,6 +277,10 @@ impl ChunkManager {
                 chunk_index: usize,
                 result: Result<DownloadingEndCause, DownloadError>,
             },
+            EndgameChunkDownloadEnd {
+                chunk_index: usize,
+                result: Result<DownloadingEndCause, DownloadError>,
+            },
         }
 
         impl Future for RunFuture<'_> {
@@ -148,12 +303,21 @@ impl ChunkManager {
                             result,
                         })
                     }
+                    RunFuture::EndgameChunkDownloadEnd {
+                        future,
+                        chunk_index
+                    } => {
+                        future.poll_unpin(cx).map(|result| RunFutureResult::EndgameChunkDownloadEnd {
+                            chunk_index: *chunk_index,
+                            result,
+                        })
+                    }
                 }
             }
         }
 
         let mut futures_unordered = FuturesUnordered::new();
-
+        self.update_contiguous_len().await;
 
         let file = Arc::new(Mutex::new(file));
         let download_next_chunk = || async {
@@ -175,6 +339,14 @@ impl ChunkManager {
                 }
             }
         };
+        let download_endgame_chunk = || async {
+            self.download_endgame_chunk(downloaded_len_receiver.clone(), Self::clone_request(&request))
+                .await
+                .map(|(chunk_index, future)| RunFuture::EndgameChunkDownloadEnd {
+                    chunk_index,
+                    future: future.boxed(),
+                })
+        };
         match download_next_chunk().await {
             None => {
                 #[cfg(feature = "tracing")]
@@ -227,7 +399,7 @@ impl ChunkManager {
                         continue;
                     }
 
-                    let current_count = self.get_chunks().await.len();
+                    let current_count = self.get_chunks().await.len() + self.endgame_chunks.lock().await.len();
                     let diff = download_connection_count as i16 - current_count as i16;
                     if diff >= 0 {
                         self.superfluities_connection_count
@@ -254,9 +426,20 @@ impl ChunkManager {
                 }
                 RunFutureResult::ChunkDownloadEnd {
                     chunk_index,
-                    result: Ok(DownloadingEndCause::DownloadFinished)
-                } => {
-                    let (downloading_chunk_count, _) = self.remove_chunk(chunk_index).await;
+                    result: Ok(end_cause)
+                } if end_cause == DownloadingEndCause::DownloadFinished
+                    || (!self.cancel_token.is_cancelled() && self.chunk_controls.lock().contains_key(&chunk_index)) => {
+                    let (downloading_chunk_count, chunk_item) = self.remove_chunk(chunk_index).await;
+                    // 被控制而取消的 chunk，未下载的部分放回后由空出的连接继续下载
+                    if let Some(control) = self.chunk_controls.lock().remove(&chunk_index) {
+                        #[cfg(feature = "tracing")]
+                        tracing::trace!("Chunk {} end by control: {:?}", chunk_index, control);
+                        if end_cause == DownloadingEndCause::Cancelled
+                            && chunk_item.is_some_and(|n| self.return_chunk_tail(&n, control)) {
+                            is_iter_finished = false;
+                        }
+                    }
+                    self.update_contiguous_len().await;
 
                     #[cfg(feature = "breakpoint-resume")]
                     save_data().await;
@@ -268,6 +451,14 @@ impl ChunkManager {
                             );
                             break;
                         }
+                        if self.superfluities_connection_count.load(Ordering::SeqCst) == 0 {
+                            if let Some(future) = download_endgame_chunk().await {
+                                futures_unordered.push(future)
+                            }
+                        } else {
+                            self.superfluities_connection_count
+                                .fetch_sub(1, Ordering::SeqCst);
+                        }
                     } else if self.superfluities_connection_count.load(Ordering::SeqCst) == 0 {
                         match download_next_chunk().await {
                             None => {
@@ -279,6 +470,9 @@ impl ChunkManager {
                                     );
                                     break;
                                 }
+                                if let Some(future) = download_endgame_chunk().await {
+                                    futures_unordered.push(future)
+                                }
                             }
                             Some(future) => futures_unordered.push(future)
                         }
@@ -287,6 +481,30 @@ impl ChunkManager {
                             .fetch_sub(1, Ordering::SeqCst);
                     }
                 }
+                RunFutureResult::EndgameChunkDownloadEnd {
+                    chunk_index,
+                    result
+                } => {
+                    // 竞速连接的结果不影响整体下载，原 chunk 会在竞速结束后给出自己的结果
+                    #[cfg(feature = "tracing")]
+                    tracing::trace!("Endgame chunk {} end: {:?}", chunk_index, result);
+                    self.endgame_chunks.lock().await.remove(&chunk_index);
+                    if let Some(chunk_item) = self.downloading_chunks.lock().await.get(&chunk_index) {
+                        chunk_item.end_unsettled_race();
+                    }
+                    #[cfg(not(feature = "tracing"))]
+                    let _ = result;
+                    if !self.cancel_token.is_cancelled() {
+                        if self.superfluities_connection_count.load(Ordering::SeqCst) == 0 {
+                            if let Some(future) = download_endgame_chunk().await {
+                                futures_unordered.push(future)
+                            }
+                        } else {
+                            self.superfluities_connection_count
+                                .fetch_sub(1, Ordering::SeqCst);
+                        }
+                    }
+                }
                 RunFutureResult::ChunkDownloadEnd {
                     result: Err(err),
                     ..
@@ -301,8 +519,9 @@ impl ChunkManager {
                         self.cancel_token.cancel();
                     }
                 }
+                // 下载完成的已在上面处理，这里只剩被取消的
                 RunFutureResult::ChunkDownloadEnd {
-                    result: Ok(DownloadingEndCause::Cancelled),
+                    result: Ok(_),
                     ..
                 } => {
                     if matches!(result,Ok(DownloadingEndCause::DownloadFinished)) {
@@ -340,39 +559,26 @@ impl ChunkManager {
 
     pub async fn get_chunks_info(&self) -> ChunksInfo {
         let downloading_chunks = self.get_chunks().await;
-        let mut finished_chunks = vec![];
-
-        let no_chunk_remaining = self.chunk_iterator.data.read().no_chunk_remaining();
-        if !downloading_chunks.is_empty() {
-            let first_start = downloading_chunks[0].chunk_info.range.start;
-            if first_start != 0 {
-                finished_chunks.push(ChunkRange::new(0, first_start - 1));
-            }
-            for (index, _) in downloading_chunks.iter().enumerate() {
-                if index == downloading_chunks.len() - 1 {
-                    break;
-                }
-
-                let start = downloading_chunks[index].chunk_info.range.end;
-                let end = downloading_chunks[index + 1].chunk_info.range.start;
-                if (end - start) != 1 {
-                    finished_chunks.push(ChunkRange::new(start + 1, end - 1));
-                }
-            }
-            if no_chunk_remaining {
-                let last = downloading_chunks.last().unwrap();
-                if last.chunk_info.range.end != self.chunk_iterator.content_length - 1 {
-                    finished_chunks.push(ChunkRange::new(
-                        last.chunk_info.range.end + 1,
-                        self.chunk_iterator.content_length - 1,
-                    ))
-                }
-            }
-        }
+        // 剩余的、上次未完成的、正在下载的 chunk 以外的部分都已写入文件
+        let (finished_chunks, no_chunk_remaining) = {
+            let data = self.chunk_iterator.data.read();
+            let pending = data
+                .remaining
+                .ranges
+                .iter()
+                .copied()
+                .chain(data.last_incomplete_chunks.iter().map(|n| n.range))
+                .chain(downloading_chunks.iter().map(|n| n.chunk_info.range));
+            (
+                complement_ranges(self.chunk_iterator.content_length, pending),
+                data.no_chunk_remaining(),
+            )
+        };
         ChunksInfo {
             downloading_chunks,
             finished_chunks,
             no_chunk_remaining,
+            progress_map: self.progress_map(),
         }
     }
 
@@ -382,6 +588,32 @@ impl ChunkManager {
         (downloading_chunks.len(), removed)
     }
 
+    /// 尾段竞速：没有剩余的 chunk 时，为未完成部分最大的 chunk 创建一个竞速连接
+    async fn download_endgame_chunk(
+        &self,
+        downloaded_len_receiver: Option<Arc<dyn DownloadedLenChangeNotify>>,
+        request: Box<Request>,
+    ) -> Option<(usize, impl Future<Output=Result<DownloadingEndCause, DownloadError>>)> {
+        if !self.endgame || self.cancel_token.is_cancelled() {
+            return None;
+        }
+        let mut endgame_chunks = self.endgame_chunks.lock().await;
+        let racer = self
+            .get_chunks()
+            .await
+            .into_iter()
+            .filter(|n| n.can_race() && !endgame_chunks.contains_key(&n.chunk_info.index))
+            .filter(|n| !self.chunk_controls.lock().contains_key(&n.chunk_info.index))
+            .max_by_key(|n| n.chunk_info.range.len() - n.downloaded_len.load(Ordering::SeqCst))
+            .and_then(|n| n.start_race(self.cancel_token.child_token()))?;
+        let racer = Arc::new(racer);
+        endgame_chunks.insert(racer.chunk_info.index, racer.clone());
+        #[cfg(feature = "tracing")]
+        tracing::trace!("Endgame chunk {} start: {:?}", racer.chunk_info.index, racer.chunk_info.range);
+        // 竞速连接只通知扩展（比如限速），不上报下载长度，胜出后由原 chunk 补上
+        Some((racer.chunk_info.index, racer.download_chunk(request, self.retry_count, Some(LenChangedNotify::new(None, downloaded_len_receiver)))))
+    }
+
     #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
     async fn download_next_chunk(
         &self,
@@ -394,14 +626,22 @@ impl ChunkManager {
                 chunk_info,
                 self.cancel_token.child_token(),
                 self.client.clone(),
+                self.client_config.clone(),
                 file,
                 self.etag.clone(),
+                self.chunk_iterator.data.clone(),
+                self.progress_map.clone(),
+                self.host_connection_limiter.clone(),
+                self.request_interceptors.clone(),
+                self.url_refresh.clone(),
+                self.chunk_hooks.clone(),
+                self.range_offset,
             ));
             self.insert_chunk(chunk_item.clone()).await;
-            Some((chunk_item.chunk_info.index, chunk_item.download_chunk(request, self.retry_count, Some(LenChangedNotify {
-                notify: downloaded_len_receiver,
-                downloaded_len_sender: self.downloaded_len_sender.clone(),
-            }))))
+            Some((chunk_item.chunk_info.index, chunk_item.download_chunk(request, self.retry_count, Some(LenChangedNotify::new(
+                Some(self.downloaded_len_sender.clone()),
+                downloaded_len_receiver,
+            )))))
         } else {
             None
         }
@@ -409,14 +649,33 @@ impl ChunkManager {
 }
 
 pub struct LenChangedNotify {
-    downloaded_len_sender: Arc<sync::watch::Sender<u64>>,
+    // 竞速连接为 None，下载长度由原 chunk 在竞速结束后上报
+    downloaded_len_sender: Option<Arc<sync::watch::Sender<u64>>>,
     notify: Option<Arc<dyn DownloadedLenChangeNotify>>,
 }
 
+impl LenChangedNotify {
+    pub(crate) fn new(
+        downloaded_len_sender: Option<Arc<sync::watch::Sender<u64>>>,
+        notify: Option<Arc<dyn DownloadedLenChangeNotify>>,
+    ) -> Self {
+        Self {
+            downloaded_len_sender,
+            notify,
+        }
+    }
+
+    /// 只更新下载长度，不通知扩展
+    pub(crate) fn add_downloaded_len(&self, len: usize) {
+        if let Some(downloaded_len_sender) = self.downloaded_len_sender.as_ref() {
+            downloaded_len_sender.send_modify(|n| *n += len as u64);
+        }
+    }
+}
+
 impl DownloadedLenChangeNotify for LenChangedNotify {
     fn receive_len(&self, len: usize) -> OptionFuture<BoxFuture<()>> {
-        self.downloaded_len_sender
-            .send_modify(|n| *n += len as u64);
+        self.add_downloaded_len(len);
         if let Some(notify) = self.notify.as_ref() {
             notify.receive_len(len)
         } else {
@@ -466,4 +725,130 @@ impl ChunksInfo {
             .map(Into::into)
             .collect()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ChunkData, ChunkInfo, RemainingChunks};
+
+    use super::*;
+
+    fn chunk_manager(content_length: u64, remaining: Vec<ChunkRange>) -> ChunkManager {
+        let chunk_data = ChunkData {
+            iter_count: 0,
+            remaining: RemainingChunks { chunk_size: 10, ranges: remaining },
+            last_incomplete_chunks: vec![],
+            chunk_hashes: vec![],
+        };
+        ChunkManager::new(
+            NonZeroU8::new(2).unwrap(),
+            reqwest::Client::new(),
+            Default::default(),
+            CancellationToken::new(),
+            Arc::new(sync::watch::channel(0).0),
+            ChunkIterator::new(content_length, chunk_data, false),
+            None,
+            0,
+            false,
+            0,
+            NonZeroUsize::new(4).unwrap(),
+            None,
+            Vec::new().into(),
+            None,
+            None,
+        )
+    }
+
+    async fn downloading_chunk(chunk_manager: &ChunkManager, range: ChunkRange, downloaded_len: u64, cancel_token: CancellationToken) -> Arc<ChunkItem> {
+        let file_path = std::env::temp_dir().join(format!("http-downloader-control-{}", std::process::id()));
+        let file = tokio::fs::File::create(&file_path).await.unwrap();
+        let chunk_item = Arc::new(ChunkItem::new(
+            ChunkInfo { index: 1, range },
+            cancel_token,
+            reqwest::Client::new(),
+            Default::default(),
+            Arc::new(Mutex::new(file)),
+            None,
+            chunk_manager.chunk_iterator.data.clone(),
+            chunk_manager.progress_map.clone(),
+            None,
+            Vec::new().into(),
+            None,
+            None,
+            0,
+        ));
+        chunk_item.downloaded_len.store(downloaded_len, Ordering::SeqCst);
+        chunk_manager.downloading_chunks.lock().await.insert(1, chunk_item.clone());
+        tokio::fs::remove_file(&file_path).await.unwrap();
+        chunk_item
+    }
+
+    fn remaining(chunk_manager: &ChunkManager) -> Vec<(u64, u64)> {
+        chunk_manager.chunk_iterator.data.read().remaining.ranges.iter().map(|n| (n.start, n.end)).collect()
+    }
+
+    #[test]
+    fn prioritize_range_validation() {
+        let chunk_manager = chunk_manager(100, vec![ChunkRange::new(0, 99)]);
+        let (start, end) = (5, 1);
+        assert!(matches!(
+            chunk_manager.prioritize_range(start..=end),
+            Err(PrioritizeRangeError::InvalidRange { start: 5, end: 1, content_length: 100 })
+        ));
+        assert!(matches!(chunk_manager.prioritize_range(90..=100), Err(PrioritizeRangeError::InvalidRange { .. })));
+        assert_eq!(remaining(&chunk_manager), vec![(0, 99)]);
+
+        chunk_manager.prioritize_range(90..=99).unwrap();
+        assert_eq!(remaining(&chunk_manager), vec![(90, 99), (0, 89)]);
+    }
+
+    #[tokio::test]
+    async fn control_chunk_cancels_and_records_control() {
+        let chunk_manager = chunk_manager(100, vec![ChunkRange::new(40, 99)]);
+        let cancel_token = CancellationToken::new();
+        downloading_chunk(&chunk_manager, ChunkRange::new(0, 39), 10, cancel_token.clone()).await;
+
+        assert!(matches!(
+            chunk_manager.control_chunk(2, ChunkControl::Cancel).await,
+            Err(ChunkControlError::ChunkNotFound(2))
+        ));
+        assert!(!cancel_token.is_cancelled());
+
+        chunk_manager.control_chunk(1, ChunkControl::Restart).await.unwrap();
+        assert!(cancel_token.is_cancelled());
+        assert!(matches!(chunk_manager.chunk_controls.lock().get(&1), Some(ChunkControl::Restart)));
+
+        // 正在竞速的 chunk 不能被控制
+        let chunk_manager = super::tests::chunk_manager(100, vec![ChunkRange::new(40, 99)]);
+        let chunk_item = downloading_chunk(&chunk_manager, ChunkRange::new(0, 39), 10, CancellationToken::new()).await;
+        let _racer = chunk_item.start_race(CancellationToken::new()).unwrap();
+        assert!(matches!(
+            chunk_manager.control_chunk(1, ChunkControl::Cancel).await,
+            Err(ChunkControlError::ChunkRacing(1))
+        ));
+        assert!(chunk_manager.chunk_controls.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn return_chunk_tail() {
+        let cases = [
+            (ChunkControl::Cancel, vec![(40, 99), (10, 39)]),
+            (ChunkControl::Restart, vec![(10, 39), (40, 99)]),
+            (ChunkControl::Split(NonZeroUsize::new(3).unwrap()), vec![(10, 19), (20, 29), (30, 39), (40, 99)]),
+            // 剩余长度不足拆分的段数时，每段 1 字节
+            (ChunkControl::Split(NonZeroUsize::new(50).unwrap()), (10..=39).map(|n| (n, n)).chain([(40, 99)]).collect()),
+        ];
+        for (control, expected) in cases {
+            let chunk_manager = chunk_manager(100, vec![ChunkRange::new(40, 99)]);
+            let chunk_item = downloading_chunk(&chunk_manager, ChunkRange::new(0, 39), 10, CancellationToken::new()).await;
+            assert!(chunk_manager.return_chunk_tail(&chunk_item, control));
+            assert_eq!(remaining(&chunk_manager), expected);
+        }
+
+        // 已经下载完成的 chunk 没有需要放回的部分
+        let chunk_manager = chunk_manager(100, vec![ChunkRange::new(40, 99)]);
+        let chunk_item = downloading_chunk(&chunk_manager, ChunkRange::new(0, 39), 40, CancellationToken::new()).await;
+        assert!(!chunk_manager.return_chunk_tail(&chunk_item, ChunkControl::Cancel));
+        assert_eq!(remaining(&chunk_manager), vec![(40, 99)]);
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ClientConfigError {
+    #[error("read file {:?} failed，{:?}", .0, .1)]
+    ReadFileFailed(PathBuf, io::Error),
+    #[error("invalid proxy {}，{:?}", .0, .1)]
+    InvalidProxy(String, reqwest::Error),
+    #[error("invalid certificate {:?}，{:?}", .0, .1)]
+    InvalidCertificate(PathBuf, reqwest::Error),
+    #[error("invalid identity {:?}，{:?}", .0, .1)]
+    InvalidIdentity(PathBuf, reqwest::Error),
+    #[error("build http client failed，{:?}", .0)]
+    BuildClientFailed(reqwest::Error),
+    // FTP 不支持代理，配置了作用于这个主机的代理时不会绕过代理直接连接
+    #[error("proxy is not supported for ftp host {}", .0)]
+    FtpProxyNotSupported(String),
+}
+
+/// 代理作用于哪些请求
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum ProxyScope {
+    #[default]
+    All,
+    Http,
+    Https,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct ProxyConfig {
+    // 代理地址，支持 http://、https://，开启 `socks` feature 后支持 socks5://、socks5h://
+    pub url: String,
+    pub scope: ProxyScope,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // 不使用这个代理的主机，逗号分隔，格式同 NO_PROXY 环境变量
+    pub no_proxy: Option<String>,
+}
+
+impl ProxyConfig {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            ..Default::default()
+        }
+    }
+
+    fn to_proxy(&self) -> Result<reqwest::Proxy, ClientConfigError> {
+        let proxy = match self.scope {
+            ProxyScope::All => reqwest::Proxy::all(&self.url),
+            ProxyScope::Http => reqwest::Proxy::http(&self.url),
+            ProxyScope::Https => reqwest::Proxy::https(&self.url),
+        };
+        let mut proxy = proxy.map_err(|err| ClientConfigError::InvalidProxy(self.url.clone(), err))?;
+        if let Some(username) = self.username.as_ref() {
+            proxy = proxy.basic_auth(username, self.password.as_deref().unwrap_or_default());
+        }
+        if let Some(no_proxy) = self.no_proxy.as_ref() {
+            proxy = proxy.no_proxy(reqwest::NoProxy::from_string(no_proxy));
+        }
+        Ok(proxy)
+    }
+}
+
+/// 客户端证书
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ClientIdentity {
+    /// PKCS#12 格式的证书与私钥
+    Pkcs12 { path: PathBuf, password: String },
+    /// PEM 格式的证书链与 PKCS#8 私钥
+    Pkcs8Pem { certificate_path: PathBuf, key_path: PathBuf },
+}
+
+impl ClientIdentity {
+    fn to_identity(&self) -> Result<reqwest::Identity, ClientConfigError> {
+        match self {
+            ClientIdentity::Pkcs12 { path, password } => reqwest::Identity::from_pkcs12_der(&read_file(path)?, password)
+                .map_err(|err| ClientConfigError::InvalidIdentity(path.clone(), err)),
+            ClientIdentity::Pkcs8Pem { certificate_path, key_path } => {
+                reqwest::Identity::from_pkcs8_pem(&read_file(certificate_path)?, &read_file(key_path)?)
+                    .map_err(|err| ClientConfigError::InvalidIdentity(certificate_path.clone(), err))
+            }
+        }
+    }
+}
+
+/// 可序列化的 HTTP 客户端配置，没有通过 `HttpDownloaderBuilder::client` 指定客户端时，开始下载前用它创建客户端
+///
+/// 代理密码与客户端证书密码会以明文序列化，持久化或对外展示前可以用 `without_secrets` 去掉
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct ClientConfig {
+    pub proxies: Vec<ProxyConfig>,
+    // 不使用系统代理（环境变量），`proxies` 不受影响
+    pub no_system_proxy: bool,
+    // 额外信任的根证书，PEM（可以包含多个证书）或 DER 格式
+    pub root_certificates: Vec<PathBuf>,
+    pub identity: Option<ClientIdentity>,
+    // 不校验服务器证书，有安全风险
+    pub danger_accept_invalid_certs: bool,
+    // 不校验服务器证书中的主机名，有安全风险
+    pub danger_accept_invalid_hostnames: bool,
+    // 发起连接使用的本地地址，用于选择网卡
+    pub local_address: Option<IpAddr>,
+    // 域名解析覆盖，端口会被忽略，使用 URL 中的端口
+    pub resolve: HashMap<String, Vec<SocketAddr>>,
+}
+
+impl ClientConfig {
+    /// 去掉代理密码与客户端证书密码后的配置
+    pub fn without_secrets(&self) -> Self {
+        let mut client_config = self.clone();
+        for proxy in client_config.proxies.iter_mut() {
+            proxy.password = None;
+        }
+        if let Some(ClientIdentity::Pkcs12 { password, .. }) = client_config.identity.as_mut() {
+            password.clear();
+        }
+        client_config
+    }
+
+    /// 将配置应用到 `builder` 上
+    pub fn apply(&self, mut builder: reqwest::ClientBuilder) -> Result<reqwest::ClientBuilder, ClientConfigError> {
+        if self.no_system_proxy {
+            builder = builder.no_proxy();
+        }
+        for proxy in &self.proxies {
+            builder = builder.proxy(proxy.to_proxy()?);
+        }
+        for path in &self.root_certificates {
+            for certificate in read_certificates(path)? {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        if let Some(identity) = self.identity.as_ref() {
+            builder = builder.identity(identity.to_identity()?);
+        }
+        if self.danger_accept_invalid_certs {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        if self.danger_accept_invalid_hostnames {
+            builder = builder.danger_accept_invalid_hostnames(true);
+        }
+        if let Some(local_address) = self.local_address {
+            builder = builder.local_address(local_address);
+        }
+        for (domain, addrs) in &self.resolve {
+            builder = builder.resolve_to_addrs(domain, addrs);
+        }
+        Ok(builder)
+    }
+
+    pub fn build_client(&self) -> Result<reqwest::Client, ClientConfigError> {
+        self.apply(reqwest::Client::builder())?
+            .build()
+            .map_err(ClientConfigError::BuildClientFailed)
+    }
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, ClientConfigError> {
+    std::fs::read(path).map_err(|err| ClientConfigError::ReadFileFailed(path.to_path_buf(), err))
+}
+
+fn read_certificates(path: &Path) -> Result<Vec<reqwest::Certificate>, ClientConfigError> {
+    let bytes = read_file(path)?;
+    let certificates = if bytes.windows(10).any(|n| n == b"-----BEGIN") {
+        reqwest::Certificate::from_pem_bundle(&bytes)
+    } else {
+        reqwest::Certificate::from_der(&bytes).map(|n| vec![n])
+    };
+    certificates.map_err(|err| ClientConfigError::InvalidCertificate(path.to_path_buf(), err))
+}
@@ -0,0 +1,142 @@
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder, ZstdDecoder};
+use bytes::Bytes;
+use futures_util::Stream;
+use reqwest::header::{CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::Response;
+use tokio::io::{AsyncBufRead, AsyncRead};
+use tokio_util::io::StreamReader;
+use url::Url;
+
+/// 解压方式，只在单连接下载时生效，需要解压时不会使用多连接下载
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum DecompressMode {
+    #[default]
+    Disabled,
+    /// 请求 gzip、br、zstd 编码，按响应的 Content-Encoding 解压
+    ContentEncoding,
+    /// 同时解压 .gz 文件（地址以 .gz 结尾或 Content-Type 为 gzip）
+    ContentEncodingAndGzipFile,
+}
+
+impl DecompressMode {
+    pub(crate) fn accept_encoding(&self) -> &'static str {
+        match self {
+            DecompressMode::Disabled => "identity",
+            _ => "gzip, br, zstd",
+        }
+    }
+
+    /// 下载地址是否指向需要解压的 .gz 文件，保存的文件名与是否解压都按它决定，不受重定向影响
+    pub(crate) fn is_gzip_file(&self, url: &Url) -> bool {
+        *self == DecompressMode::ContentEncodingAndGzipFile && url.path().to_ascii_lowercase().ends_with(".gz")
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Brotli,
+    Zstd,
+}
+
+impl Compression {
+    /// 根据响应判断是否需要解压，编码不支持时返回 None，`url` 为下载地址
+    pub(crate) fn from_response(response: &Response, decompress_mode: DecompressMode, url: &Url) -> Option<Self> {
+        if decompress_mode == DecompressMode::Disabled {
+            return None;
+        }
+        let headers = response.headers();
+        match headers.get(CONTENT_ENCODING).and_then(|n| n.to_str().ok()) {
+            Some(content_encoding) => match content_encoding.trim().to_ascii_lowercase().as_str() {
+                "gzip" | "x-gzip" => Some(Compression::Gzip),
+                "br" => Some(Compression::Brotli),
+                "zstd" => Some(Compression::Zstd),
+                _ => None,
+            },
+            None if decompress_mode == DecompressMode::ContentEncodingAndGzipFile => {
+                let is_gzip_type = headers
+                    .get(CONTENT_TYPE)
+                    .and_then(|n| n.to_str().ok())
+                    .is_some_and(|n| n.starts_with("application/gzip") || n.starts_with("application/x-gzip"));
+                (is_gzip_type || decompress_mode.is_gzip_file(url)).then_some(Compression::Gzip)
+            }
+            None => None,
+        }
+    }
+
+    fn decoder<'a>(&self, reader: impl AsyncBufRead + Send + Unpin + 'a) -> Box<dyn AsyncRead + Send + Unpin + 'a> {
+        match self {
+            Compression::Gzip => {
+                let mut decoder = GzipDecoder::new(reader);
+                // .gz 文件可能由多个 gzip 成员拼接而成
+                decoder.multiple_members(true);
+                Box::new(decoder)
+            }
+            Compression::Brotli => Box::new(BrotliDecoder::new(reader)),
+            Compression::Zstd => Box::new(ZstdDecoder::new(reader)),
+        }
+    }
+}
+
+/// 响应的编码与已解压的长度
+#[derive(Debug)]
+pub struct Decompression {
+    pub compression: Compression,
+    decompressed_len: AtomicU64,
+}
+
+impl Decompression {
+    pub(crate) fn new(compression: Compression) -> Self {
+        Self {
+            compression,
+            decompressed_len: AtomicU64::new(0),
+        }
+    }
+
+    /// 已解压的长度，`downloaded_len` 为已接收的压缩数据长度
+    pub fn decompressed_len(&self) -> u64 {
+        self.decompressed_len.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn add_decompressed_len(&self, len: usize) {
+        self.decompressed_len.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    /// 解压 `stream` 中的压缩数据
+    pub(crate) fn reader<'a>(&self, stream: impl Stream<Item=io::Result<Bytes>> + Send + Unpin + 'a) -> impl AsyncRead + Send + Unpin + 'a {
+        self.compression.decoder(StreamReader::new(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(content_encoding: Option<&str>) -> Response {
+        let mut response = http::Response::builder();
+        if let Some(content_encoding) = content_encoding {
+            response = response.header(CONTENT_ENCODING, content_encoding);
+        }
+        response.body("").unwrap().into()
+    }
+
+    #[test]
+    fn gzip_file_follows_download_url() {
+        let mode = DecompressMode::ContentEncodingAndGzipFile;
+        let gz: Url = "https://example.com/data.json.gz".parse().unwrap();
+        let plain: Url = "https://example.com/download?id=1".parse().unwrap();
+        assert!(mode.is_gzip_file(&gz));
+        assert!(!mode.is_gzip_file(&plain));
+        assert!(!DecompressMode::ContentEncoding.is_gzip_file(&gz));
+
+        // 与响应的地址（重定向后的地址）无关，保存的文件名去掉了 .gz 时一定会解压
+        assert_eq!(Compression::from_response(&response(None), mode, &gz), Some(Compression::Gzip));
+        assert_eq!(Compression::from_response(&response(None), mode, &plain), None);
+        assert_eq!(Compression::from_response(&response(None), DecompressMode::ContentEncoding, &gz), None);
+        assert_eq!(Compression::from_response(&response(Some("br")), mode, &plain), Some(Compression::Brotli));
+    }
+}
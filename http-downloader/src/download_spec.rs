@@ -0,0 +1,134 @@
+use std::num::{NonZeroU8, NonZeroUsize};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use url::Url;
+
+use crate::{ChunkHashVerify, ChunkRange, ClientConfig, HttpRedirectionHandle};
+
+/// 当前 `DownloadSpec` 的结构版本，结构变化时增加
+pub const DOWNLOAD_SPEC_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Error, Debug)]
+pub enum DownloadSpecError {
+    #[error("unsupported schema version {0}，supported version: {}", DOWNLOAD_SPEC_SCHEMA_VERSION)]
+    UnsupportedSchemaVersion(u32),
+    #[error("invalid method，{0}")]
+    InvalidMethod(String),
+    #[error("invalid header，{0}")]
+    InvalidHeader(String),
+    #[error("invalid etag，{0}")]
+    InvalidEtag(String),
+    #[error("invalid byte range，start: {0}，end: {1}")]
+    InvalidByteRange(u64, u64),
+}
+
+/// `HttpDownloaderBuilder` 中所有可以序列化的选项，用于持久化下载任务并在之后重新创建完全相同的下载器
+///
+/// 运行时对象（客户端、`open_option`、`http_request_configure`、取消令牌、主机连接数限制、`url_refresher`）不包含在内，
+/// 通过 `HttpDownloaderBuilder::try_from` 得到构建器后需要重新设置
+///
+/// 扩展的选项（比如速度限制）不是构建器的选项，而是在 `build` 时传入的扩展中，速度限制还可以在下载时修改，
+/// 所以也不包含在内，需要与 `DownloadSpec` 一起保存当前的值，重新创建扩展时传入
+///
+/// `client_config` 中的代理密码等凭据以明文保存，见 `ClientConfig::without_secrets`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DownloadSpec {
+    pub schema_version: u32,
+    pub url: Url,
+    pub save_dir: PathBuf,
+    // None 时从地址或响应中获取
+    pub file_name: Option<String>,
+    pub chunk_size: NonZeroUsize,
+    pub download_connection_count: NonZeroU8,
+    pub set_len_in_advance: bool,
+    pub create_dir: bool,
+    pub request_retry_count: u8,
+    pub etag: Option<String>,
+    // 按顺序添加，同名的请求头会保留多个值
+    pub headers: Vec<(String, String)>,
+    pub downloaded_len_send_interval: Option<Duration>,
+    pub chunks_send_interval: Option<Duration>,
+    pub strict_check_accept_ranges: bool,
+    pub handle_redirection: HttpRedirectionHandle,
+    pub use_browser_user_agent: bool,
+    pub endgame: bool,
+    pub byte_range: Option<ChunkRange>,
+    pub sequential: bool,
+    pub chunk_hash_verify: ChunkHashVerify,
+    pub progress_map_pieces: NonZeroUsize,
+    pub client_config: ClientConfig,
+    pub method: String,
+    pub body: Option<Vec<u8>>,
+    // 没有开启 `decompress` 功能时保存的数据中没有这个字段
+    #[cfg(feature = "decompress")]
+    #[serde(default)]
+    pub decompress_mode: crate::DecompressMode,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use crate::{HttpDownloaderBuilder, ProxyConfig};
+
+    use super::*;
+
+    #[test]
+    fn round_trip_through_storage() {
+        let mut header_map = headers::HeaderMap::new();
+        header_map.append("x-token", "a".parse().unwrap());
+        header_map.append("x-token", "b".parse().unwrap());
+        let builder = HttpDownloaderBuilder::new("https://example.com/a.bin".parse().unwrap(), PathBuf::from("/tmp/download"))
+            .file_name(Some("b.bin".to_string()))
+            .chunk_size(NonZeroUsize::new(1024 * 1024).unwrap())
+            .download_connection_count(NonZeroU8::new(8).unwrap())
+            .etag(Some("W/\"abc\"".parse().unwrap()))
+            .header_map(header_map)
+            .use_browser_user_agent(false)
+            .byte_range(100..=199)
+            .chunk_hash_verify(ChunkHashVerify::Sample(NonZeroUsize::new(4).unwrap()))
+            .downloaded_len_send_interval(None)
+            .handle_redirection(HttpRedirectionHandle::Invalid)
+            .method(reqwest::Method::POST)
+            .body("query")
+            .proxy(ProxyConfig { url: "socks5://127.0.0.1:1080".to_string(), ..Default::default() })
+            .local_address(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let spec = DownloadSpec::from(&builder);
+        let document = bson::to_document(&spec).unwrap();
+        let restored: DownloadSpec = bson::from_document(document.clone()).unwrap();
+        assert_eq!(restored, spec);
+        // 没有开启 `decompress` 功能时保存的数据
+        #[cfg(feature = "decompress")]
+        {
+            let mut document = document;
+            document.remove("decompress_mode");
+            let restored: DownloadSpec = bson::from_document(document).unwrap();
+            assert_eq!(restored.decompress_mode, crate::DecompressMode::default());
+        }
+        let rebuilt = HttpDownloaderBuilder::try_from(restored).unwrap();
+        assert_eq!(DownloadSpec::from(&rebuilt), spec);
+        assert_eq!(spec.headers, vec![("x-token".to_string(), "a".to_string()), ("x-token".to_string(), "b".to_string())]);
+
+        let newer = DownloadSpec { schema_version: DOWNLOAD_SPEC_SCHEMA_VERSION + 1, ..spec };
+        assert!(matches!(HttpDownloaderBuilder::try_from(newer), Err(DownloadSpecError::UnsupportedSchemaVersion(_))));
+    }
+
+    #[test]
+    fn secrets_are_not_kept() {
+        let builder = HttpDownloaderBuilder::new("https://example.com/a.bin".parse().unwrap(), PathBuf::from("/tmp/download"))
+            .proxy(ProxyConfig {
+                url: "http://127.0.0.1:8080".to_string(),
+                username: Some("user".to_string()),
+                password: Some("secret".to_string()),
+                ..Default::default()
+            });
+        let spec = DownloadSpec::from(&builder);
+        assert_eq!(spec.client_config.proxies[0].password.as_deref(), Some("secret"));
+        let client_config = spec.client_config.without_secrets();
+        assert_eq!(client_config.proxies[0].username.as_deref(), Some("user"));
+        assert_eq!(client_config.proxies[0].password, None);
+    }
+}
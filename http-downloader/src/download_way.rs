@@ -1,4 +1,5 @@
 use std::fmt::Debug;
+use std::num::NonZeroUsize;
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -11,13 +12,17 @@ use tokio::select;
 use tokio::sync;
 use tokio_util::sync::CancellationToken;
 
-use crate::{ChunkManager, DownloadArchiveData, DownloadedLenChangeNotify, DownloadError, DownloadingEndCause, HttpDownloadConfig};
+use crate::{ChunkManager, ChunkRange, DownloadArchiveData, DownloadedLenChangeNotify, DownloadError, DownloadingEndCause, HttpDownloadConfig, ProgressMap};
 
 #[derive(Debug)]
 pub struct SingleDownload {
     cancel_token: CancellationToken,
     downloaded_len_sender: Arc<sync::watch::Sender<u64>>,
+    contiguous_len_sender: sync::watch::Sender<u64>,
     pub content_length: Option<u64>,
+    // 需要解压时，`content_length` 为压缩数据的长度
+    #[cfg(feature = "decompress")]
+    pub decompression: Option<crate::Decompression>,
 }
 
 impl SingleDownload {
@@ -26,13 +31,33 @@ impl SingleDownload {
         downloaded_len_sender: Arc<sync::watch::Sender<u64>>,
         content_length: Option<u64>,
     ) -> Self {
+        let (contiguous_len_sender, _) = sync::watch::channel(0);
         Self {
             cancel_token,
             downloaded_len_sender,
+            contiguous_len_sender,
             content_length,
+            #[cfg(feature = "decompress")]
+            decompression: None,
         }
     }
 
+    /// 边下载边解压，写入磁盘的是解压后的数据
+    #[cfg(feature = "decompress")]
+    pub(crate) fn with_decompression(mut self, compression: crate::Compression) -> Self {
+        self.decompression = Some(crate::Decompression::new(compression));
+        self
+    }
+
+    /// 已写入磁盘的长度
+    pub fn contiguous_len(&self) -> u64 {
+        *self.contiguous_len_sender.borrow()
+    }
+
+    pub fn contiguous_len_receiver(&self) -> sync::watch::Receiver<u64> {
+        self.contiguous_len_sender.subscribe()
+    }
+
     pub async fn download(
         &self,
         mut file: File,
@@ -40,6 +65,10 @@ impl SingleDownload {
         downloaded_len_receiver: Option<Arc<dyn DownloadedLenChangeNotify>>,
         buffer_size: usize,
     ) -> Result<DownloadingEndCause, DownloadError> {
+        #[cfg(feature = "decompress")]
+        if let Some(decompression) = self.decompression.as_ref() {
+            return self.download_decompressed(decompression, file, *response, downloaded_len_receiver, buffer_size).await;
+        }
         use futures_util::StreamExt;
         let mut chunk_bytes = Vec::with_capacity(buffer_size);
         let future = async {
@@ -61,6 +90,7 @@ impl SingleDownload {
                     file.write_all(&chunk_bytes).await?;
                     file.flush().await?;
                     file.sync_all().await?;
+                    self.contiguous_len_sender.send_modify(|n| *n += chunk_bytes.len() as u64);
                     chunk_bytes.clear();
                 }
 
@@ -78,6 +108,68 @@ impl SingleDownload {
                 file.write_all(&chunk_bytes).await?;
                 file.flush().await?;
                 file.sync_all().await?;
+                self.contiguous_len_sender.send_modify(|n| *n += chunk_bytes.len() as u64);
+                DownloadingEndCause::DownloadFinished
+            }
+            _ = self.cancel_token.cancelled() => {DownloadingEndCause::Cancelled}
+        })
+    }
+
+    #[cfg(feature = "decompress")]
+    async fn download_decompressed(
+        &self,
+        decompression: &crate::Decompression,
+        mut file: File,
+        response: Response,
+        downloaded_len_receiver: Option<Arc<dyn DownloadedLenChangeNotify>>,
+        buffer_size: usize,
+    ) -> Result<DownloadingEndCause, DownloadError> {
+        use futures_util::StreamExt;
+        use tokio::io::AsyncReadExt;
+        let stream = response.bytes_stream().then(|bytes| {
+            let downloaded_len_receiver = downloaded_len_receiver.clone();
+            async move {
+                let bytes = bytes.map_err(std::io::Error::other)?;
+                // 下载进度与限速按接收到的压缩数据计算
+                self.downloaded_len_sender.send_modify(|n| *n += bytes.len() as u64);
+                if let Some(downloaded_len_receiver) = downloaded_len_receiver.as_ref() {
+                    downloaded_len_receiver.receive_len(bytes.len()).await;
+                }
+                std::io::Result::Ok(bytes)
+            }
+        });
+        let mut reader = decompression.reader(Box::pin(stream));
+        let future = async {
+            let mut buffer = vec![0; buffer_size];
+            let mut finished = false;
+            while !finished {
+                // 填满缓冲后写入磁盘
+                let mut len = 0;
+                while len < buffer.len() {
+                    let read_len = reader.read(&mut buffer[len..]).await.map_err(|err| {
+                        // 还原请求错误
+                        match err.get_ref().is_some_and(|n| n.is::<reqwest::Error>()) {
+                            true => DownloadError::HttpRequestFailed(*err.into_inner().unwrap().downcast::<reqwest::Error>().unwrap()),
+                            false => DownloadError::IoError(err),
+                        }
+                    })?;
+                    if read_len == 0 {
+                        finished = true;
+                        break;
+                    }
+                    len += read_len;
+                    decompression.add_decompressed_len(read_len);
+                }
+                file.write_all(&buffer[..len]).await?;
+                file.flush().await?;
+                file.sync_all().await?;
+                self.contiguous_len_sender.send_modify(|n| *n += len as u64);
+            }
+            Result::<(), DownloadError>::Ok(())
+        };
+        Ok(select! {
+            r = future => {
+                r?;
                 DownloadingEndCause::DownloadFinished
             }
             _ = self.cancel_token.cancelled() => {DownloadingEndCause::Cancelled}
@@ -110,3 +202,60 @@ pub enum DownloadWay {
     Ranges(Arc<ChunkManager>),
     Single(SingleDownload),
 }
+
+impl DownloadWay {
+    /// 从文件开头起连续写入磁盘的长度接收器
+    pub fn contiguous_len_receiver(&self) -> sync::watch::Receiver<u64> {
+        match self {
+            DownloadWay::Ranges(chunk_manager) => chunk_manager.contiguous_len_receiver(),
+            DownloadWay::Single(single_download) => single_download.contiguous_len_receiver(),
+        }
+    }
+
+    /// 下载内容的总长度，未知时返回 None
+    pub fn content_length(&self) -> Option<u64> {
+        match self {
+            DownloadWay::Ranges(chunk_manager) => Some(chunk_manager.chunk_iterator.content_length),
+            DownloadWay::Single(single_download) => {
+                // 解压后的长度未知
+                #[cfg(feature = "decompress")]
+                if single_download.decompression.is_some() {
+                    return None;
+                }
+                single_download.content_length
+            }
+        }
+    }
+
+    /// 已解压的长度，不需要解压时返回 None
+    #[cfg(feature = "decompress")]
+    pub fn decompressed_len(&self) -> Option<u64> {
+        match self {
+            DownloadWay::Ranges(_) => None,
+            DownloadWay::Single(single_download) => single_download.decompression.as_ref().map(|n| n.decompressed_len()),
+        }
+    }
+
+    /// 下载进度图，单连接下载时总长度未知则返回 None
+    pub fn progress_map(&self, piece_count: NonZeroUsize) -> Option<ProgressMap> {
+        match self {
+            DownloadWay::Ranges(chunk_manager) => Some(chunk_manager.progress_map()),
+            DownloadWay::Single(single_download) => single_download.content_length.map(|content_length| {
+                let mut progress_map = ProgressMap::new(content_length, piece_count);
+                #[cfg(feature = "decompress")]
+                let downloaded_len = match single_download.decompression.is_some() {
+                    // 写入磁盘的是解压后的数据，按接收到的压缩数据计算
+                    true => *single_download.downloaded_len_sender.borrow(),
+                    false => single_download.contiguous_len(),
+                };
+                #[cfg(not(feature = "decompress"))]
+                let downloaded_len = single_download.contiguous_len();
+                let downloaded_len = downloaded_len.min(content_length);
+                if downloaded_len != 0 {
+                    progress_map.add_range(ChunkRange::from_len(0, downloaded_len));
+                }
+                progress_map
+            }),
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::future::Future;
 use std::io::SeekFrom;
 use std::num::{NonZeroU64, NonZeroU8, NonZeroUsize};
+use std::ops::RangeInclusive;
 use std::path::PathBuf;
 use std::sync::{Arc, Weak};
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -20,7 +21,10 @@ use tokio::task::JoinError;
 use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
 
-use crate::{ChunkData, ChunkItem, ChunkIterator, ChunkManager, ChunksInfo, DownloadArchiveData, DownloadedLenChangeNotify, DownloaderWrapper, DownloadFuture, DownloadWay, HttpDownloadConfig, HttpRedirectionHandle, RemainingChunks, SingleDownload};
+use crate::{ChunkControl, ClientConfigError, ChunkData, ChunkHashVerify, ChunkItem, ChunkIterator, ChunkManager, ChunkRange, ChunksInfo, DownloadArchiveData, DownloadedLenChangeNotify, DownloaderWrapper, DownloadFuture, DownloadWay, HostConnectionPermit, HttpDownloadConfig, HttpRedirectionHandle, Redirection, ProgressMap, RemainingChunks, RequestInterceptor, SequentialReader, SharedDownloaderWrapper, SingleDownload};
+use crate::local_source;
+use crate::request_interceptor;
+use crate::url_refresher::{MAX_URL_REFRESH_TIMES, UrlRefresh};
 use crate::exclusive::Exclusive;
 
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
@@ -36,10 +40,15 @@ pub enum DownloadStartError {
     #[error("{:?}", .0)]
     Other(#[from] anyhow::Error),
 
+    #[error("{:?}", .0)]
+    InvalidClientConfig(#[from] ClientConfigError),
+
     #[error("already downloading")]
     AlreadyDownloading,
     #[error("Directory does not exist")]
     DirectoryDoesNotExist,
+    #[error("invalid byte range，start: {0}，end: {1}")]
+    InvalidByteRange(u64, u64),
 
     #[cfg(feature = "status-tracker")]
     #[error("Initializing")]
@@ -57,6 +66,13 @@ pub enum HttpResponseInvalidCause {
     ContentLengthInvalid,
     StatusCodeUnsuccessful,
     RedirectionNoLocation,
+    // 重定向到 http(s) 以外的地址，`file:`、`data:` 等地址只有原地址也是同一协议时才会跟随
+    RedirectionSchemeNotAllowed,
+    ByteRangeNotSupported,
+    // chunk 请求没有返回 206，服务器忽略了 Range 请求头；不再把完整的响应当作 chunk 的内容写入文件
+    ChunkRangeNotSatisfied,
+    // 指定了下载范围，但 Content-Range 的起始位置与请求的不同
+    ContentRangeMismatch,
 }
 
 #[derive(Error, Debug)]
@@ -81,6 +97,8 @@ pub enum DownloadError {
     ServerFileAlreadyChanged,
     #[error("The redirection times are too many.")]
     RedirectionTimesTooMany,
+    #[error("url refresh failed，{:?}", .0)]
+    UrlRefreshFailed(anyhow::Error),
 }
 
 #[derive(Error, Debug)]
@@ -109,10 +127,139 @@ pub enum ChangeChunkSizeError {
     DownloadTargetNotSupported,
 }
 
+#[derive(Error, Debug)]
+pub enum PrioritizeRangeError {
+    #[error("it is no start")]
+    NoStart,
+    #[error("The download target is not supported")]
+    DownloadTargetNotSupported,
+    #[error("invalid range {start}..={end}，content length: {content_length}")]
+    InvalidRange { start: u64, end: u64, content_length: u64 },
+}
+
+#[derive(Error, Debug)]
+pub enum ChunkControlError {
+    #[error("it is no start")]
+    NoStart,
+    #[error("The download target is not supported")]
+    DownloadTargetNotSupported,
+    #[error("chunk {0} is not downloading")]
+    ChunkNotFound(usize),
+    #[error("chunk {0} is racing in endgame")]
+    ChunkRacing(usize),
+}
+
+/// 开始下载时断点续传数据的使用情况
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ResumeState {
+    /// 没有断点续传数据
+    NotResumed,
+    /// 从断点续传数据继续下载
+    Resumed,
+    /// 断点续传数据与文件不匹配，已重新开始下载
+    Restarted(ResumeInvalidCause),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ResumeInvalidCause {
+    /// 文件不存在
+    FileNotFound,
+    /// 文件长度小于已完成部分的结束位置
+    FileTooShort { file_len: u64, completed_end: u64 },
+    /// 存档时的文件大小与服务器返回的不一致
+    ContentLengthMismatch { archived: u64, current: u64 },
+    /// 剩余范围超出了文件大小
+    ChunkRangeOutOfBounds,
+    /// 存档时的 ETag 与服务器返回的不一致，文件已被修改
+    EtagMismatch { archived: String, current: Option<String> },
+    /// 服务器不支持范围请求，无法续传
+    RangesNotSupported,
+}
+
+async fn check_archive_data(
+    file_path: PathBuf,
+    archive_data: &DownloadArchiveData,
+    content_length: u64,
+    etag: Option<&str>,
+) -> Result<Option<ResumeInvalidCause>, DownloadError> {
+    if let Some(archived) = archive_data.content_length {
+        if archived != content_length {
+            return Ok(Some(ResumeInvalidCause::ContentLengthMismatch { archived, current: content_length }));
+        }
+    }
+    // 旧存档没有记录 ETag
+    if let Some(archived) = archive_data.etag.as_ref() {
+        if etag != Some(archived.as_str()) {
+            return Ok(Some(ResumeInvalidCause::EtagMismatch { archived: archived.clone(), current: etag.map(|n| n.to_string()) }));
+        }
+    }
+    let Some(chunk_data) = archive_data.chunk_data.as_ref() else {
+        return Ok(None);
+    };
+    let is_out_of_bounds = chunk_data.remaining.ranges.iter()
+        .chain(chunk_data.last_incomplete_chunks.iter().map(|n| &n.range))
+        .any(|n| n.end >= content_length);
+    if is_out_of_bounds {
+        return Ok(Some(ResumeInvalidCause::ChunkRangeOutOfBounds));
+    }
+    let completed_end = chunk_data.completed_end(content_length);
+    if completed_end == 0 {
+        return Ok(None);
+    }
+    match tokio::fs::metadata(file_path).await {
+        Ok(metadata) if metadata.len() < completed_end => Ok(Some(ResumeInvalidCause::FileTooShort {
+            file_len: metadata.len(),
+            completed_end,
+        })),
+        Ok(_) => Ok(None),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Some(ResumeInvalidCause::FileNotFound)),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// 决定是否使用断点续传数据，`content_length` 为 None 表示不能按范围下载
+async fn resume_archive_data(
+    file_path: PathBuf,
+    archive_data: Option<Box<DownloadArchiveData>>,
+    content_length: Option<u64>,
+    etag: Option<&str>,
+) -> Result<(Option<Box<DownloadArchiveData>>, ResumeState), DownloadError> {
+    let Some(archive_data) = archive_data else {
+        return Ok((None, ResumeState::NotResumed));
+    };
+    let Some(content_length) = content_length else {
+        return Ok((None, ResumeState::Restarted(ResumeInvalidCause::RangesNotSupported)));
+    };
+    match check_archive_data(file_path, &archive_data, content_length, etag).await? {
+        None => Ok((Some(archive_data), ResumeState::Resumed)),
+        Some(cause) => {
+            #[cfg(feature = "tracing")]
+            tracing::warn!("The archive data does not match the file, restart download. {:?}", cause);
+            Ok((None, ResumeState::Restarted(cause)))
+        }
+    }
+}
+
+fn check_byte_range_response(byte_range: ChunkRange, response: &reqwest::Response) -> Result<(), HttpResponseInvalidCause> {
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return Err(HttpResponseInvalidCause::ByteRangeNotSupported);
+    }
+    let content_range = response.headers().typed_get::<headers::ContentRange>().and_then(|n| n.bytes_range());
+    match content_range {
+        Some((start, end)) if start == byte_range.start && end <= byte_range.end => Ok(()),
+        _ => Err(HttpResponseInvalidCause::ContentRangeMismatch),
+    }
+}
+
 pub struct DownloadingState {
     pub downloading_duration: u32,
     pub download_instant: Instant,
     pub download_way: DownloadWay,
+    pub resume_state: ResumeState,
+    // 首次请求的响应中的 ETag，与断点续传数据一起保存
+    pub etag: Option<String>,
 }
 
 impl DownloadingState {
@@ -131,13 +278,18 @@ pub struct BreakpointResume {
 pub struct HttpFileDownloader {
     pub downloading_state_oneshot_vec: Vec<sync::oneshot::Sender<Arc<DownloadingState>>>,
     pub downloaded_len_change_notify: Option<Arc<dyn DownloadedLenChangeNotify>>,
+    // 作用于所有请求的拦截器，由扩展添加
+    pub request_interceptors: Vec<Arc<dyn RequestInterceptor>>,
+    // 扩展的 chunk 级别钩子，由 `ExtendedHttpFileDownloader` 设置
+    pub(crate) chunk_hooks: Option<SharedDownloaderWrapper>,
     pub archive_data_future: Option<Exclusive<BoxFuture<'static, Result<Option<Box<DownloadArchiveData>>>>>>,
     #[cfg(feature = "breakpoint-resume")]
     pub breakpoint_resume: Option<Arc<BreakpointResume>>,
     pub config: Arc<HttpDownloadConfig>,
     pub downloaded_len_receiver: sync::watch::Receiver<u64>,
     pub content_length: Arc<AtomicU64>,
-    client: reqwest::Client,
+    // 没有指定客户端时，开始下载前根据 `config.client_config` 创建
+    client: Option<reqwest::Client>,
     downloading_state: Arc<RwLock<
         Option<(
             sync::oneshot::Receiver<DownloadingEndCause>,
@@ -151,6 +303,11 @@ pub struct HttpFileDownloader {
 
 impl HttpFileDownloader {
     pub fn new(client: reqwest::Client, config: Arc<HttpDownloadConfig>) -> Self {
+        Self::with_client(Some(client), config)
+    }
+
+    /// `client` 为 None 时，开始下载前根据 `config.client_config` 创建客户端
+    pub(crate) fn with_client(client: Option<reqwest::Client>, config: Arc<HttpDownloadConfig>) -> Self {
         let cancel_token = config.cancel_token.clone().unwrap_or_default();
         let (downloaded_len_sender, downloaded_len_receiver) = sync::watch::channel::<u64>(0);
         let total_size_semaphore = Arc::new(sync::Semaphore::new(0));
@@ -158,6 +315,8 @@ impl HttpFileDownloader {
         Self {
             downloading_state_oneshot_vec: vec![],
             downloaded_len_change_notify: None,
+            request_interceptors: vec![],
+            chunk_hooks: None,
             archive_data_future: None,
             #[cfg(feature = "breakpoint-resume")]
             breakpoint_resume: None,
@@ -206,6 +365,27 @@ impl HttpFileDownloader {
         }
     }
 
+    pub fn prioritize_range(&self, range: RangeInclusive<u64>) -> Result<(), PrioritizeRangeError> {
+        match self.downloading_state.read().as_ref() {
+            None => Err(PrioritizeRangeError::NoStart),
+            Some((_, downloading_state)) => match &downloading_state.download_way {
+                DownloadWay::Single(_) => Err(PrioritizeRangeError::DownloadTargetNotSupported),
+                DownloadWay::Ranges(chunk_manager) => chunk_manager.prioritize_range(range),
+            },
+        }
+    }
+
+    pub async fn control_chunk(&self, index: usize, control: ChunkControl) -> Result<(), ChunkControlError> {
+        let chunk_manager = match self.downloading_state.read().as_ref() {
+            None => return Err(ChunkControlError::NoStart),
+            Some((_, downloading_state)) => match &downloading_state.download_way {
+                DownloadWay::Single(_) => return Err(ChunkControlError::DownloadTargetNotSupported),
+                DownloadWay::Ranges(chunk_manager) => chunk_manager.clone(),
+            },
+        };
+        chunk_manager.control_chunk(index, control).await
+    }
+
     #[cfg(feature = "async-stream")]
     pub fn downloaded_len_stream(&self) -> impl Stream<Item=u64> + 'static {
         let mut downloaded_len_receiver = self.downloaded_len_receiver.clone();
@@ -342,6 +522,36 @@ impl HttpFileDownloader {
         self.config.file_path()
     }
 
+    /// 下载使用的客户端，没有指定客户端时开始下载后才有值
+    pub fn client(&self) -> Option<&reqwest::Client> {
+        self.client.as_ref()
+    }
+
+    pub fn sequential_reader(&self) -> Option<SequentialReader> {
+        self.downloading_state.read().as_ref().map(|(_, downloading_state)| {
+            SequentialReader::new(
+                self.config.file_path(),
+                downloading_state.download_way.content_length(),
+                downloading_state.download_way.contiguous_len_receiver(),
+            )
+        })
+    }
+
+    pub fn progress_map(&self) -> Option<ProgressMap> {
+        self.downloading_state.read().as_ref().and_then(|(_, downloading_state)| {
+            downloading_state.download_way.progress_map(self.config.progress_map_pieces)
+        })
+    }
+
+    /// 已解压的长度，不需要解压或下载没有开始时返回 None
+    #[cfg(feature = "decompress")]
+    pub fn decompressed_len(&self) -> Option<u64> {
+        self.downloading_state
+            .read()
+            .as_ref()
+            .and_then(|(_, downloading_state)| downloading_state.download_way.decompressed_len())
+    }
+
     fn reset(&self) {
         self.downloaded_len_sender.send(0).unwrap_or_else(|_err| {
             #[cfg(feature = "tracing")]
@@ -359,13 +569,33 @@ impl HttpFileDownloader {
         if self.is_downloading() {
             return Err(DownloadStartError::AlreadyDownloading);
         }
+        if let Some(byte_range) = self.config.byte_range {
+            if byte_range.start > byte_range.end {
+                return Err(DownloadStartError::InvalidByteRange(byte_range.start, byte_range.end));
+            }
+        }
 
         if self.config.create_dir {
             std::fs::create_dir_all(&self.config.save_dir)?;
         } else if !self.config.save_dir.exists() {
             return Err(DownloadStartError::DirectoryDoesNotExist);
         }
-        Ok(self.start_download())
+        // ftp:// 地址在本地连接，配置了代理时直接返回错误
+        #[cfg(feature = "ftp")]
+        crate::ftp::check_proxy(&self.config.client_config, &self.config.url)?;
+        let client = match self.client.as_ref() {
+            Some(client) => client.clone(),
+            None => {
+                let mut client_builder = self.config.client_config.apply(reqwest::Client::builder())?;
+                // 由下载器处理重定向，拦截器才能处理每一次重定向的请求与响应
+                if !self.request_interceptors.is_empty() && self.config.handle_redirection != HttpRedirectionHandle::Invalid {
+                    client_builder = client_builder.redirect(reqwest::redirect::Policy::none());
+                }
+                let client = client_builder.build().map_err(ClientConfigError::BuildClientFailed)?;
+                self.client.insert(client).clone()
+            }
+        };
+        Ok(self.start_download(client))
     }
 
     pub fn take_downloading_state(
@@ -398,16 +628,19 @@ impl HttpFileDownloader {
     //noinspection RsExternalLinter
     fn start_download(
         &mut self,
+        client: reqwest::Client,
     ) -> impl Future<Output=Result<DownloadingEndCause, DownloadError>> + 'static {
         if self.cancel_token.is_cancelled() {
             self.cancel_token = CancellationToken::new();
         }
         let config = self.config.clone();
-        let client = self.client.clone();
         let total_size_semaphore = self.total_size_semaphore.clone();
         let content_length_arc = self.content_length.clone();
         let downloading_state = self.downloading_state.clone();
         let downloaded_len_change_notify = self.downloaded_len_change_notify.take();
+        let request_interceptors: Arc<[Arc<dyn RequestInterceptor>]> = self.request_interceptors.clone().into();
+        let chunk_hooks = self.chunk_hooks.clone();
+        let url_refresh = config.url_refresher.clone().map(|n| Arc::new(UrlRefresh::new(n, config.clone())));
         let archive_data_future = self.archive_data_future.take();
         let downloading_state_oneshot_vec: Vec<sync::oneshot::Sender<Arc<DownloadingState>>> = self.downloading_state_oneshot_vec.drain(..).collect();
         let downloaded_len_sender = self.downloaded_len_sender.clone();
@@ -417,7 +650,10 @@ impl HttpFileDownloader {
 
 
         async move {
-            fn request<'a>(client: &'a reqwest::Client, config: &'a HttpDownloadConfig, location: Option<String>, redirection_times: usize) -> BoxFuture<'a, Result<(reqwest::Response, Option<String>), DownloadError>> {
+            // 响应、重定向后的地址与这次连接的许可
+            type ProbeResponse = (reqwest::Response, Option<Redirection>, Option<HostConnectionPermit>);
+
+            fn request<'a>(client: &'a reqwest::Client, config: &'a HttpDownloadConfig, request_interceptors: &'a [Arc<dyn RequestInterceptor>], url_refresh: Option<&'a UrlRefresh>, mut redirection: Option<Redirection>, redirection_times: usize) -> BoxFuture<'a, Result<ProbeResponse, DownloadError>> {
                 async move {
                     if let HttpRedirectionHandle::RequestNewLocation { max_times } = config.handle_redirection {
                         if redirection_times >= max_times {
@@ -425,9 +661,35 @@ impl HttpFileDownloader {
                         }
                     }
                     let mut retry_count = 0;
-                    let response = loop {
-                        let response = client.execute(config.create_http_request(location.as_ref().map(|n| n.as_str())))
-                            .await.and_then(|n| n.error_for_status());
+                    let mut interceptor_retry_count = 0;
+                    let mut url_refresh_count = 0;
+                    let (response, permit) = loop {
+                        let generation = url_refresh.map(|n| n.generation());
+                        let mut http_request = config.create_http_request(redirection.as_ref());
+                        request_interceptor::intercept_request(request_interceptors, &mut http_request);
+                        // 与 chunk 一样占用一个连接许可，单连接下载时持有到下载结束
+                        let permit = match config.host_connection_limiter.as_ref() {
+                            None => None,
+                            Some(host_connection_limiter) => Some(host_connection_limiter.acquire(http_request.url()).await),
+                        };
+                        let response = local_source::execute(client, &config.client_config, http_request).await;
+                        if let Ok(response) = response.as_ref() {
+                            if request_interceptor::intercept_response(request_interceptors, response)
+                                && interceptor_retry_count < request_interceptor::MAX_INTERCEPTOR_RETRY_TIMES {
+                                interceptor_retry_count += 1;
+                                continue;
+                            }
+                            if let (Some(url_refresh), Some(generation)) = (url_refresh, generation) {
+                                if UrlRefresh::is_expired_status(response.status()) && url_refresh_count < MAX_URL_REFRESH_TIMES {
+                                    url_refresh_count += 1;
+                                    url_refresh.refresh(response.url(), generation).await?;
+                                    // 刷新后的地址替代了原地址与重定向后的地址
+                                    redirection = None;
+                                    continue;
+                                }
+                            }
+                        }
+                        let response = response.and_then(|n| n.error_for_status());
 
                         if response.is_err() && retry_count < config.request_retry_count {
                             retry_count += 1;
@@ -440,7 +702,7 @@ impl HttpFileDownloader {
                         );
                             continue;
                         }
-                        break response;
+                        break (response, permit);
                     };
                     // todo: 删除重定向，reqwest 本身可以处理重定向
                     match response {
@@ -452,7 +714,14 @@ impl HttpFileDownloader {
                                 return Err(DownloadError::HttpRequestResponseInvalid(HttpResponseInvalidCause::RedirectionNoLocation, response));
                             };
                             println!("handle_redirection!!!!!!! {}",location);
-                            request(client, config, Some(location), redirection_times + 1).await
+                            let redirection = Redirection::new(redirection.as_ref(), &config.method, response.status(), location);
+                            // chunk 请求也使用重定向后的地址，服务器不能让下载器读取本地文件
+                            if !Redirection::is_allowed(&config.request_url(None), &config.request_url(Some(&redirection))) {
+                                return Err(DownloadError::HttpRequestResponseInvalid(HttpResponseInvalidCause::RedirectionSchemeNotAllowed, response));
+                            }
+                            // 重定向后的请求重新获取许可，同一主机只允许一个连接时不会等待自己
+                            drop(permit);
+                            request(client, config, request_interceptors, url_refresh, Some(redirection), redirection_times + 1).await
                         }
                         Ok(response) if !response.status().is_success() => {
                             Err(DownloadError::HttpRequestResponseInvalid(HttpResponseInvalidCause::StatusCodeUnsuccessful, response))
@@ -460,14 +729,14 @@ impl HttpFileDownloader {
                         Err(err) => {
                             Err(DownloadError::HttpRequestFailed(err))
                         }
-                        Ok(response) => Ok((response, location)),
+                        Ok(response) => Ok((response, redirection, permit)),
                     }
                 }.boxed()
             }
 
             let (end_sender, end_receiver) = sync::oneshot::channel();
             let dec = {
-                let (response, location) = match request(&client, &config, None, 0).await {
+                let (response, redirection, permit) = match request(&client, &config, &request_interceptors, url_refresh.as_deref(), None, 0).await {
                     Ok(r) => r,
                     Err(err) => {
                         total_size_semaphore.add_permits(1);
@@ -502,75 +771,145 @@ impl HttpFileDownloader {
                     total_size_semaphore.add_permits(1);
                     return Err(DownloadError::HttpRequestResponseInvalid(HttpResponseInvalidCause::ContentLengthInvalid, response));
                 }
+                // 指定了下载范围，服务器必须返回从请求位置开始的 206 响应
+                if let Some(byte_range) = config.byte_range {
+                    if let Err(cause) = check_byte_range_response(byte_range, &response) {
+                        total_size_semaphore.add_permits(1);
+                        return Err(DownloadError::HttpRequestResponseInvalid(cause, response));
+                    }
+                }
                 content_length_arc.store(content_length.unwrap_or(0), Ordering::Relaxed);
+                if let Some(url_refresh) = url_refresh.as_ref() {
+                    url_refresh.set_expected(&response);
+                }
 
                 let accept_ranges = response.headers().typed_get::<headers::AcceptRanges>();
 
                 let is_ranges_bytes_none = accept_ranges.is_none();
-                let is_ranges_bytes =
-                    !is_ranges_bytes_none && accept_ranges.unwrap() == headers::AcceptRanges::bytes();
+                let is_ranges_bytes = config.byte_range.is_some() ||
+                    (!is_ranges_bytes_none && accept_ranges.unwrap() == headers::AcceptRanges::bytes());
                 let archive_data = match archive_data_future {
                     None => { None }
                     Some(archive_data_future) => {
                         archive_data_future.await.map_err(DownloadError::ArchiveDataLoadError)?
                     }
                 };
+                #[cfg(feature = "decompress")]
+                let compression = crate::Compression::from_response(&response, config.decompress_mode, &config.url);
+                // 服务器或代理依然压缩了响应，长度与 Range 偏移都不再对应原始数据
+                let is_content_encoded = response
+                    .headers()
+                    .get(reqwest::header::CONTENT_ENCODING)
+                    .is_some_and(|n| !n.as_bytes().eq_ignore_ascii_case(b"identity"));
+                #[cfg(feature = "decompress")]
+                let is_content_encoded = is_content_encoded || compression.is_some();
+                let is_ranges_way = content_length.is_some()
+                    && !is_content_encoded
+                    // 只有 GET 请求的 Range 有明确定义，其他方法需要服务器明确表示支持
+                    && (if config.strict_check_accept_ranges || (config.method != reqwest::Method::GET && !redirection.as_ref().is_some_and(|n| n.use_get)) {
+                    is_ranges_bytes
+                } else {
+                    is_ranges_bytes_none || is_ranges_bytes
+                });
+                let response_etag = response.headers()
+                    .get(reqwest::header::ETAG)
+                    .and_then(|n| n.to_str().ok())
+                    .map(|n| n.to_string());
+                let (archive_data, resume_state) = resume_archive_data(
+                    config.file_path(),
+                    archive_data,
+                    content_length.filter(|_| is_ranges_way),
+                    response_etag.as_deref(),
+                ).await?;
                 let downloading_duration = archive_data.as_ref()
                     .map(|n| n.downloading_duration)
                     .unwrap_or(0);
                 let download_way = {
-                    if content_length.is_some()
-                        && (if config.strict_check_accept_ranges {
-                        is_ranges_bytes
-                    } else {
-                        is_ranges_bytes_none || is_ranges_bytes
-                    })
-                    {
+                    if is_ranges_way {
                         let content_length = content_length.unwrap();
-                        let chunk_data = archive_data
-                            .and_then(|archive_data| {
+                        let chunk_data = match archive_data {
+                            None => None,
+                            Some(archive_data) => {
+                                let mut downloaded_len = archive_data.downloaded_len;
+                                let chunk_data = match archive_data.chunk_data {
+                                    None => None,
+                                    Some(mut data) => {
+                                        data.remaining.chunk_size = config.chunk_size.get();
+                                        if config.chunk_hash_verify != ChunkHashVerify::Disabled {
+                                            let file_path = config.file_path();
+                                            let chunk_hash_verify = config.chunk_hash_verify;
+                                            let (data_result, mismatched_len) = tokio::task::spawn_blocking(move || {
+                                                let mismatched_len = data.verify_chunk_hashes(&file_path, chunk_hash_verify)?;
+                                                io::Result::Ok((data, mismatched_len))
+                                            }).await??;
+                                            #[cfg(feature = "tracing")]
+                                            if mismatched_len > 0 {
+                                                tracing::warn!("chunk hash mismatched, {} bytes will be downloaded again", mismatched_len);
+                                            }
+                                            downloaded_len = downloaded_len.saturating_sub(mismatched_len);
+                                            Some(data_result)
+                                        } else {
+                                            Some(data)
+                                        }
+                                    }
+                                };
                                 downloaded_len_sender
-                                    .send(archive_data.downloaded_len)
+                                    .send(downloaded_len)
                                     .unwrap_or_else(|_err| {
                                         #[cfg(feature = "tracing")]
                                         tracing::error!("send downloaded_len failed! {}", _err);
                                     });
-                                archive_data.chunk_data.map(|mut data| {
-                                    data.remaining.chunk_size = config.chunk_size.get();
-                                    data
-                                })
-                            })
+                                chunk_data
+                            }
+                        }
                             .unwrap_or_else(|| ChunkData {
                                 iter_count: 0,
                                 remaining: RemainingChunks::new(config.chunk_size, content_length),
                                 last_incomplete_chunks: Default::default(),
+                                chunk_hashes: Default::default(),
                             });
 
-                        let chunk_iterator = ChunkIterator::new(content_length, chunk_data);
+                        let chunk_iterator = ChunkIterator::new(content_length, chunk_data, config.sequential);
                         let chunk_manager = Arc::new(ChunkManager::new(
                             config.download_connection_count,
                             client,
+                            Arc::new(config.client_config.clone()),
                             cancel_token,
                             downloaded_len_sender,
                             chunk_iterator,
                             etag,
                             config.request_retry_count,
+                            config.endgame,
+                            config.byte_range.map(|n| n.start).unwrap_or(0),
+                            config.progress_map_pieces,
+                            config.host_connection_limiter.clone(),
+                            request_interceptors.clone(),
+                            url_refresh.clone(),
+                            chunk_hooks.clone(),
                         ));
                         DownloadWay::Ranges(chunk_manager)
                     } else {
-                        // download way changed? reset archive_data?
-                        DownloadWay::Single(SingleDownload::new(
+                        let single_download = SingleDownload::new(
                             cancel_token,
                             downloaded_len_sender,
                             content_length,
-                        ))
+                        );
+                        #[cfg(feature = "decompress")]
+                        let single_download = match compression {
+                            None => single_download,
+                            Some(compression) => single_download.with_decompression(compression),
+                        };
+                        DownloadWay::Single(single_download)
                     }
                 };
 
+                let is_restarted = matches!(resume_state, ResumeState::Restarted(_));
                 let state = DownloadingState {
                     downloading_duration,
                     download_instant: Instant::now(),
                     download_way,
+                    resume_state,
+                    etag: response_etag,
                 };
 
 
@@ -588,6 +927,10 @@ impl HttpFileDownloader {
                     let mut file = tokio::fs::OpenOptions::from(options)
                         .open(config.file_path())
                         .await?;
+                    // 重新开始下载时清空之前的内容
+                    if is_restarted {
+                        file.set_len(0).await?
+                    }
                     if config.set_len_in_advance {
                         file.set_len(content_length.unwrap()).await?
                     }
@@ -604,7 +947,10 @@ impl HttpFileDownloader {
 
                 let dec_result = match &state.download_way {
                     DownloadWay::Ranges(item) => {
-                        let request = Box::new(config.create_http_request(location.as_ref().map(|n| n.as_str())));
+                        // 首次请求的连接不再使用，释放许可给 chunk
+                        drop(response);
+                        drop(permit);
+                        let request = Box::new(config.create_http_request(redirection.as_ref()));
                         item.start_download(
                             file,
                             request,
@@ -615,6 +961,7 @@ impl HttpFileDownloader {
                             .await
                     }
                     DownloadWay::Single(item) => {
+                        let _permit = permit;
                         item.download(
                             file,
                             Box::new(response),
@@ -648,14 +995,17 @@ impl HttpFileDownloader {
 
 pub struct ExtendedHttpFileDownloader {
     pub inner: HttpFileDownloader,
-    downloader_wrapper: Box<dyn DownloaderWrapper>,
+    // 与 chunk 共享，chunk 下载时调用 chunk 级别的钩子
+    downloader_wrapper: SharedDownloaderWrapper,
 }
 
 impl ExtendedHttpFileDownloader {
     pub fn new(
-        downloader: HttpFileDownloader,
+        mut downloader: HttpFileDownloader,
         downloader_wrapper: Box<dyn DownloaderWrapper>,
     ) -> Self {
+        let downloader_wrapper = Arc::new(RwLock::new(downloader_wrapper));
+        downloader.chunk_hooks = Some(downloader_wrapper.clone());
         Self {
             inner: downloader,
             downloader_wrapper,
@@ -664,16 +1014,17 @@ impl ExtendedHttpFileDownloader {
 
     /// 准备下载，返回了用于下载用的 'static 的 Future
     pub fn prepare_download(&mut self) -> Result<DownloadFuture, DownloadStartError> {
-        self.downloader_wrapper.prepare_download(&mut self.inner)?;
+        let mut downloader_wrapper = self.downloader_wrapper.write();
+        downloader_wrapper.prepare_download(&mut self.inner)?;
         let prepare_download_result = self.inner.download();
-        let download_future = self.downloader_wrapper.handle_prepare_download_result(&mut self.inner, prepare_download_result.map(|n| n.boxed()))?;
+        let download_future = downloader_wrapper.handle_prepare_download_result(&mut self.inner, prepare_download_result.map(|n| n.boxed()))?;
 
-        self.downloader_wrapper.download(&mut self.inner, download_future)
+        downloader_wrapper.download(&mut self.inner, download_future)
     }
 
     /// 取消下载
     pub fn cancel(&self) -> impl Future<Output=()> + 'static {
-        let cancel = self.downloader_wrapper.on_cancel();
+        let cancel = self.downloader_wrapper.read().on_cancel();
         let cancel_future = self.inner.cancel();
         async move {
             cancel.await;
@@ -709,6 +1060,18 @@ impl ExtendedHttpFileDownloader {
         self.inner.change_chunk_size(chunk_size)
     }
 
+    /// 优先下载文件中 `range` 范围内（包含两端）还未下载的部分，比如 MP4 的 moov 或 ZIP 的中央目录
+    #[inline]
+    pub fn prioritize_range(&self, range: RangeInclusive<u64>) -> Result<(), PrioritizeRangeError> {
+        self.inner.prioritize_range(range)
+    }
+
+    /// 控制一个正在下载的 chunk：取消、重新连接或拆分，未下载的部分会放回剩余的 chunk 中
+    #[inline]
+    pub async fn control_chunk(&self, index: usize, control: ChunkControl) -> Result<(), ChunkControlError> {
+        self.inner.control_chunk(index, control).await
+    }
+
     /// chunks 流，如果还真正的开始下载（获取了请求响应内容）会返回 None，可通过 `total_size_future().await` 等待获取它，避免得到 None
     #[cfg(feature = "async-stream")]
     #[inline]
@@ -759,6 +1122,24 @@ impl ExtendedHttpFileDownloader {
         self.inner.get_file_path()
     }
 
+    /// 按顺序读取已下载内容的 `AsyncRead`，如果还真正的开始下载（获取了请求响应内容）会返回 None，可通过 `total_size_future().await` 等待获取它，避免得到 None
+    #[inline]
+    pub fn sequential_reader(&self) -> Option<SequentialReader> {
+        self.inner.sequential_reader()
+    }
+
+    /// 各块的下载进度，下载还没有开始或单连接下载不知道总长度时返回 None
+    #[inline]
+    pub fn progress_map(&self) -> Option<ProgressMap> {
+        self.inner.progress_map()
+    }
+
+    #[cfg(feature = "decompress")]
+    #[inline]
+    pub fn decompressed_len(&self) -> Option<u64> {
+        self.inner.decompressed_len()
+    }
+
     /// 获取 DownloadingState，如果下载没有开始则返回 None
     #[inline]
     pub fn get_downloading_state(&self) -> Option<Weak<DownloadingState>> {
@@ -800,4 +1181,185 @@ mod tests {
         sync_send::<ExtendedHttpFileDownloader>();
         sync_sync::<ExtendedHttpFileDownloader>();
     }
+
+    fn response(status: u16, content_range: Option<&str>) -> reqwest::Response {
+        let mut response = http::Response::builder().status(status);
+        if let Some(content_range) = content_range {
+            response = response.header(http::header::CONTENT_RANGE, content_range);
+        }
+        response.body("").unwrap().into()
+    }
+
+    fn archive_data(content_length: Option<u64>, remaining: &[(u64, u64)]) -> Box<DownloadArchiveData> {
+        archive_data_with_etag(content_length, remaining, None)
+    }
+
+    fn archive_data_with_etag(content_length: Option<u64>, remaining: &[(u64, u64)], etag: Option<&str>) -> Box<DownloadArchiveData> {
+        Box::new(DownloadArchiveData {
+            downloaded_len: 0,
+            downloading_duration: 0,
+            chunk_data: Some(ChunkData {
+                iter_count: 0,
+                remaining: RemainingChunks {
+                    chunk_size: 10,
+                    ranges: remaining.iter().map(|(start, end)| ChunkRange::new(*start, *end)).collect(),
+                },
+                last_incomplete_chunks: vec![],
+                chunk_hashes: vec![],
+            }),
+            content_length,
+            etag: etag.map(|n| n.to_string()),
+        })
+    }
+
+    #[tokio::test]
+    async fn resume_invalid_causes() {
+        let file_path = std::env::temp_dir().join(format!("http-downloader-resume-{}", std::process::id()));
+        tokio::fs::write(&file_path, [0u8; 50]).await.unwrap();
+        let resume_state = |archive_data, content_length| {
+            let file_path = file_path.clone();
+            async move { resume_archive_data(file_path, archive_data, content_length, Some("\"a\"")).await.unwrap().1 }
+        };
+
+        assert_eq!(resume_state(None, Some(100)).await, ResumeState::NotResumed);
+        // 已完成 0-49，文件长度正好足够
+        assert_eq!(resume_state(Some(archive_data(Some(100), &[(50, 99)])), Some(100)).await, ResumeState::Resumed);
+        assert_eq!(
+            resume_state(Some(archive_data(Some(100), &[(50, 99)])), None).await,
+            ResumeState::Restarted(ResumeInvalidCause::RangesNotSupported)
+        );
+        assert_eq!(
+            resume_state(Some(archive_data(Some(200), &[(50, 99)])), Some(100)).await,
+            ResumeState::Restarted(ResumeInvalidCause::ContentLengthMismatch { archived: 200, current: 100 })
+        );
+        // 旧存档没有记录文件大小，只能通过剩余范围发现不一致
+        assert_eq!(
+            resume_state(Some(archive_data(None, &[(50, 149)])), Some(100)).await,
+            ResumeState::Restarted(ResumeInvalidCause::ChunkRangeOutOfBounds)
+        );
+        assert_eq!(
+            resume_state(Some(archive_data(Some(100), &[(80, 99)])), Some(100)).await,
+            ResumeState::Restarted(ResumeInvalidCause::FileTooShort { file_len: 50, completed_end: 80 })
+        );
+        assert_eq!(
+            resume_state(Some(archive_data_with_etag(Some(100), &[(50, 99)], Some("\"a\""))), Some(100)).await,
+            ResumeState::Resumed
+        );
+        assert_eq!(
+            resume_state(Some(archive_data_with_etag(Some(100), &[(50, 99)], Some("\"b\""))), Some(100)).await,
+            ResumeState::Restarted(ResumeInvalidCause::EtagMismatch { archived: "\"b\"".to_string(), current: Some("\"a\"".to_string()) })
+        );
+
+        tokio::fs::remove_file(&file_path).await.unwrap();
+        assert_eq!(
+            resume_state(Some(archive_data(Some(100), &[(50, 99)])), Some(100)).await,
+            ResumeState::Restarted(ResumeInvalidCause::FileNotFound)
+        );
+    }
+
+    // 有拦截器时客户端不自动重定向，由下载器处理
+    struct NoopInterceptor;
+
+    impl RequestInterceptor for NoopInterceptor {
+        fn intercept_request(&self, _request: &mut reqwest::Request) {}
+    }
+
+    #[tokio::test]
+    async fn method_and_body_on_redirect() {
+        use crate::test_server::{TestResponse, TestServer};
+
+        for (status, method, redirected_method, body_kept) in [
+            (303, reqwest::Method::POST, "GET", false),
+            (302, reqwest::Method::POST, "GET", false),
+            (301, reqwest::Method::POST, "GET", false),
+            (307, reqwest::Method::POST, "POST", true),
+            (308, reqwest::Method::PUT, "PUT", true),
+            (302, reqwest::Method::PUT, "PUT", true),
+        ] {
+            let server = TestServer::start(move |request| match request.target.as_str() {
+                "/submit" => TestResponse::new(status).header("Location", "/result"),
+                _ => TestResponse::new(200).header("Accept-Ranges", "none").body("hello"),
+            }).await;
+            let save_dir = std::env::temp_dir().join(format!("http-downloader-redirect-{}-{}", std::process::id(), status));
+            let (mut downloader, _) = crate::HttpDownloaderBuilder::new(server.url("/submit"), save_dir.clone())
+                .file_name(Some("result".to_string()))
+                .method(method.clone())
+                .body("query")
+                .build(());
+            downloader.inner.request_interceptors.push(Arc::new(NoopInterceptor));
+            downloader.prepare_download().unwrap().await.unwrap();
+            std::fs::remove_dir_all(&save_dir).unwrap();
+
+            let requests = server.requests();
+            assert_eq!(requests.len(), 2);
+            assert_eq!((requests[0].method.as_str(), requests[0].body.as_slice()), (method.as_str(), b"query".as_slice()));
+            assert_eq!(requests[1].target, "/result");
+            assert_eq!(requests[1].method, redirected_method, "{} {}", status, method);
+            assert_eq!(!requests[1].body.is_empty(), body_kept, "{} {}", status, method);
+        }
+    }
+
+    #[tokio::test]
+    async fn redirect_scheme_is_restricted() {
+        use crate::test_server::{TestResponse, TestServer};
+
+        let secret_dir = std::env::temp_dir().join(format!("http-downloader-secret-{}", std::process::id()));
+        std::fs::create_dir_all(&secret_dir).unwrap();
+        let secret_path = secret_dir.join("secret");
+        std::fs::write(&secret_path, "secret").unwrap();
+        let secret_url = url::Url::from_file_path(&secret_path).unwrap().to_string();
+        for location in [secret_url.as_str(), "data:,secret", "ftp://127.0.0.1:1/secret"] {
+            let location = location.to_string();
+            let server = TestServer::start(move |_| TestResponse::new(302).header("Location", location.clone())).await;
+            let save_dir = secret_dir.join("download");
+            let (mut downloader, _) = crate::HttpDownloaderBuilder::new(server.url("/file"), save_dir.clone())
+                .file_name(Some("file".to_string()))
+                .build(());
+            downloader.inner.request_interceptors.push(Arc::new(NoopInterceptor));
+            let result = downloader.prepare_download().unwrap().await;
+            assert!(matches!(
+                result,
+                Err(DownloadError::HttpRequestResponseInvalid(HttpResponseInvalidCause::RedirectionSchemeNotAllowed, _))
+            ));
+            assert!(!save_dir.join("file").exists());
+        }
+
+        // 本地地址之间可以重定向，http(s) 地址始终可以
+        let file_url = url::Url::from_file_path(&secret_path).unwrap();
+        assert!(Redirection::is_allowed(&file_url, &file_url.join("other").unwrap()));
+        assert!(Redirection::is_allowed(&file_url, &"https://example.com/".parse().unwrap()));
+        assert!(!Redirection::is_allowed(&"http://example.com/".parse().unwrap(), &file_url));
+        std::fs::remove_dir_all(&secret_dir).unwrap();
+    }
+
+    #[test]
+    fn byte_range_probe_validation() {
+        let byte_range = ChunkRange::new(100, 199);
+        assert!(check_byte_range_response(byte_range, &response(206, Some("bytes 100-199/1000"))).is_ok());
+        // 资源比请求的范围短
+        assert!(check_byte_range_response(byte_range, &response(206, Some("bytes 100-149/150"))).is_ok());
+        assert!(matches!(
+            check_byte_range_response(byte_range, &response(200, None)),
+            Err(HttpResponseInvalidCause::ByteRangeNotSupported)
+        ));
+        for content_range in [None, Some("bytes 0-99/1000"), Some("bytes 100-299/1000"), Some("bytes */1000")] {
+            assert!(matches!(
+                check_byte_range_response(byte_range, &response(206, content_range)),
+                Err(HttpResponseInvalidCause::ContentRangeMismatch)
+            ));
+        }
+    }
+
+    #[test]
+    fn inverted_byte_range_is_rejected() {
+        let (start, end) = (10, 5);
+        let builder = crate::HttpDownloaderBuilder::new("http://localhost/a".parse().unwrap(), std::env::temp_dir()).byte_range(start..=end);
+        #[cfg(feature = "serde")]
+        assert!(matches!(
+            crate::HttpDownloaderBuilder::try_from(crate::DownloadSpec::from(&builder)),
+            Err(crate::DownloadSpecError::InvalidByteRange(10, 5))
+        ));
+        let (mut downloader, _) = builder.build(());
+        assert!(matches!(downloader.prepare_download(), Err(DownloadStartError::InvalidByteRange(10, 5))));
+    }
 }
\ No newline at end of file
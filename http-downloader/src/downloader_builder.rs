@@ -1,16 +1,20 @@
 use std::borrow::Cow;
+use std::net::{IpAddr, SocketAddr};
 use std::num::{NonZeroU8, NonZeroUsize};
+use std::ops::RangeInclusive;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
+use bytes::Bytes;
 use headers::{ETag, HeaderMap, HeaderMapExt};
 use tokio_util::sync::CancellationToken;
 use url::Url;
 
-use crate::{DownloadExtensionBuilder, ExtendedHttpFileDownloader, HttpFileDownloader};
+use crate::{ChunkHashVerify, ClientConfig, ClientIdentity, ProxyConfig, ChunkRange, DEFAULT_PROGRESS_MAP_PIECES, DownloadExtensionBuilder, ExtendedHttpFileDownloader, HostConnectionLimiter, HttpFileDownloader, RefreshedUrl, UrlRefresher};
 
-#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
 pub enum HttpRedirectionHandle {
     Invalid,
     RequestNewLocation {
@@ -39,6 +43,53 @@ pub struct HttpDownloadConfig {
     pub cancel_token: Option<CancellationToken>,
     pub handle_redirection: HttpRedirectionHandle,
     pub use_browser_user_agent: bool,
+    // 尾段竞速：没有剩余 chunk 时，空闲连接重复请求最慢 chunk 的未完成尾部
+    pub endgame: bool,
+    // 只下载远程资源中的这一段，文件中只保存这一段的内容
+    pub byte_range: Option<ChunkRange>,
+    // 顺序下载，总是优先下载位置最靠前的部分
+    pub sequential: bool,
+    // 断点续传时校验已下载数据的方式
+    pub chunk_hash_verify: ChunkHashVerify,
+    // 下载进度图的块数
+    pub progress_map_pieces: NonZeroUsize,
+    // 多个下载器共享的主机连接数限制
+    pub host_connection_limiter: Option<Arc<HostConnectionLimiter>>,
+    // 没有指定客户端时，用于创建客户端的配置
+    pub client_config: ClientConfig,
+    // 首个请求与 chunk 请求使用的请求方法，不是 GET 时服务器必须明确返回 `Accept-Ranges: bytes` 才会多连接下载
+    pub method: reqwest::Method,
+    // 首个请求与 chunk 请求都会携带的请求体
+    pub body: Option<Bytes>,
+    // 签名地址过期时获取新的地址
+    pub url_refresher: Option<Arc<dyn UrlRefresher>>,
+    // 刷新后的地址与请求头，之后的请求（包括重新开始下载）都使用它
+    pub refreshed_url: parking_lot::RwLock<Option<RefreshedUrl>>,
+    // 单连接下载时的解压方式
+    #[cfg(feature = "decompress")]
+    pub decompress_mode: crate::DecompressMode,
+}
+
+/// 首次请求经过重定向后的地址，chunk 请求也使用它
+#[derive(Debug, Clone)]
+pub(crate) struct Redirection {
+    pub location: String,
+    // 303，或者 POST 请求收到 301、302 之后改为不带请求体的 GET，之后的请求都保持 GET
+    pub use_get: bool,
+}
+
+impl Redirection {
+    pub(crate) fn new(previous: Option<&Redirection>, method: &reqwest::Method, status: reqwest::StatusCode, location: String) -> Self {
+        let use_get = previous.is_some_and(|n| n.use_get)
+            || (status == reqwest::StatusCode::SEE_OTHER && method != reqwest::Method::HEAD)
+            || (matches!(status, reqwest::StatusCode::MOVED_PERMANENTLY | reqwest::StatusCode::FOUND) && method == reqwest::Method::POST);
+        Self { location, use_get }
+    }
+
+    /// 重定向只能去往 http(s) 地址，`file:`、`data:` 等本地地址只有原地址也是同一协议时才允许
+    pub(crate) fn is_allowed(original_url: &Url, location_url: &Url) -> bool {
+        matches!(location_url.scheme(), "http" | "https") || location_url.scheme() == original_url.scheme()
+    }
 }
 
 impl HttpDownloadConfig {
@@ -47,21 +98,61 @@ impl HttpDownloadConfig {
         self.save_dir.join(&self.file_name)
     }
 
-    pub(crate) fn create_http_request(&self, redirection_location: Option<&str>) -> reqwest::Request {
-        let mut url = (*self.url).clone().clone();
-        if let Some(location) = redirection_location {
-            url.set_path(location);
+    /// 请求地址，刷新后的地址替代原地址，再按重定向的 Location 解析
+    pub(crate) fn request_url(&self, redirection: Option<&Redirection>) -> Url {
+        self.resolve_url(self.refreshed_url.read().as_ref(), redirection)
+    }
+
+    fn resolve_url(&self, refreshed_url: Option<&RefreshedUrl>, redirection: Option<&Redirection>) -> Url {
+        let mut url = match refreshed_url {
+            None => (*self.url).clone(),
+            Some(refreshed_url) => refreshed_url.url.clone(),
+        };
+        if let Some(redirection) = redirection {
+            // Location 可以是绝对地址，也可以是相对地址
+            match url.join(&redirection.location) {
+                Ok(location) => url = location,
+                Err(_) => url.set_path(&redirection.location),
+            }
         }
-        let mut request = reqwest::Request::new(reqwest::Method::GET, url);
+        url
+    }
+
+    pub(crate) fn create_http_request(&self, redirection: Option<&Redirection>) -> reqwest::Request {
+        let refreshed_url = self.refreshed_url.read().clone();
+        let url = self.resolve_url(refreshed_url.as_ref(), redirection);
+        let mut request = match redirection {
+            Some(redirection) if redirection.use_get => reqwest::Request::new(reqwest::Method::GET, url),
+            _ => {
+                let mut request = reqwest::Request::new(self.method.clone(), url);
+                if let Some(body) = self.body.as_ref() {
+                    *request.body_mut() = Some(body.clone().into());
+                }
+                request
+            }
+        };
         let header_map = request.headers_mut();
         if self.use_browser_user_agent {
             header_map.insert(reqwest::header::USER_AGENT, headers::HeaderValue::from_str("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/112.0.0.0 Safari/537.36 Edg/112.0.1722.48").unwrap());
         }
         header_map.insert(reqwest::header::ACCEPT, headers::HeaderValue::from_str("*/*").unwrap());
+        // 响应被压缩时长度与 Range 偏移都对应压缩后的数据，没有开启解压时只接受原始数据
+        #[cfg(feature = "decompress")]
+        header_map.insert(reqwest::header::ACCEPT_ENCODING, headers::HeaderValue::from_static(self.decompress_mode.accept_encoding()));
+        #[cfg(not(feature = "decompress"))]
+        header_map.insert(reqwest::header::ACCEPT_ENCODING, headers::HeaderValue::from_static("identity"));
         header_map.typed_insert(headers::Connection::keep_alive());
         for (header_name, header_value) in self.header_map.iter() {
             header_map.insert(header_name, header_value.clone());
         }
+        if let Some(refreshed_url) = refreshed_url.as_ref() {
+            for (header_name, header_value) in refreshed_url.header_map.iter() {
+                header_map.insert(header_name, header_value.clone());
+            }
+        }
+        if let Some(byte_range) = self.byte_range.as_ref() {
+            header_map.typed_insert(byte_range.to_range_header());
+        }
         // 限速后超时会出现异常?
         *request.timeout_mut() = None;
         // *request.timeout_mut() = self.config.timeout;
@@ -95,6 +186,18 @@ pub struct HttpDownloaderBuilder {
     cancel_token: Option<CancellationToken>,
     handle_redirection: HttpRedirectionHandle,
     use_browser_user_agent: bool,
+    endgame: bool,
+    byte_range: Option<ChunkRange>,
+    sequential: bool,
+    chunk_hash_verify: ChunkHashVerify,
+    progress_map_pieces: NonZeroUsize,
+    host_connection_limiter: Option<Arc<HostConnectionLimiter>>,
+    client_config: ClientConfig,
+    method: reqwest::Method,
+    body: Option<Bytes>,
+    url_refresher: Option<Arc<dyn UrlRefresher>>,
+    #[cfg(feature = "decompress")]
+    decompress_mode: crate::DecompressMode,
 }
 
 impl HttpDownloaderBuilder {
@@ -124,6 +227,18 @@ impl HttpDownloaderBuilder {
                 max_times: 8
             },
             use_browser_user_agent: true,
+            endgame: false,
+            byte_range: None,
+            sequential: false,
+            chunk_hash_verify: ChunkHashVerify::Disabled,
+            progress_map_pieces: NonZeroUsize::new(DEFAULT_PROGRESS_MAP_PIECES).unwrap(),
+            host_connection_limiter: None,
+            client_config: Default::default(),
+            method: reqwest::Method::GET,
+            body: None,
+            url_refresher: None,
+            #[cfg(feature = "decompress")]
+            decompress_mode: Default::default(),
         }
     }
 
@@ -132,6 +247,60 @@ impl HttpDownloaderBuilder {
         self
     }
 
+    /// HTTP 客户端配置，通过 `client` 指定了客户端时不生效
+    pub fn client_config(mut self, client_config: ClientConfig) -> Self {
+        self.client_config = client_config;
+        self
+    }
+
+    /// 添加代理，可多次调用
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.client_config.proxies.push(proxy);
+        self
+    }
+
+    /// 不使用系统代理（环境变量）
+    pub fn no_system_proxy(mut self, no_system_proxy: bool) -> Self {
+        self.client_config.no_system_proxy = no_system_proxy;
+        self
+    }
+
+    /// 额外信任的根证书文件，PEM 或 DER 格式，可多次调用
+    pub fn root_certificate(mut self, path: PathBuf) -> Self {
+        self.client_config.root_certificates.push(path);
+        self
+    }
+
+    /// 客户端证书
+    pub fn client_identity(mut self, identity: ClientIdentity) -> Self {
+        self.client_config.identity = Some(identity);
+        self
+    }
+
+    /// 不校验服务器证书，有安全风险，只应在测试或内网环境中使用；证书不被校验时其中的主机名也就没有意义
+    pub fn danger_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+        self.client_config.danger_accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    /// 证书有效但不校验其中的主机名，有安全风险，只应在测试或内网环境中使用
+    pub fn danger_accept_invalid_hostnames(mut self, accept_invalid_hostnames: bool) -> Self {
+        self.client_config.danger_accept_invalid_hostnames = accept_invalid_hostnames;
+        self
+    }
+
+    /// 发起连接使用的本地地址，用于选择网卡
+    pub fn local_address(mut self, local_address: IpAddr) -> Self {
+        self.client_config.local_address = Some(local_address);
+        self
+    }
+
+    /// 将 `domain` 解析到指定的地址，端口会被忽略，使用 URL 中的端口
+    pub fn resolve(mut self, domain: impl Into<String>, addrs: Vec<SocketAddr>) -> Self {
+        self.client_config.resolve.insert(domain.into(), addrs);
+        self
+    }
+
     /// 当目录不存在时，是否创建它
     pub fn create_dir(mut self, create_dir: bool) -> Self {
         self.create_dir = create_dir;
@@ -160,6 +329,31 @@ impl HttpDownloaderBuilder {
         self
     }
 
+    /// 请求方法，默认 GET，不是 GET 时只有服务器明确返回 `Accept-Ranges: bytes` 才会多连接下载
+    pub fn method(mut self, method: reqwest::Method) -> Self {
+        self.method = method;
+        self
+    }
+
+    /// 请求体，首个请求与每个 chunk 请求都会携带，需要自行通过 `header_map` 设置 Content-Type
+    pub fn body(mut self, body: impl Into<Bytes>) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// 签名地址过期（请求返回 401、403、410）时调用，用新的地址与请求头继续下载
+    pub fn url_refresher(mut self, url_refresher: Arc<dyn UrlRefresher>) -> Self {
+        self.url_refresher = Some(url_refresher);
+        self
+    }
+
+    /// 解压 gzip、br、zstd 编码的响应或 .gz 文件，需要解压时只使用单连接下载，默认不解压
+    #[cfg(feature = "decompress")]
+    pub fn decompress_mode(mut self, decompress_mode: crate::DecompressMode) -> Self {
+        self.decompress_mode = decompress_mode;
+        self
+    }
+
     /// 请求头自定义
     pub fn header_map(mut self, header_map: HeaderMap) -> Self {
         self.header_map = header_map;
@@ -216,6 +410,44 @@ impl HttpDownloaderBuilder {
         self
     }
 
+    /// 尾段竞速，没有剩余 chunk 时，空闲连接会重复请求最慢 chunk 的未完成尾部，先完成者胜出
+    pub fn endgame(mut self, endgame: bool) -> Self {
+        self.endgame = endgame;
+        self
+    }
+
+    /// 只下载远程资源中的一段（包含两端），多连接与断点续传都只作用于这一段，文件中只保存这一段的内容
+    ///
+    /// 起始位置大于结束位置时，开始下载返回 `DownloadStartError::InvalidByteRange`
+    pub fn byte_range(mut self, byte_range: RangeInclusive<u64>) -> Self {
+        self.byte_range = Some(ChunkRange { start: *byte_range.start(), end: *byte_range.end() });
+        self
+    }
+
+    /// 顺序下载，总是优先下载位置最靠前的部分，配合 `sequential_reader` 可以边下载边按顺序读取
+    pub fn sequential(mut self, sequential: bool) -> Self {
+        self.sequential = sequential;
+        self
+    }
+
+    /// 断点续传时校验已下载数据的方式，校验不通过的部分会重新下载，默认不校验
+    pub fn chunk_hash_verify(mut self, chunk_hash_verify: ChunkHashVerify) -> Self {
+        self.chunk_hash_verify = chunk_hash_verify;
+        self
+    }
+
+    /// 下载进度图（`ProgressMap`）将文件划分为多少块，默认 256
+    pub fn progress_map_pieces(mut self, progress_map_pieces: NonZeroUsize) -> Self {
+        self.progress_map_pieces = progress_map_pieces;
+        self
+    }
+
+    /// 与其他下载器共享的主机连接数限制，chunk 请求前需要先获取许可
+    pub fn host_connection_limiter(mut self, host_connection_limiter: Arc<HostConnectionLimiter>) -> Self {
+        self.host_connection_limiter = Some(host_connection_limiter);
+        self
+    }
+
     /// 下载连接数
     pub fn download_connection_count(mut self, download_connection_count: NonZeroU8) -> Self {
         self.download_connection_count = download_connection_count;
@@ -236,15 +468,22 @@ impl HttpDownloaderBuilder {
         self,
         extension_builder: DEB,
     ) -> (ExtendedHttpFileDownloader, DEB::ExtensionState) {
-        let mut downloader = HttpFileDownloader::new(
-            self.client.unwrap_or(Default::default()),
+        let file_name = self.file_name.unwrap_or_else(|| {
+            let file_name = self.url.file_name().to_string();
+            // 保存解压后的 .gz 文件时去掉扩展名
+            #[cfg(feature = "decompress")]
+            if self.decompress_mode.is_gzip_file(&self.url) && file_name.len() > 3 && file_name.to_ascii_lowercase().ends_with(".gz") {
+                return file_name[..file_name.len() - 3].to_string();
+            }
+            file_name
+        });
+        let mut downloader = HttpFileDownloader::with_client(
+            self.client,
             Arc::new(HttpDownloadConfig {
                 set_len_in_advance: self.set_len_in_advance,
                 download_connection_count: self.download_connection_count,
                 chunk_size: self.chunk_size,
-                file_name: self
-                    .file_name
-                    .unwrap_or_else(|| self.url.file_name().to_string()),
+                file_name,
                 open_option: self.open_option,
                 create_dir: self.create_dir,
                 url: Arc::new(self.url),
@@ -259,7 +498,20 @@ impl HttpDownloaderBuilder {
                 http_request_configure: self.http_request_configure,
                 cancel_token: self.cancel_token,
                 handle_redirection: self.handle_redirection,
-                use_browser_user_agent: true,
+                use_browser_user_agent: self.use_browser_user_agent,
+                endgame: self.endgame,
+                byte_range: self.byte_range,
+                sequential: self.sequential,
+                chunk_hash_verify: self.chunk_hash_verify,
+                progress_map_pieces: self.progress_map_pieces,
+                host_connection_limiter: self.host_connection_limiter,
+                client_config: self.client_config,
+                method: self.method,
+                body: self.body,
+                url_refresher: self.url_refresher,
+                refreshed_url: Default::default(),
+                #[cfg(feature = "decompress")]
+                decompress_mode: self.decompress_mode,
             }),
         );
         let (extension, es) = extension_builder.build(&mut downloader);
@@ -267,6 +519,107 @@ impl HttpDownloaderBuilder {
     }
 }
 
+#[cfg(feature = "serde")]
+impl From<&HttpDownloaderBuilder> for crate::DownloadSpec {
+    fn from(builder: &HttpDownloaderBuilder) -> Self {
+        let etag = builder.etag.as_ref().and_then(|etag| {
+            let mut values = Vec::new();
+            headers::Header::encode(etag, &mut values);
+            values.first().and_then(|n| n.to_str().ok()).map(|n| n.to_string())
+        });
+        Self {
+            schema_version: crate::DOWNLOAD_SPEC_SCHEMA_VERSION,
+            url: builder.url.clone(),
+            save_dir: builder.save_dir.clone(),
+            file_name: builder.file_name.clone(),
+            chunk_size: builder.chunk_size,
+            download_connection_count: builder.download_connection_count,
+            set_len_in_advance: builder.set_len_in_advance,
+            create_dir: builder.create_dir,
+            request_retry_count: builder.request_retry_count,
+            etag,
+            headers: builder.header_map.iter()
+                .map(|(name, value)| (name.to_string(), String::from_utf8_lossy(value.as_bytes()).to_string()))
+                .collect(),
+            downloaded_len_send_interval: builder.downloaded_len_send_interval,
+            chunks_send_interval: builder.chunks_send_interval,
+            strict_check_accept_ranges: builder.strict_check_accept_ranges,
+            handle_redirection: builder.handle_redirection.clone(),
+            use_browser_user_agent: builder.use_browser_user_agent,
+            endgame: builder.endgame,
+            byte_range: builder.byte_range,
+            sequential: builder.sequential,
+            chunk_hash_verify: builder.chunk_hash_verify,
+            progress_map_pieces: builder.progress_map_pieces,
+            client_config: builder.client_config.clone(),
+            method: builder.method.to_string(),
+            body: builder.body.as_ref().map(|n| n.to_vec()),
+            #[cfg(feature = "decompress")]
+            decompress_mode: builder.decompress_mode,
+        }
+    }
+}
+
+/// 其他选项为默认值，运行时对象需要重新设置
+#[cfg(feature = "serde")]
+impl TryFrom<crate::DownloadSpec> for HttpDownloaderBuilder {
+    type Error = crate::DownloadSpecError;
+
+    fn try_from(spec: crate::DownloadSpec) -> Result<Self, Self::Error> {
+        use crate::DownloadSpecError;
+
+        if spec.schema_version == 0 || spec.schema_version > crate::DOWNLOAD_SPEC_SCHEMA_VERSION {
+            return Err(DownloadSpecError::UnsupportedSchemaVersion(spec.schema_version));
+        }
+        let method = reqwest::Method::from_bytes(spec.method.as_bytes())
+            .map_err(|_| DownloadSpecError::InvalidMethod(spec.method.clone()))?;
+        let etag = match spec.etag {
+            None => None,
+            Some(etag) => Some(etag.parse::<ETag>().map_err(|_| DownloadSpecError::InvalidEtag(etag))?),
+        };
+        if let Some(byte_range) = spec.byte_range {
+            if byte_range.start > byte_range.end {
+                return Err(DownloadSpecError::InvalidByteRange(byte_range.start, byte_range.end));
+            }
+        }
+        let mut header_map = HeaderMap::new();
+        for (name, value) in spec.headers {
+            let header_name = headers::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| DownloadSpecError::InvalidHeader(name.clone()))?;
+            let header_value = headers::HeaderValue::from_bytes(value.as_bytes())
+                .map_err(|_| DownloadSpecError::InvalidHeader(name))?;
+            header_map.append(header_name, header_value);
+        }
+        let mut builder = HttpDownloaderBuilder::new(spec.url, spec.save_dir);
+        builder.file_name = spec.file_name;
+        builder.chunk_size = spec.chunk_size;
+        builder.download_connection_count = spec.download_connection_count;
+        builder.set_len_in_advance = spec.set_len_in_advance;
+        builder.create_dir = spec.create_dir;
+        builder.request_retry_count = spec.request_retry_count;
+        builder.etag = etag;
+        builder.header_map = header_map;
+        builder.downloaded_len_send_interval = spec.downloaded_len_send_interval;
+        builder.chunks_send_interval = spec.chunks_send_interval;
+        builder.strict_check_accept_ranges = spec.strict_check_accept_ranges;
+        builder.handle_redirection = spec.handle_redirection;
+        builder.use_browser_user_agent = spec.use_browser_user_agent;
+        builder.endgame = spec.endgame;
+        builder.byte_range = spec.byte_range;
+        builder.sequential = spec.sequential;
+        builder.chunk_hash_verify = spec.chunk_hash_verify;
+        builder.progress_map_pieces = spec.progress_map_pieces;
+        builder.client_config = spec.client_config;
+        builder.method = method;
+        builder.body = spec.body.map(Bytes::from);
+        #[cfg(feature = "decompress")]
+        {
+            builder.decompress_mode = spec.decompress_mode;
+        }
+        Ok(builder)
+    }
+}
+
 pub trait UrlFileName {
     fn file_name(&self) -> Cow<str>;
 }
@@ -274,6 +627,10 @@ pub trait UrlFileName {
 impl UrlFileName for Url {
     fn file_name(&self) -> Cow<str> {
         let website_default: &'static str = "index.html";
+        // data: 地址没有路径
+        if self.scheme() == "data" {
+            return Cow::Borrowed("data");
+        }
         self.path_segments()
             .map(|n| {
                 n.last()
@@ -0,0 +1,595 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use reqwest::header::{HeaderValue, AUTHORIZATION, COOKIE, SET_COOKIE, WWW_AUTHENTICATE};
+use sha2::Digest;
+use url::Url;
+
+use crate::{DownloaderWrapper, DownloadExtensionBuilder, HttpFileDownloader, RequestInterceptor};
+
+/// HTTP 认证方式
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum HttpAuth {
+    Basic { username: String, password: String },
+    Bearer(String),
+    /// 收到服务器的质询后才会携带认证信息，支持 MD5、MD5-sess、SHA-256、SHA-256-sess
+    Digest { username: String, password: String },
+}
+
+#[derive(Debug, Clone)]
+struct DigestChallenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    // 服务器支持 auth 时为 Some("auth")
+    qop: Option<String>,
+    algorithm: String,
+    sha256: bool,
+    sess: bool,
+}
+
+impl DigestChallenge {
+    fn parse(header: &str) -> Option<(Self, bool)> {
+        let (scheme, params) = header.trim().split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("digest") {
+            return None;
+        }
+        let params = parse_auth_params(params);
+        let algorithm = params.get("algorithm").cloned().unwrap_or_else(|| "MD5".to_string());
+        let (sha256, sess) = match algorithm.to_ascii_uppercase().as_str() {
+            "MD5" => (false, false),
+            "MD5-SESS" => (false, true),
+            "SHA-256" => (true, false),
+            "SHA-256-SESS" => (true, true),
+            _ => return None,
+        };
+        let qop = params.get("qop").and_then(|qop| {
+            qop.split(',')
+                .map(str::trim)
+                .find(|n| n.eq_ignore_ascii_case("auth"))
+                .map(|_| "auth".to_string())
+        });
+        // 服务器指定了 qop 但不支持 auth（只支持 auth-int）
+        if params.contains_key("qop") && qop.is_none() {
+            return None;
+        }
+        let stale = params.get("stale").is_some_and(|n| n.eq_ignore_ascii_case("true"));
+        Some((
+            Self {
+                realm: params.get("realm").cloned().unwrap_or_default(),
+                nonce: params.get("nonce")?.clone(),
+                opaque: params.get("opaque").cloned(),
+                qop,
+                algorithm,
+                sha256,
+                sess,
+            },
+            stale,
+        ))
+    }
+
+    fn hash(&self, data: &str) -> String {
+        if self.sha256 {
+            to_hex(&sha2::Sha256::digest(data.as_bytes()))
+        } else {
+            to_hex(&md5::Md5::digest(data.as_bytes()))
+        }
+    }
+
+    fn authorization(&self, username: &str, password: &str, method: &str, uri: &str, nonce_count: u32, cnonce: &str) -> String {
+        let mut ha1 = self.hash(&format!("{}:{}:{}", username, self.realm, password));
+        if self.sess {
+            ha1 = self.hash(&format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        let ha2 = self.hash(&format!("{}:{}", method, uri));
+        let nc = format!("{:08x}", nonce_count);
+        let response = match self.qop.as_ref() {
+            Some(qop) => self.hash(&format!("{}:{}:{}:{}:{}:{}", ha1, self.nonce, nc, cnonce, qop, ha2)),
+            None => self.hash(&format!("{}:{}:{}", ha1, self.nonce, ha2)),
+        };
+        let mut authorization = format!(
+            r#"Digest username="{}", realm="{}", nonce="{}", uri="{}", algorithm={}, response="{}""#,
+            username, self.realm, self.nonce, uri, self.algorithm, response
+        );
+        if let Some(qop) = self.qop.as_ref() {
+            authorization.push_str(&format!(r#", qop={}, nc={}, cnonce="{}""#, qop, nc, cnonce));
+        }
+        if let Some(opaque) = self.opaque.as_ref() {
+            authorization.push_str(&format!(r#", opaque="{}""#, opaque));
+        }
+        authorization
+    }
+}
+
+/// 解析 `key=value, key="quoted, value"` 形式的认证参数，参数名转为小写
+fn parse_auth_params(params: &str) -> HashMap<String, String> {
+    let mut result = HashMap::new();
+    let mut chars = params.chars().peekable();
+    loop {
+        while chars.next_if(|n| n.is_whitespace() || *n == ',').is_some() {}
+        let key: String = std::iter::from_fn(|| chars.next_if(|n| *n != '=' && *n != ',')).collect();
+        if key.is_empty() && chars.peek().is_none() {
+            break;
+        }
+        let mut value = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            while chars.next_if(|n| n.is_whitespace()).is_some() {}
+            if chars.next_if_eq(&'"').is_some() {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => value.extend(chars.next()),
+                        '"' => break,
+                        c => value.push(c),
+                    }
+                }
+            } else {
+                value = std::iter::from_fn(|| chars.next_if(|n| *n != ',')).collect();
+            }
+        }
+        result.insert(key.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+    result
+}
+
+struct AuthInterceptor {
+    auth: HttpAuth,
+    // 只向下载地址所在的源（协议、主机、端口）发送认证信息，重定向到其他主机时不携带
+    origin: url::Origin,
+    digest_challenge: parking_lot::Mutex<Option<DigestChallenge>>,
+    nonce_count: AtomicU32,
+}
+
+impl RequestInterceptor for AuthInterceptor {
+    fn intercept_request(&self, request: &mut reqwest::Request) {
+        if request.headers().contains_key(AUTHORIZATION) || request.url().origin() != self.origin {
+            return;
+        }
+        let authorization = match &self.auth {
+            HttpAuth::Basic { username, password } => format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password))
+            ),
+            HttpAuth::Bearer(token) => format!("Bearer {}", token),
+            HttpAuth::Digest { username, password } => {
+                let Some(challenge) = self.digest_challenge.lock().clone() else {
+                    return;
+                };
+                let url = request.url();
+                let uri = match url.query() {
+                    None => url.path().to_string(),
+                    Some(query) => format!("{}?{}", url.path(), query),
+                };
+                let nonce_count = self.nonce_count.fetch_add(1, Ordering::SeqCst) + 1;
+                let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+                let cnonce = to_hex(&md5::Md5::digest(format!("{}:{}", time, nonce_count).as_bytes()))[..16].to_string();
+                challenge.authorization(username, password, request.method().as_str(), &uri, nonce_count, &cnonce)
+            }
+        };
+        if let Ok(authorization) = HeaderValue::from_str(&authorization) {
+            request.headers_mut().insert(AUTHORIZATION, authorization);
+        }
+    }
+
+    fn intercept_response(&self, response: &reqwest::Response) -> bool {
+        if !matches!(self.auth, HttpAuth::Digest { .. })
+            || response.status() != reqwest::StatusCode::UNAUTHORIZED
+            || response.url().origin() != self.origin {
+            return false;
+        }
+        let Some((challenge, stale)) = response
+            .headers()
+            .get_all(WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|n| n.to_str().ok())
+            .find_map(DigestChallenge::parse) else {
+            return false;
+        };
+        let mut current = self.digest_challenge.lock();
+        // 同一个 nonce 再次被拒绝说明用户名或密码错误，不再重试
+        let retry = stale || current.as_ref().is_none_or(|n| n.nonce != challenge.nonce);
+        if retry {
+            *current = Some(challenge);
+            self.nonce_count.store(0, Ordering::SeqCst);
+        }
+        retry
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    // 不以 `.` 开头
+    pub domain: String,
+    // 是否也发送给子域名
+    pub include_subdomains: bool,
+    pub path: String,
+    pub secure: bool,
+    // None 表示会话 Cookie
+    pub expires: Option<SystemTime>,
+}
+
+impl Cookie {
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|n| n <= now)
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+        let domain_matched = host == self.domain
+            || (self.include_subdomains && host.strip_suffix(&self.domain).is_some_and(|n| n.ends_with('.')));
+        let path = url.path();
+        let path_matched = path == self.path
+            || (path.starts_with(&self.path)
+            && (self.path.ends_with('/') || path[self.path.len()..].starts_with('/')));
+        domain_matched && path_matched && (!self.secure || url.scheme() == "https")
+    }
+}
+
+/// Cookie 容器，请求时携带匹配的 Cookie，并保存首个请求、重定向与 chunk 响应中的 `Set-Cookie`
+///
+/// 通过 `HttpDownloaderBuilder::client` 指定客户端时，需要关闭客户端的自动重定向才能保存重定向响应中的 Cookie
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    cookies: parking_lot::RwLock<Vec<Cookie>>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 读取 Netscape 格式（浏览器扩展导出的 cookies.txt）的 Cookie 文件
+    pub fn load_netscape(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::parse_netscape(&std::fs::read_to_string(path)?))
+    }
+
+    /// 解析 Netscape 格式的 Cookie，忽略格式不正确的行
+    pub fn parse_netscape(content: &str) -> Self {
+        let jar = Self::new();
+        for line in content.lines() {
+            let line = match line.strip_prefix("#HttpOnly_") {
+                Some(line) => line,
+                None if line.starts_with('#') => continue,
+                None => line,
+            };
+            let fields: Vec<&str> = line.trim_end_matches('\r').split('\t').collect();
+            let [domain, include_subdomains, path, secure, expires, name, value] = fields[..] else {
+                continue;
+            };
+            let Ok(expires) = expires.parse::<u64>() else {
+                continue;
+            };
+            jar.insert(Cookie {
+                name: name.to_string(),
+                value: value.to_string(),
+                domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+                include_subdomains: include_subdomains.eq_ignore_ascii_case("TRUE"),
+                path: path.to_string(),
+                secure: secure.eq_ignore_ascii_case("TRUE"),
+                expires: (expires != 0).then(|| UNIX_EPOCH + Duration::from_secs(expires)),
+            });
+        }
+        jar
+    }
+
+    /// 导出为 Netscape 格式，可用于持久化
+    pub fn to_netscape(&self) -> String {
+        let mut content = String::from("# Netscape HTTP Cookie File\n");
+        for cookie in self.cookies.read().iter() {
+            let expires = cookie
+                .expires
+                .map(|n| n.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs())
+                .unwrap_or(0);
+            content.push_str(&format!(
+                "{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                if cookie.include_subdomains { "." } else { "" },
+                cookie.domain,
+                if cookie.include_subdomains { "TRUE" } else { "FALSE" },
+                cookie.path,
+                if cookie.secure { "TRUE" } else { "FALSE" },
+                expires,
+                cookie.name,
+                cookie.value
+            ));
+        }
+        content
+    }
+
+    /// 添加 Cookie，替换名称、域名、路径相同的 Cookie
+    pub fn insert(&self, cookie: Cookie) {
+        let mut cookies = self.cookies.write();
+        cookies.retain(|n| !(n.name == cookie.name && n.domain == cookie.domain && n.path == cookie.path));
+        cookies.push(cookie);
+    }
+
+    pub fn cookies(&self) -> Vec<Cookie> {
+        self.cookies.read().clone()
+    }
+
+    /// 处理 `url` 响应中的一个 `Set-Cookie` 头
+    pub fn set_cookie(&self, url: &Url, set_cookie: &str) {
+        let Some(host) = url.host_str().map(|n| n.to_ascii_lowercase()) else {
+            return;
+        };
+        let mut parts = set_cookie.split(';');
+        let Some((name, value)) = parts.next().and_then(|n| n.split_once('=')) else {
+            return;
+        };
+        let mut cookie = Cookie {
+            name: name.trim().to_string(),
+            value: value.trim().trim_matches('"').to_string(),
+            domain: host.clone(),
+            include_subdomains: false,
+            path: default_cookie_path(url.path()),
+            secure: false,
+            expires: None,
+        };
+        let mut max_age = None;
+        for attribute in parts {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "domain" if !value.is_empty() => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    // 只接受当前主机或其上级域名
+                    if host != domain && !host.ends_with(&format!(".{}", domain)) {
+                        return;
+                    }
+                    // 不能设置给公共后缀，否则会发送给其下所有网站
+                    if host != domain && is_public_suffix(&domain) {
+                        return;
+                    }
+                    cookie.domain = domain;
+                    cookie.include_subdomains = true;
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                "max-age" => max_age = value.parse::<i64>().ok(),
+                "expires" => cookie.expires = httpdate::parse_http_date(value).ok(),
+                _ => {}
+            }
+        }
+        // Max-Age 优先于 Expires
+        if let Some(max_age) = max_age {
+            cookie.expires = Some(if max_age <= 0 {
+                UNIX_EPOCH
+            } else {
+                SystemTime::now() + Duration::from_secs(max_age as u64)
+            });
+        }
+        if cookie.is_expired(SystemTime::now()) {
+            self.cookies
+                .write()
+                .retain(|n| !(n.name == cookie.name && n.domain == cookie.domain && n.path == cookie.path));
+        } else {
+            self.insert(cookie);
+        }
+    }
+
+    /// 发送给 `url` 的 `Cookie` 请求头，没有匹配的 Cookie 时返回 None
+    pub fn cookie_header(&self, url: &Url) -> Option<String> {
+        let now = SystemTime::now();
+        let mut cookies: Vec<Cookie> = self
+            .cookies
+            .read()
+            .iter()
+            .filter(|n| !n.is_expired(now) && n.matches(url))
+            .cloned()
+            .collect();
+        if cookies.is_empty() {
+            return None;
+        }
+        // 路径更长的 Cookie 排在前面
+        cookies.sort_by_key(|n| std::cmp::Reverse(n.path.len()));
+        Some(
+            cookies
+                .iter()
+                .map(|n| format!("{}={}", n.name, n.value))
+                .collect::<Vec<_>>()
+                .join("; "),
+        )
+    }
+}
+
+/// 没有内置公共后缀列表，拒绝顶级域名与常见的二级公共后缀（`co.uk`、`com.cn` 等）
+fn is_public_suffix(domain: &str) -> bool {
+    const SECOND_LEVEL: [&str; 10] = ["ac", "co", "com", "edu", "gov", "ltd", "mil", "net", "org", "plc"];
+    let labels: Vec<&str> = domain.split('.').collect();
+    match labels[..] {
+        [_] => true,
+        [second, top] => top.len() == 2 && SECOND_LEVEL.contains(&second),
+        _ => false,
+    }
+}
+
+fn default_cookie_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(index) => path[..index].to_string(),
+    }
+}
+
+impl RequestInterceptor for CookieJar {
+    fn intercept_request(&self, request: &mut reqwest::Request) {
+        let Some(cookie) = self.cookie_header(request.url()) else {
+            return;
+        };
+        // 保留通过 header_map 设置的 Cookie
+        let cookie = match request.headers().get(COOKIE).and_then(|n| n.to_str().ok()) {
+            Some(original) if !original.is_empty() => format!("{}; {}", original, cookie),
+            _ => cookie,
+        };
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            request.headers_mut().insert(COOKIE, cookie);
+        }
+    }
+
+    fn intercept_response(&self, response: &reqwest::Response) -> bool {
+        for set_cookie in response.headers().get_all(SET_COOKIE).iter() {
+            if let Ok(set_cookie) = set_cookie.to_str() {
+                self.set_cookie(response.url(), set_cookie);
+            }
+        }
+        false
+    }
+}
+
+/// HTTP 认证与 Cookie，作用于首个请求、重定向请求与所有 chunk 请求
+///
+/// 认证信息只发送给下载地址所在的源，Cookie 按域名匹配
+pub struct DownloadAuthExtension {
+    pub auth: Option<HttpAuth>,
+    pub cookie_jar: Option<Arc<CookieJar>>,
+}
+
+impl DownloadAuthExtension {
+    pub fn new(auth: Option<HttpAuth>, cookie_jar: Option<Arc<CookieJar>>) -> Self {
+        Self {
+            auth,
+            cookie_jar,
+        }
+    }
+}
+
+pub struct DownloadAuthState {
+    // 下载过程中收到的 Cookie 也会保存在这里，可以用 `to_netscape` 持久化
+    pub cookie_jar: Option<Arc<CookieJar>>,
+}
+
+pub struct DownloadAuthDownloaderWrapper;
+
+impl DownloadExtensionBuilder for DownloadAuthExtension {
+    type Wrapper = DownloadAuthDownloaderWrapper;
+    type ExtensionState = DownloadAuthState;
+
+    fn build(self, downloader: &mut HttpFileDownloader) -> (Self::Wrapper, Self::ExtensionState) where Self: Sized {
+        if let Some(cookie_jar) = self.cookie_jar.clone() {
+            downloader.request_interceptors.push(cookie_jar);
+        }
+        if let Some(auth) = self.auth {
+            downloader.request_interceptors.push(Arc::new(AuthInterceptor {
+                auth,
+                origin: downloader.config.url.origin(),
+                digest_challenge: Default::default(),
+                nonce_count: AtomicU32::new(0),
+            }));
+        }
+        (
+            DownloadAuthDownloaderWrapper,
+            DownloadAuthState {
+                cookie_jar: self.cookie_jar,
+            },
+        )
+    }
+}
+
+impl DownloaderWrapper for DownloadAuthDownloaderWrapper {}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|n| format!("{:02x}", n)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::HttpDownloaderBuilder;
+    use crate::test_server::{TestResponse, TestServer};
+
+    use super::*;
+
+    #[test]
+    fn digest_response() {
+        // RFC 2617 3.5
+        let (challenge, stale) = DigestChallenge::parse(
+            r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+        )
+            .unwrap();
+        assert!(!stale);
+        let authorization = challenge.authorization("Mufasa", "Circle Of Life", "GET", "/dir/index.html", 1, "0a4f113b");
+        assert!(authorization.contains(r#"response="6629fae49393a05397450978507c4ef1""#));
+        assert!(authorization.contains(r#"nc=00000001, cnonce="0a4f113b""#));
+        assert!(authorization.contains(r#"opaque="5ccc069c403ebaf9f0171e9517f40e41""#));
+    }
+
+    #[test]
+    fn cookie_jar() {
+        let jar = CookieJar::parse_netscape(
+            "# Netscape HTTP Cookie File\n\
+            .example.com\tTRUE\t/\tFALSE\t0\tsession\tabc\n\
+            #HttpOnly_files.example.com\tFALSE\t/dl\tTRUE\t0\ttoken\txyz\n\
+            invalid line\n",
+        );
+        assert_eq!(jar.cookies().len(), 2);
+        let url: Url = "https://files.example.com/dl/a.zip".parse().unwrap();
+        assert_eq!(jar.cookie_header(&url).as_deref(), Some("token=xyz; session=abc"));
+        let url: Url = "http://files.example.com/dl/a.zip".parse().unwrap();
+        assert_eq!(jar.cookie_header(&url).as_deref(), Some("session=abc"));
+        assert_eq!(jar.cookie_header(&"https://example.org/".parse().unwrap()), None);
+
+        let url: Url = "https://example.com/login/submit".parse().unwrap();
+        jar.set_cookie(&url, "id=1; Path=/; Max-Age=3600; HttpOnly");
+        jar.set_cookie(&url, "local=2");
+        jar.set_cookie(&url, "evil=3; Domain=example.org");
+        // 公共后缀
+        jar.set_cookie(&"https://a.example.co.uk/".parse().unwrap(), "evil=4; Domain=co.uk");
+        jar.set_cookie(&url, "evil=5; Domain=com");
+        assert!(jar.cookies().iter().all(|n| !n.name.starts_with("evil")));
+        assert_eq!(jar.cookie_header(&"https://example.com/x".parse().unwrap()).as_deref(), Some("session=abc; id=1"));
+        assert_eq!(jar.cookie_header(&"https://example.com/login/next".parse().unwrap()).as_deref(), Some("local=2; session=abc; id=1"));
+        jar.set_cookie(&url, "session=; Domain=example.com; Path=/; Max-Age=0");
+        assert_eq!(jar.cookie_header(&"https://example.com/x".parse().unwrap()).as_deref(), Some("id=1"));
+        assert_eq!(CookieJar::parse_netscape(&jar.to_netscape()).cookies().len(), jar.cookies().len());
+    }
+
+    #[tokio::test]
+    async fn auth_is_not_sent_to_other_hosts() {
+        let other = TestServer::start(|_| TestResponse::new(200).header("Accept-Ranges", "none").body("hello")).await;
+        let location = other.url("/file.bin").to_string();
+        let origin = TestServer::start(move |request| match request.target.as_str() {
+            "/redirect" => TestResponse::new(302).header("Location", location.clone()),
+            _ => TestResponse::new(404),
+        }).await;
+        let save_dir = std::env::temp_dir().join(format!("http-downloader-auth-{}", std::process::id()));
+        let (mut downloader, _) = HttpDownloaderBuilder::new(origin.url("/redirect"), save_dir.clone())
+            .file_name(Some("file.bin".to_string()))
+            .build(DownloadAuthExtension::new(
+                Some(HttpAuth::Basic { username: "user".to_string(), password: "secret".to_string() }),
+                None,
+            ));
+        downloader.prepare_download().unwrap().await.unwrap();
+        assert_eq!(std::fs::read(save_dir.join("file.bin")).unwrap(), b"hello");
+        std::fs::remove_dir_all(&save_dir).unwrap();
+
+        assert!(origin.requests().iter().all(|n| n.header("authorization").is_some()));
+        assert!(!other.requests().is_empty());
+        assert!(other.requests().iter().all(|n| n.method == "GET" && n.body.is_empty()));
+        assert!(other.requests().iter().all(|n| n.header("authorization").is_none()));
+
+        // 协议或端口不同也不是同一个源
+        let interceptor = AuthInterceptor {
+            auth: HttpAuth::Bearer("token".to_string()),
+            origin: Url::parse("https://example.com/a.bin").unwrap().origin(),
+            digest_challenge: Default::default(),
+            nonce_count: AtomicU32::new(0),
+        };
+        for (url, sent) in [
+            ("https://example.com/b.bin", true),
+            ("https://example.com:443/b.bin", true),
+            ("http://example.com/b.bin", false),
+            ("https://example.com:8443/b.bin", false),
+            ("https://cdn.example.com/b.bin", false),
+        ] {
+            let mut request = reqwest::Request::new(reqwest::Method::GET, url.parse().unwrap());
+            interceptor.intercept_request(&mut request);
+            assert_eq!(request.headers().contains_key(AUTHORIZATION), sent, "{}", url);
+        }
+    }
+}
@@ -158,6 +158,8 @@ impl<T: DownloadDataArchiverBuilder + 'static> DownloaderWrapper for DownloadBre
                                 - data.remaining_len(),
                             downloading_duration: downloading_state.get_current_downloading_duration(),
                             chunk_data: Some(data),
+                            content_length: Some(chunk_manager.chunk_iterator.content_length),
+                            etag: downloading_state.etag.clone(),
                         };
                         download_archiver.save(Box::new(archive_data)).await?;
                         notified = notifies.data_archive_notify.notified();
@@ -0,0 +1,272 @@
+use std::any::{Any, TypeId};
+
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+
+use crate::{ChunkItem, DownloadError, DownloadExtensionBuilder, DownloaderWrapper, DownloadFuture, DownloadStartError, HttpFileDownloader};
+
+type BoxedExtensionBuilder = Box<dyn FnOnce(&mut HttpFileDownloader, &mut ExtensionStates) -> Box<dyn DownloaderWrapper> + Send>;
+
+/// 运行时组合的扩展，数量没有限制，可以根据设置决定添加哪些扩展
+///
+/// 与元组相同，扩展按添加顺序构建与调用，构建后通过 `ExtensionStates` 按类型或添加序号获取每个扩展的状态
+#[derive(Default)]
+pub struct DynExtensionSet {
+    builders: Vec<BoxedExtensionBuilder>,
+}
+
+impl DynExtensionSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 添加扩展
+    pub fn with<DEB>(mut self, extension_builder: DEB) -> Self
+        where DEB: DownloadExtensionBuilder + Send,
+              DEB::ExtensionState: Send + Sync {
+        self.push(extension_builder);
+        self
+    }
+
+    /// 添加扩展，返回扩展的添加序号，同一类型的状态有多个时通过序号获取
+    pub fn push<DEB>(&mut self, extension_builder: DEB) -> usize
+        where DEB: DownloadExtensionBuilder + Send,
+              DEB::ExtensionState: Send + Sync {
+        self.builders.push(Box::new(move |downloader, states| {
+            let (wrapper, state) = extension_builder.build(downloader);
+            states.push(state);
+            Box::new(wrapper)
+        }));
+        self.builders.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.builders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.builders.is_empty()
+    }
+}
+
+impl DownloadExtensionBuilder for DynExtensionSet {
+    type Wrapper = DynExtensionWrapper;
+    type ExtensionState = ExtensionStates;
+
+    fn build(self, downloader: &mut HttpFileDownloader) -> (Self::Wrapper, Self::ExtensionState) where Self: Sized {
+        let mut states = ExtensionStates::default();
+        let wrappers = self.builders.into_iter().map(|builder| builder(downloader, &mut states)).collect();
+        (DynExtensionWrapper { wrappers }, states)
+    }
+}
+
+pub struct DynExtensionWrapper {
+    wrappers: Vec<Box<dyn DownloaderWrapper>>,
+}
+
+impl DownloaderWrapper for DynExtensionWrapper {
+    fn prepare_download(&mut self, downloader: &mut HttpFileDownloader) -> Result<(), DownloadStartError> {
+        for wrapper in self.wrappers.iter_mut() {
+            wrapper.prepare_download(downloader)?;
+        }
+        Ok(())
+    }
+
+    fn handle_prepare_download_result(
+        &mut self,
+        downloader: &mut HttpFileDownloader,
+        prepare_download_result: Result<DownloadFuture, DownloadStartError>,
+    ) -> Result<DownloadFuture, DownloadStartError> {
+        self.wrappers.iter_mut().fold(prepare_download_result, |result, wrapper| {
+            wrapper.handle_prepare_download_result(downloader, result)
+        })
+    }
+
+    fn download(&mut self, downloader: &mut HttpFileDownloader, download_future: DownloadFuture) -> Result<DownloadFuture, DownloadStartError> {
+        self.wrappers.iter_mut().try_fold(download_future, |download_future, wrapper| {
+            wrapper.download(downloader, download_future)
+        })
+    }
+
+    fn on_cancel(&self) -> BoxFuture<'static, ()> {
+        let futures: Vec<_> = self.wrappers.iter().map(|wrapper| wrapper.on_cancel()).collect();
+        async move {
+            for future in futures {
+                future.await;
+            }
+        }.boxed()
+    }
+
+    fn before_chunk_request(&self, chunk_item: &ChunkItem, request: &mut reqwest::Request) {
+        for wrapper in self.wrappers.iter() {
+            wrapper.before_chunk_request(chunk_item, request);
+        }
+    }
+
+    fn on_chunk_bytes(&self, chunk_item: &ChunkItem, offset: u64, bytes: &[u8]) {
+        for wrapper in self.wrappers.iter() {
+            wrapper.on_chunk_bytes(chunk_item, offset, bytes);
+        }
+    }
+
+    fn on_chunk_finished(&self, chunk_item: &ChunkItem, offset: u64, chunk_bytes: &[u8]) {
+        for wrapper in self.wrappers.iter() {
+            wrapper.on_chunk_finished(chunk_item, offset, chunk_bytes);
+        }
+    }
+
+    fn on_chunk_failed(&self, chunk_item: &ChunkItem, error: &DownloadError) {
+        for wrapper in self.wrappers.iter() {
+            wrapper.on_chunk_failed(chunk_item, error);
+        }
+    }
+}
+
+/// 扩展状态，按类型存取时使用同一类型中最先添加的扩展的状态，也可以按添加序号存取
+#[derive(Default)]
+pub struct ExtensionStates {
+    // 按扩展的添加顺序保存，没有状态的扩展为 None
+    states: Vec<Option<Box<dyn Any + Send + Sync>>>,
+}
+
+impl ExtensionStates {
+    fn push<T: Send + Sync + 'static>(&mut self, state: T) {
+        // 没有状态的扩展不需要记录
+        if TypeId::of::<T>() == TypeId::of::<()>() {
+            self.states.push(None);
+        } else {
+            self.states.push(Some(Box::new(state)));
+        }
+    }
+
+    fn position<T: 'static>(&self) -> Option<usize> {
+        self.states.iter().position(|n| n.as_ref().is_some_and(|n| n.is::<T>()))
+    }
+
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.get_at(self.position::<T>()?)
+    }
+
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.get_at_mut(self.position::<T>()?)
+    }
+
+    /// 取出状态
+    pub fn take<T: 'static>(&mut self) -> Option<T> {
+        self.take_at(self.position::<T>()?)
+    }
+
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.position::<T>().is_some()
+    }
+
+    /// 按添加序号获取，序号对应的扩展状态不是 `T` 时返回 None
+    pub fn get_at<T: 'static>(&self, index: usize) -> Option<&T> {
+        self.states.get(index)?.as_ref()?.downcast_ref()
+    }
+
+    pub fn get_at_mut<T: 'static>(&mut self, index: usize) -> Option<&mut T> {
+        self.states.get_mut(index)?.as_mut()?.downcast_mut()
+    }
+
+    /// 按添加序号取出状态
+    pub fn take_at<T: 'static>(&mut self, index: usize) -> Option<T> {
+        let state = self.states.get_mut(index)?;
+        if !state.as_ref()?.is::<T>() {
+            return None;
+        }
+        state.take()?.downcast().ok().map(|n| *n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use parking_lot::Mutex;
+
+    use crate::HttpDownloaderBuilder;
+
+    use super::*;
+
+    // 调用 on_cancel 时记录自己的序号，状态为序号
+    struct OrderExtension(usize, Arc<Mutex<Vec<usize>>>);
+
+    impl DownloadExtensionBuilder for OrderExtension {
+        type Wrapper = OrderExtension;
+        type ExtensionState = usize;
+
+        fn build(self, _downloader: &mut HttpFileDownloader) -> (Self::Wrapper, Self::ExtensionState) {
+            let index = self.0;
+            (self, index)
+        }
+    }
+
+    impl DownloaderWrapper for OrderExtension {
+        fn on_cancel(&self) -> BoxFuture<'static, ()> {
+            self.1.lock().push(self.0);
+            futures_util::future::ready(()).boxed()
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct NamedState(&'static str);
+
+    struct NamedExtension(&'static str);
+
+    impl DownloadExtensionBuilder for NamedExtension {
+        type Wrapper = ();
+        type ExtensionState = NamedState;
+
+        fn build(self, _downloader: &mut HttpFileDownloader) -> (Self::Wrapper, Self::ExtensionState) {
+            ((), NamedState(self.0))
+        }
+    }
+
+    #[tokio::test]
+    async fn more_extensions_than_tuple() {
+        let builder = || HttpDownloaderBuilder::new("http://localhost/a".parse().unwrap(), std::env::temp_dir());
+        let tuple_order = Arc::new(Mutex::new(Vec::new()));
+        let extension = |index| OrderExtension(index, tuple_order.clone());
+        let (downloader, states) = builder().build((
+            extension(0), extension(1), extension(2), extension(3), extension(4), extension(5), extension(6), extension(7),
+        ));
+        downloader.cancel().await;
+        assert_eq!(states, (0, 1, 2, 3, 4, 5, 6, 7));
+
+        let dyn_order = Arc::new(Mutex::new(Vec::new()));
+        let mut extensions = DynExtensionSet::new();
+        for index in 0..10 {
+            assert_eq!(extensions.push(OrderExtension(index, dyn_order.clone())), index);
+        }
+        let extensions = extensions.with(()).with(NamedExtension("named"));
+        assert_eq!(extensions.len(), 12);
+        let (downloader, mut states) = builder().build(extensions);
+        downloader.cancel().await;
+        // 与元组相同，按添加顺序调用
+        assert_eq!(*dyn_order.lock(), (0..10).collect::<Vec<_>>());
+        assert_eq!(tuple_order.lock()[..], dyn_order.lock()[..8]);
+
+        // 同一类型的状态按类型获取时是最先添加的，其他的按序号获取
+        assert_eq!(states.get::<usize>(), Some(&0));
+        assert_eq!(states.get_at::<usize>(9), Some(&9));
+        assert_eq!(states.get_at::<NamedState>(9), None);
+        assert_eq!(states.get_at::<usize>(10), None);
+        assert_eq!(states.get_at::<usize>(12), None);
+        *states.get_at_mut::<usize>(6).unwrap() += 100;
+        assert_eq!(states.get_at::<usize>(6), Some(&106));
+        assert_eq!(states.take_at::<usize>(5), Some(5));
+        assert_eq!(states.take_at::<usize>(5), None);
+
+        assert!(states.contains::<NamedState>());
+        assert_eq!(states.get::<NamedState>().map(|n| n.0), Some("named"));
+        assert_eq!(states.take::<NamedState>().map(|n| n.0), Some("named"));
+        assert!(!states.contains::<NamedState>());
+        assert!(!states.contains::<String>());
+
+        assert_eq!(states.take::<usize>(), Some(0));
+        assert_eq!(states.get::<usize>(), Some(&1));
+        *states.get_mut::<usize>().unwrap() += 100;
+        assert_eq!(states.get_at::<usize>(1), Some(&101));
+    }
+}
@@ -0,0 +1,553 @@
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use anyhow::Result;
+use futures_util::FutureExt;
+use headers::{ContentLength, ContentRange, HeaderMapExt};
+use sha2::digest::DynDigest;
+use thiserror::Error;
+use url::Url;
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::{ChunkRange, DownloaderWrapper, DownloadError, DownloadExtensionBuilder, DownloadFuture, DownloadingEndCause, DownloadStartError, HttpDownloaderBuilder, HttpFileDownloader, RequestInterceptor};
+use crate::{local_source, request_interceptor};
+
+// 整个文件的校验和，按强度从高到低选择
+const FILE_HASH_TYPES: [&str; 4] = ["sha-512", "sha-384", "sha-256", "sha-1"];
+
+#[derive(Error, Debug)]
+pub enum MetalinkError {
+    #[error("http request failed，{:?}", .0)]
+    HttpRequestFailed(#[from] reqwest::Error),
+    #[error("xml parse failed，{:?}", .0)]
+    XmlError(#[from] xml::reader::Error),
+    #[error("invalid metalink document: {}", .0)]
+    InvalidDocument(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct MetalinkUrl {
+    pub url: Url,
+    // 值越小越优先，没有指定时最后使用
+    pub priority: Option<u32>,
+    // ISO 3166-1 国家代码
+    pub location: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MetalinkHash {
+    // 小写的哈希类型，比如 `sha-256`
+    pub hash_type: String,
+    // 小写的十六进制值
+    pub value: String,
+}
+
+/// 分块校验和，每块长度为 `length`，最后一块可能不足
+#[derive(Debug, Clone)]
+pub struct MetalinkPieces {
+    pub length: u64,
+    pub hash_type: String,
+    pub hashes: Vec<String>,
+}
+
+impl MetalinkPieces {
+    /// 第 `index` 块在大小为 `size` 的文件中的范围，块在文件之外时返回 None
+    fn range(&self, index: usize, size: u64) -> Option<ChunkRange> {
+        let start = (index as u64).checked_mul(self.length).filter(|n| *n < size)?;
+        Some(ChunkRange::new(start, start.saturating_add(self.length).min(size) - 1))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MetalinkFile {
+    // 可以包含子目录
+    pub name: String,
+    pub size: Option<u64>,
+    pub hashes: Vec<MetalinkHash>,
+    pub pieces: Option<MetalinkPieces>,
+    // 按优先级排序
+    pub urls: Vec<MetalinkUrl>,
+}
+
+impl MetalinkFile {
+    /// 支持的强度最高的整个文件的校验和
+    pub fn file_hash(&self) -> Option<&MetalinkHash> {
+        FILE_HASH_TYPES
+            .iter()
+            .find_map(|hash_type| self.hashes.iter().find(|n| n.hash_type == *hash_type))
+    }
+
+    /// 创建下载这个文件的 builder，地址为优先级最高的镜像，有分块校验和时 chunk 大小与块大小一致
+    ///
+    /// 需要与 `DownloadMetalinkExtension` 一起使用，其他镜像才会生效
+    pub fn downloader_builder(&self, save_dir: PathBuf) -> HttpDownloaderBuilder {
+        let path = Path::new(&self.name);
+        let save_dir = match path.parent() {
+            Some(parent) => save_dir.join(parent),
+            None => save_dir,
+        };
+        let file_name = path.file_name().map(|n| n.to_string_lossy().to_string());
+        let mut builder = HttpDownloaderBuilder::new(self.urls[0].url.clone(), save_dir).file_name(file_name);
+        if let Some(chunk_size) = self.pieces.as_ref().and_then(|n| usize::try_from(n.length).ok()).and_then(std::num::NonZeroUsize::new) {
+            builder = builder.chunk_size(chunk_size);
+        }
+        builder
+    }
+}
+
+/// Metalink 文档（RFC 5854，`.meta4`）
+#[derive(Debug, Clone)]
+pub struct Metalink {
+    pub files: Vec<MetalinkFile>,
+}
+
+impl Metalink {
+    pub async fn load(client: &reqwest::Client, url: Url) -> Result<Self, MetalinkError> {
+        let response = client.get(url).send().await?.error_for_status()?;
+        let url = response.url().clone();
+        let bytes = response.bytes().await?;
+        Self::parse(&url, &bytes)
+    }
+
+    /// 解析文档，相对地址基于 `url`
+    pub fn parse(url: &Url, data: &[u8]) -> Result<Self, MetalinkError> {
+        let invalid = |msg: String| MetalinkError::InvalidDocument(msg);
+
+        let mut files = vec![];
+        let mut file: Option<MetalinkFile> = None;
+        let mut pieces: Option<MetalinkPieces> = None;
+        let mut hash_type: Option<String> = None;
+        let mut url_attributes: (Option<u32>, Option<String>) = (None, None);
+        let mut stack: Vec<String> = vec![];
+        let mut text = String::new();
+        for event in EventReader::new(data) {
+            match event? {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    let attribute = |key: &str| attributes.iter().find(|n| n.name.local_name == key).map(|n| n.value.trim().to_string());
+                    match name.local_name.as_str() {
+                        "file" => {
+                            file = Some(MetalinkFile {
+                                name: attribute("name").ok_or_else(|| invalid("file without name".to_string()))?,
+                                size: None,
+                                hashes: vec![],
+                                pieces: None,
+                                urls: vec![],
+                            });
+                        }
+                        "pieces" => {
+                            let length = attribute("length")
+                                .and_then(|n| n.parse().ok())
+                                .filter(|n| *n > 0)
+                                .ok_or_else(|| invalid("pieces without length".to_string()))?;
+                            pieces = Some(MetalinkPieces {
+                                length,
+                                hash_type: attribute("type").unwrap_or_default().to_ascii_lowercase(),
+                                hashes: vec![],
+                            });
+                        }
+                        "hash" => hash_type = attribute("type").map(|n| n.to_ascii_lowercase()),
+                        "url" => url_attributes = (attribute("priority").and_then(|n| n.parse().ok()), attribute("location")),
+                        _ => {}
+                    }
+                    stack.push(name.local_name);
+                    text.clear();
+                }
+                XmlEvent::Characters(value) | XmlEvent::CData(value) => text.push_str(&value),
+                XmlEvent::EndElement { name } => {
+                    stack.pop();
+                    let parent = stack.last().map(String::as_str);
+                    let value = text.trim();
+                    match (name.local_name.as_str(), file.as_mut()) {
+                        ("size", Some(file)) if parent == Some("file") => {
+                            file.size = Some(value.parse().map_err(|_| invalid(format!("invalid size {}", value)))?);
+                        }
+                        ("hash", Some(_)) if parent == Some("pieces") => {
+                            if let Some(pieces) = pieces.as_mut() {
+                                pieces.hashes.push(value.to_ascii_lowercase());
+                            }
+                        }
+                        ("hash", Some(file)) if parent == Some("file") => {
+                            if let Some(hash_type) = hash_type.take() {
+                                file.hashes.push(MetalinkHash { hash_type, value: value.to_ascii_lowercase() });
+                            }
+                        }
+                        ("pieces", Some(file)) => file.pieces = pieces.take(),
+                        ("url", Some(file)) => {
+                            let mirror_url = url.join(value).map_err(|err| invalid(format!("invalid url {}, {}", value, err)))?;
+                            let (priority, location) = std::mem::take(&mut url_attributes);
+                            // 与重定向相同，远程的 metalink 不能让下载器读取本地文件
+                            if crate::Redirection::is_allowed(url, &mirror_url) {
+                                file.urls.push(MetalinkUrl { url: mirror_url, priority, location });
+                            }
+                        }
+                        ("file", Some(_)) => files.extend(file.take()),
+                        _ => {}
+                    }
+                    text.clear();
+                }
+                _ => {}
+            }
+        }
+
+        for file in files.iter_mut() {
+            // 文件名不能跳出保存目录
+            let path = Path::new(&file.name);
+            if file.name.is_empty() || path.components().any(|n| !matches!(n, Component::Normal(_))) {
+                return Err(invalid(format!("invalid file name {}", file.name)));
+            }
+            if file.urls.is_empty() {
+                return Err(invalid(format!("file {} has no url", file.name)));
+            }
+            file.urls.sort_by_key(|n| n.priority.unwrap_or(u32::MAX));
+            if let (Some(pieces), Some(size)) = (file.pieces.as_ref(), file.size) {
+                if pieces.hashes.len() as u64 != size.div_ceil(pieces.length) {
+                    return Err(invalid(format!("file {} has {} piece hashes, expected {}", file.name, pieces.hashes.len(), size.div_ceil(pieces.length))));
+                }
+            }
+        }
+        if files.is_empty() {
+            return Err(invalid("no file".to_string()));
+        }
+        Ok(Self { files })
+    }
+}
+
+fn new_digest(hash_type: &str) -> Option<Box<dyn DynDigest + Send>> {
+    match hash_type {
+        "sha-1" => Some(Box::new(sha1::Sha1::default())),
+        "sha-256" => Some(Box::new(sha2::Sha256::default())),
+        "sha-384" => Some(Box::new(sha2::Sha384::default())),
+        "sha-512" => Some(Box::new(sha2::Sha512::default())),
+        _ => None,
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|n| format!("{:02x}", n)).collect()
+}
+
+fn file_hash(path: &Path, hash_type: &str) -> io::Result<Option<String>> {
+    let Some(mut digest) = new_digest(hash_type) else {
+        return Ok(None);
+    };
+    let mut file = fs::File::open(path)?;
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        let len = file.read(&mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        digest.update(&buffer[..len]);
+    }
+    Ok(Some(to_hex(&digest.finalize())))
+}
+
+/// 返回校验和不匹配的块，文件大小与块数不对应时返回错误（文档中没有文件大小时无法提前检查）
+fn mismatched_pieces(path: &Path, pieces: &MetalinkPieces, size: u64) -> io::Result<Vec<(usize, ChunkRange)>> {
+    let Some(mut digest) = new_digest(&pieces.hash_type) else {
+        #[cfg(feature = "tracing")]
+        tracing::warn!("unsupported metalink piece hash type {}", pieces.hash_type);
+        return Ok(vec![]);
+    };
+    let covered_len = (pieces.hashes.len() as u64).saturating_mul(pieces.length);
+    if pieces.range(pieces.hashes.len().saturating_sub(1), size).is_none() || covered_len < size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} metalink pieces of {} bytes do not match the file size {}", pieces.hashes.len(), pieces.length, size),
+        ));
+    }
+    let mut file = fs::File::open(path)?;
+    let mut buffer = vec![0; pieces.length.min(size) as usize];
+    let mut mismatched = vec![];
+    for (index, hash) in pieces.hashes.iter().enumerate() {
+        let Some(range) = pieces.range(index, size) else {
+            break;
+        };
+        let buffer = &mut buffer[..range.len() as usize];
+        file.read_exact(buffer)?;
+        digest.update(buffer);
+        if to_hex(&digest.finalize_reset()) != *hash {
+            mismatched.push((index, range));
+        }
+    }
+    Ok(mismatched)
+}
+
+/// 把主地址的请求轮流分配到各个镜像，镜像出错时换用其他镜像
+struct MirrorInterceptor {
+    primary: Url,
+    urls: Vec<Url>,
+    next: AtomicUsize,
+    // 每个镜像的出错次数，优先使用出错次数最少的镜像
+    failures: Vec<AtomicUsize>,
+    size: Option<u64>,
+}
+
+impl MirrorInterceptor {
+    fn new(file: &MetalinkFile) -> Self {
+        Self {
+            primary: file.urls[0].url.clone(),
+            urls: file.urls.iter().map(|n| n.url.clone()).collect(),
+            next: AtomicUsize::new(0),
+            failures: file.urls.iter().map(|_| AtomicUsize::new(0)).collect(),
+            size: file.size,
+        }
+    }
+
+    fn mark_failed(&self, url: &Url) {
+        if let Some(index) = self.urls.iter().position(|n| n == url) {
+            self.failures[index].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl RequestInterceptor for MirrorInterceptor {
+    fn intercept_request(&self, request: &mut reqwest::Request) {
+        // 重定向后的请求保持原样
+        if *request.url() != self.primary {
+            return;
+        }
+        let failures: Vec<usize> = self.failures.iter().map(|n| n.load(Ordering::Relaxed)).collect();
+        let min_failures = failures.iter().copied().min().unwrap_or(0);
+        let candidates: Vec<usize> = (0..self.urls.len()).filter(|n| failures[*n] == min_failures).collect();
+        let index = candidates[self.next.fetch_add(1, Ordering::Relaxed) % candidates.len()];
+        *request.url_mut() = self.urls[index].clone();
+    }
+
+    fn intercept_response(&self, response: &reqwest::Response) -> bool {
+        if !self.urls.contains(response.url()) {
+            return false;
+        }
+        let status = response.status();
+        let is_failed = if status.is_server_error() || matches!(status, reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE) {
+            true
+        } else if status.is_success() {
+            // 镜像上的文件大小与文档不一致
+            let len = match response.headers().typed_get::<ContentRange>() {
+                Some(content_range) => content_range.bytes_len(),
+                None if status == reqwest::StatusCode::OK => response.headers().typed_get::<ContentLength>().map(|n| n.0),
+                None => None,
+            };
+            matches!((self.size, len), (Some(size), Some(len)) if size != len)
+        } else {
+            false
+        };
+        if is_failed {
+            #[cfg(feature = "tracing")]
+            tracing::warn!("metalink mirror failed: {}, status: {}", response.url(), status);
+            self.mark_failed(response.url());
+        }
+        is_failed
+    }
+}
+
+/// Metalink 下载，从多个镜像下载，完成后按分块校验和重新下载损坏的块，最后校验大小与整个文件的校验和
+///
+/// 需要使用 `MetalinkFile::downloader_builder` 创建下载器
+pub struct DownloadMetalinkExtension {
+    pub file: Arc<MetalinkFile>,
+}
+
+impl DownloadMetalinkExtension {
+    pub fn new(file: Arc<MetalinkFile>) -> Self {
+        Self { file }
+    }
+}
+
+pub struct DownloadMetalinkState {
+    // 校验失败后重新下载的块数
+    pub repaired_piece_count: Arc<AtomicUsize>,
+    pub repaired_len: Arc<AtomicU64>,
+}
+
+pub struct DownloadMetalinkDownloaderWrapper {
+    file: Arc<MetalinkFile>,
+    mirror_interceptor: Arc<MirrorInterceptor>,
+    repaired_piece_count: Arc<AtomicUsize>,
+    repaired_len: Arc<AtomicU64>,
+}
+
+impl DownloadExtensionBuilder for DownloadMetalinkExtension {
+    type Wrapper = DownloadMetalinkDownloaderWrapper;
+    type ExtensionState = DownloadMetalinkState;
+
+    fn build(self, downloader: &mut HttpFileDownloader) -> (Self::Wrapper, Self::ExtensionState) where Self: Sized {
+        let mirror_interceptor = Arc::new(MirrorInterceptor::new(&self.file));
+        downloader.request_interceptors.push(mirror_interceptor.clone());
+        let repaired_piece_count = Arc::new(AtomicUsize::new(0));
+        let repaired_len = Arc::new(AtomicU64::new(0));
+        (
+            DownloadMetalinkDownloaderWrapper {
+                file: self.file,
+                mirror_interceptor,
+                repaired_piece_count: repaired_piece_count.clone(),
+                repaired_len: repaired_len.clone(),
+            },
+            DownloadMetalinkState {
+                repaired_piece_count,
+                repaired_len,
+            },
+        )
+    }
+}
+
+impl DownloaderWrapper for DownloadMetalinkDownloaderWrapper {
+    fn download(&mut self, downloader: &mut HttpFileDownloader, download_future: DownloadFuture) -> Result<DownloadFuture, DownloadStartError> {
+        let file = self.file.clone();
+        let file_path = downloader.config.file_path();
+        let config = downloader.config.clone();
+        let client = downloader.client().cloned();
+        let request_interceptors = downloader.request_interceptors.clone();
+        let mirror_interceptor = self.mirror_interceptor.clone();
+        let repaired_piece_count = self.repaired_piece_count.clone();
+        let repaired_len = self.repaired_len.clone();
+        Ok(async move {
+            let end_cause = download_future.await?;
+            // 只下载了一部分时无法校验
+            if end_cause != DownloadingEndCause::DownloadFinished || config.byte_range.is_some() {
+                return Ok(end_cause);
+            }
+            let len = tokio::fs::metadata(&file_path).await?.len();
+            if let Some(size) = file.size {
+                if len != size {
+                    return Err(DownloadError::Other(anyhow::anyhow!("metalink size mismatch, expected {}, got {}", size, len)));
+                }
+            }
+
+            if let (Some(pieces), Some(client)) = (file.pieces.clone(), client) {
+                let mismatched = {
+                    let file_path = file_path.clone();
+                    let pieces = pieces.clone();
+                    tokio::task::spawn_blocking(move || mismatched_pieces(&file_path, &pieces, len)).await??
+                };
+                for (index, range) in mismatched {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!("metalink piece {} hash mismatch, download again", index);
+                    // 每个镜像至少尝试一次
+                    let max_times = file.urls.len().max(config.request_retry_count as usize + 1);
+                    let mut data = None;
+                    for _ in 0..max_times {
+                        let mut request = config.create_http_request(None);
+                        request.headers_mut().typed_insert(range.to_range_header());
+                        request_interceptor::intercept_request(&request_interceptors, &mut request);
+                        let url = request.url().clone();
+                        let response = match local_source::execute(&client, &config.client_config, request).await {
+                            Ok(response) if response.status() == reqwest::StatusCode::PARTIAL_CONTENT => response,
+                            _ => {
+                                mirror_interceptor.mark_failed(&url);
+                                continue;
+                            }
+                        };
+                        let Ok(bytes) = response.bytes().await else {
+                            mirror_interceptor.mark_failed(&url);
+                            continue;
+                        };
+                        let is_matched = new_digest(&pieces.hash_type)
+                            .map(|mut digest| {
+                                digest.update(&bytes);
+                                to_hex(&digest.finalize()) == pieces.hashes[index]
+                            })
+                            .unwrap_or(false);
+                        if bytes.len() as u64 == range.len() && is_matched {
+                            data = Some(bytes);
+                            break;
+                        }
+                        mirror_interceptor.mark_failed(&url);
+                    }
+                    let Some(data) = data else {
+                        return Err(DownloadError::Other(anyhow::anyhow!("metalink piece {} hash mismatch on all mirrors", index)));
+                    };
+                    let file_path = file_path.clone();
+                    tokio::task::spawn_blocking(move || {
+                        let mut file = fs::OpenOptions::new().write(true).open(file_path)?;
+                        file.seek(SeekFrom::Start(range.start))?;
+                        file.write_all(&data)?;
+                        file.flush()
+                    })
+                        .await??;
+                    repaired_piece_count.fetch_add(1, Ordering::Relaxed);
+                    repaired_len.fetch_add(range.len(), Ordering::Relaxed);
+                }
+            }
+
+            if let Some(hash) = file.file_hash().cloned() {
+                let hash_type = hash.hash_type.clone();
+                let file_hash = tokio::task::spawn_blocking(move || file_hash(&file_path, &hash_type)).await??;
+                if file_hash.as_ref() != Some(&hash.value) {
+                    return Err(DownloadError::Other(anyhow::anyhow!("metalink {} mismatch, expected {}, got {:?}", hash.hash_type, hash.value, file_hash)));
+                }
+            }
+            Ok(end_cause)
+        }.boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::Digest;
+
+    use super::*;
+
+    #[test]
+    fn parse_and_verify_pieces() {
+        let data: Vec<u8> = (0..2500u32).map(|n| (n * 13 % 251) as u8).collect();
+        let piece_hashes: String = data
+            .chunks(1024)
+            .map(|n| format!("<hash>{}</hash>", to_hex(&sha1::Sha1::digest(n))))
+            .collect();
+        let document = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <metalink xmlns="urn:ietf:params:xml:ns:metalink">
+              <file name="dir/example.bin">
+                <size>{}</size>
+                <hash type="SHA-256">{}</hash>
+                <hash type="md5">ignored</hash>
+                <pieces length="1024" type="sha-1">{}</pieces>
+                <url location="de">http://mirror.example.org/example.bin</url>
+                <url priority="1">example.bin</url>
+                <url>file:///etc/passwd</url>
+                <metaurl mediatype="torrent">example.torrent</metaurl>
+              </file>
+            </metalink>"#,
+            data.len(),
+            to_hex(&sha2::Sha256::digest(&data)).to_uppercase(),
+            piece_hashes,
+        );
+        let base: Url = "https://example.com/files/example.meta4".parse().unwrap();
+        let metalink = Metalink::parse(&base, document.as_bytes()).unwrap();
+        let file = &metalink.files[0];
+        assert_eq!(file.name, "dir/example.bin");
+        assert_eq!(file.size, Some(2500));
+        assert_eq!(file.file_hash().unwrap().value, to_hex(&sha2::Sha256::digest(&data)));
+        assert_eq!(file.urls[0].url.as_str(), "https://example.com/files/example.bin");
+        assert_eq!(file.urls[1].location.as_deref(), Some("de"));
+        // 远程 metalink 中的本地地址被忽略
+        assert_eq!(file.urls.len(), 2);
+        let pieces = file.pieces.as_ref().unwrap();
+        assert_eq!(pieces.hashes.len(), 3);
+        assert_eq!(pieces.range(2, 2500).map(|n| (n.start, n.end)), Some((2048, 2499)));
+        // 文档中没有文件大小，实际文件比块数短
+        assert!(pieces.range(3, 2500).is_none());
+        assert!(pieces.range(2, 2048).is_none());
+
+        let dir = std::env::temp_dir().join(format!("http-downloader-metalink-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("example.bin");
+        let mut corrupted = data.clone();
+        corrupted[1500] ^= 0xFF;
+        fs::write(&path, &corrupted).unwrap();
+        assert_eq!(mismatched_pieces(&path, pieces, 2500).unwrap().iter().map(|n| n.0).collect::<Vec<_>>(), vec![1]);
+        for size in [1000, 4000] {
+            let err = mismatched_pieces(&path, pieces, size).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{}", size);
+        }
+        fs::write(&path, &data).unwrap();
+        assert_eq!(file_hash(&path, "sha-256").unwrap().as_ref(), Some(&file.file_hash().unwrap().value));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(Metalink::parse(&base, br#"<metalink><file name="../x"><url>a</url></file></metalink>"#).is_err());
+    }
+}
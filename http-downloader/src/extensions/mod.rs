@@ -1,22 +1,37 @@
+use std::sync::Arc;
+
 use anyhow::Result;
 use futures_util::future::{BoxFuture};
 use futures_util::FutureExt;
 
-use crate::{ChunkData, DownloadError, DownloadingEndCause, DownloadStartError, HttpFileDownloader};
+use crate::{ChunkData, ChunkItem, DownloadError, DownloadingEndCause, DownloadStartError, HttpFileDownloader};
+pub use dyn_extension_set::*;
 
+#[cfg(feature = "auth")]
+pub mod auth;
 #[cfg(feature = "breakpoint-resume")]
 pub mod breakpoint_resume;
 #[cfg(feature = "bson-file-archiver")]
 pub mod bson_file_archiver;
+mod dyn_extension_set;
+#[cfg(feature = "metalink")]
+pub mod metalink;
+#[cfg(feature = "s3")]
+pub mod s3;
 #[cfg(feature = "speed-limiter")]
 pub mod speed_limiter;
 #[cfg(feature = "speed-tracker")]
 pub mod speed_tracker;
 #[cfg(feature = "status-tracker")]
 pub mod status_tracker;
+#[cfg(feature = "zsync")]
+pub mod zsync;
 
 pub type DownloadFuture = BoxFuture<'static, Result<DownloadingEndCause, DownloadError>>;
 
+/// 由 `ExtendedHttpFileDownloader` 与正在下载的 chunk 共享，chunk 通过它调用 chunk 级别的钩子
+pub(crate) type SharedDownloaderWrapper = Arc<parking_lot::RwLock<Box<dyn DownloaderWrapper>>>;
+
 
 pub trait DownloaderWrapper: Send+Sync+'static {
     fn prepare_download(
@@ -42,6 +57,20 @@ pub trait DownloaderWrapper: Send+Sync+'static {
     fn on_cancel(&self)-> BoxFuture<'static,()> {
         futures_util::future::ready(()).boxed()
     }
+
+    // 以下为 chunk 级别的钩子，只作用于多连接（Range）下载，由各个 chunk 并发调用
+    // 尾段竞速的竞速连接同样会调用，可以通过 `ChunkItem::race_side` 区分
+
+    /// 发出 chunk 请求前调用（包括重试），在请求拦截器之后，可以修改请求，例如更换镜像、添加认证
+    fn before_chunk_request(&self, _chunk_item: &ChunkItem, _request: &mut reqwest::Request) {}
+    /// chunk 接收到数据后调用，`offset` 为数据在文件中的位置
+    fn on_chunk_bytes(&self, _chunk_item: &ChunkItem, _offset: u64, _bytes: &[u8]) {}
+    /// chunk 下载完成、数据写入文件后调用，`offset` 与 `chunk_bytes` 为这个 chunk 写入的数据
+    ///
+    /// 尾段竞速时原 chunk 与胜出的竞速连接分别调用，各自只包含自己写入的部分
+    fn on_chunk_finished(&self, _chunk_item: &ChunkItem, _offset: u64, _chunk_bytes: &[u8]) {}
+    /// chunk 出错结束时调用，已接收的数据已经写入文件
+    fn on_chunk_failed(&self, _chunk_item: &ChunkItem, _error: &DownloadError) {}
 }
 
 pub trait DownloadExtensionBuilder: 'static {
@@ -105,6 +134,22 @@ macro_rules! impl_download_extension_tuple {
                     $($de.await;)*
                 }.boxed()
             }
+            fn before_chunk_request(&self, chunk_item: &ChunkItem, request: &mut reqwest::Request) {
+                let ($($de,)*) = self;
+                $($de.before_chunk_request(chunk_item, request);)*
+            }
+            fn on_chunk_bytes(&self, chunk_item: &ChunkItem, offset: u64, bytes: &[u8]) {
+                let ($($de,)*) = self;
+                $($de.on_chunk_bytes(chunk_item, offset, bytes);)*
+            }
+            fn on_chunk_finished(&self, chunk_item: &ChunkItem, offset: u64, chunk_bytes: &[u8]) {
+                let ($($de,)*) = self;
+                $($de.on_chunk_finished(chunk_item, offset, chunk_bytes);)*
+            }
+            fn on_chunk_failed(&self, chunk_item: &ChunkItem, error: &DownloadError) {
+                let ($($de,)*) = self;
+                $($de.on_chunk_failed(chunk_item, error);)*
+            }
         }
 
         #[allow(non_snake_case)]
@@ -145,6 +190,12 @@ pub struct DownloadArchiveData {
     pub downloaded_len: u64,
     pub downloading_duration: u32,
     pub chunk_data: Option<ChunkData>,
+    // 存档时的文件总大小，用于续传前检查
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub content_length: Option<u64>,
+    // 存档时服务器返回的 ETag，用于续传前检查文件是否被修改
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub etag: Option<String>,
 }
 
 #[cfg(feature = "async-graphql")]
@@ -163,4 +214,159 @@ impl DownloadArchiveData {
         }
         self.downloaded_len / self.downloading_duration as u64
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::{NonZeroU8, NonZeroUsize};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use parking_lot::Mutex;
+
+    use crate::HttpDownloaderBuilder;
+    use crate::test_server::{TestResponse, TestServer};
+
+    use super::*;
+
+    const CHUNK_SIZE: u64 = 64 * 1024;
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum ChunkEvent {
+        Request(usize),
+        Bytes(usize, u64, u64),
+        Finished(usize, u64, u64),
+        Failed(usize),
+    }
+
+    // 记录 chunk 钩子的调用，并在 chunk 请求中加上自己的名称
+    struct RecordingExtension(&'static str, Arc<Mutex<Vec<ChunkEvent>>>);
+
+    impl DownloadExtensionBuilder for RecordingExtension {
+        type Wrapper = RecordingExtension;
+        type ExtensionState = ();
+
+        fn build(self, _downloader: &mut HttpFileDownloader) -> (Self::Wrapper, Self::ExtensionState) {
+            (self, ())
+        }
+    }
+
+    impl DownloaderWrapper for RecordingExtension {
+        fn before_chunk_request(&self, chunk_item: &ChunkItem, request: &mut reqwest::Request) {
+            request.headers_mut().append("x-hook", self.0.parse().unwrap());
+            self.1.lock().push(ChunkEvent::Request(chunk_item.chunk_info.index));
+        }
+
+        fn on_chunk_bytes(&self, chunk_item: &ChunkItem, offset: u64, bytes: &[u8]) {
+            self.1.lock().push(ChunkEvent::Bytes(chunk_item.chunk_info.index, offset, bytes.len() as u64));
+        }
+
+        fn on_chunk_finished(&self, chunk_item: &ChunkItem, offset: u64, chunk_bytes: &[u8]) {
+            self.1.lock().push(ChunkEvent::Finished(chunk_item.chunk_info.index, offset, chunk_bytes.len() as u64));
+        }
+
+        fn on_chunk_failed(&self, chunk_item: &ChunkItem, _error: &DownloadError) {
+            self.1.lock().push(ChunkEvent::Failed(chunk_item.chunk_info.index));
+        }
+    }
+
+    async fn download<DEB: DownloadExtensionBuilder>(server: &TestServer, save_dir: &std::path::Path, extension: DEB) -> Result<DownloadingEndCause, DownloadError> {
+        let (mut downloader, _) = HttpDownloaderBuilder::new(server.url("/file.bin"), save_dir.to_path_buf())
+            .chunk_size(NonZeroUsize::new(CHUNK_SIZE as usize).unwrap())
+            .download_connection_count(NonZeroU8::new(3).unwrap())
+            .request_retry_count(1)
+            .build(extension);
+        downloader.prepare_download().unwrap().await
+    }
+
+    fn count(events: &[ChunkEvent], f: impl Fn(&ChunkEvent) -> bool) -> usize {
+        events.iter().filter(|n| f(n)).count()
+    }
+
+    fn check_finished_events(events: &[ChunkEvent], data_len: u64) {
+        let chunk_count = data_len.div_ceil(CHUNK_SIZE) as usize;
+        let mut covered = 0;
+        // chunk 序号从 1 开始
+        for index in 1..=chunk_count {
+            let start = (index - 1) as u64 * CHUNK_SIZE;
+            let len = CHUNK_SIZE.min(data_len - start);
+            // 数据按顺序连续，覆盖整个 chunk
+            let mut offset = start;
+            for event in events {
+                if let ChunkEvent::Bytes(i, bytes_offset, bytes_len) = *event {
+                    if i == index {
+                        assert_eq!(bytes_offset, offset);
+                        offset += bytes_len;
+                    }
+                }
+            }
+            assert_eq!(offset, start + len);
+            assert_eq!(count(events, |n| *n == ChunkEvent::Finished(index, start, len)), 1);
+            assert_eq!(count(events, |n| *n == ChunkEvent::Request(index)), 1);
+            covered += len;
+        }
+        assert_eq!(covered, data_len);
+        assert_eq!(count(events, |n| matches!(n, ChunkEvent::Finished(..))), chunk_count);
+        assert_eq!(count(events, |n| matches!(n, ChunkEvent::Failed(_))), 0);
+    }
+
+    #[tokio::test]
+    async fn chunk_hooks() {
+        let data: Arc<Vec<u8>> = Arc::new((0..(8 * CHUNK_SIZE + 100) as u32).map(|n| (n % 233) as u8).collect());
+        let fail = Arc::new(AtomicBool::new(false));
+        let server = TestServer::start({
+            let (data, fail) = (data.clone(), fail.clone());
+            move |request| {
+                if fail.load(Ordering::SeqCst) && request.header("range") == Some(&format!("bytes={}-{}", 3 * CHUNK_SIZE, 4 * CHUNK_SIZE - 1)) {
+                    return TestResponse::new(503);
+                }
+                TestResponse::ranged(request, &data)
+            }
+        }).await;
+        let save_dir = std::env::temp_dir().join(format!("http-downloader-chunk-hooks-{}", std::process::id()));
+
+        let tuple_events: [Arc<Mutex<Vec<ChunkEvent>>>; 2] = Default::default();
+        let dyn_events: [Arc<Mutex<Vec<ChunkEvent>>>; 2] = Default::default();
+        let tuple = || (RecordingExtension("first", tuple_events[0].clone()), RecordingExtension("second", tuple_events[1].clone()));
+        let dyn_set = || DynExtensionSet::new()
+            .with(RecordingExtension("first", dyn_events[0].clone()))
+            .with(RecordingExtension("second", dyn_events[1].clone()));
+
+        for (index, events) in [&tuple_events, &dyn_events].into_iter().enumerate() {
+            let result = if index == 0 { download(&server, &save_dir, tuple()).await } else { download(&server, &save_dir, dyn_set()).await };
+            assert_eq!(result.unwrap(), DownloadingEndCause::DownloadFinished);
+            assert_eq!(std::fs::read(save_dir.join("file.bin")).unwrap(), *data);
+            for events in events {
+                check_finished_events(&events.lock(), data.len() as u64);
+                events.lock().clear();
+            }
+        }
+        // 钩子修改的请求按扩展的添加顺序发出，首次请求不是 chunk 请求
+        let requests = server.requests();
+        let chunk_requests: Vec<_> = requests.iter().filter(|n| n.header("x-hook").is_some()).collect();
+        assert_eq!(chunk_requests.len(), 2 * 9);
+        for request in chunk_requests {
+            let hooks: Vec<_> = request.headers.iter().filter(|(n, _)| n == "x-hook").map(|(_, v)| v.as_str()).collect();
+            assert_eq!(hooks, ["first", "second"]);
+        }
+
+        // 失败的 chunk 重试后只调用一次 on_chunk_failed，不会调用 on_chunk_finished
+        fail.store(true, Ordering::SeqCst);
+        for (index, events) in [&tuple_events, &dyn_events].into_iter().enumerate() {
+            std::fs::remove_dir_all(&save_dir).unwrap();
+            let result = if index == 0 { download(&server, &save_dir, tuple()).await } else { download(&server, &save_dir, dyn_set()).await };
+            assert!(matches!(result, Err(DownloadError::HttpRequestFailed(_))));
+            for events in events {
+                let events = events.lock();
+                assert_eq!(count(&events, |n| *n == ChunkEvent::Request(4)), 2);
+                assert_eq!(count(&events, |n| *n == ChunkEvent::Failed(4)), 1);
+                assert_eq!(count(&events, |n| matches!(n, ChunkEvent::Failed(_))), 1);
+                assert_eq!(count(&events, |n| matches!(n, ChunkEvent::Finished(4, ..))), 0);
+                // 其他 chunk 完成时各调用一次
+                for index in 1..=9 {
+                    assert!(count(&events, |n| matches!(*n, ChunkEvent::Finished(i, ..) if i == index)) <= 1);
+                }
+            }
+        }
+        std::fs::remove_dir_all(&save_dir).unwrap();
+    }
+}
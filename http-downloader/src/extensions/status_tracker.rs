@@ -5,7 +5,7 @@ use futures_util::future::BoxFuture;
 use futures_util::FutureExt;
 use tokio::{select, sync};
 
-use crate::{DownloaderWrapper, DownloadExtensionBuilder, DownloadFuture, DownloadingEndCause, DownloadingState, DownloadStartError, HttpFileDownloader};
+use crate::{DownloaderWrapper, DownloadExtensionBuilder, DownloadFuture, DownloadingEndCause, DownloadingState, DownloadStartError, HttpFileDownloader, ResumeState};
 
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum NetworkItemPendingType {
@@ -54,12 +54,18 @@ impl DownloadStatusSender {
 pub struct DownloadStatusTrackerState {
     pub status_sender: Arc<DownloadStatusSender>,
     pub status_receiver: sync::watch::Receiver<DownloaderStatus>,
+    // 最近一次开始下载时断点续传数据的使用情况，开始下载前为 None
+    pub resume_state_receiver: sync::watch::Receiver<Option<ResumeState>>,
 }
 
 impl DownloadStatusTrackerState {
     pub fn status(&self) -> DownloaderStatus {
         self.status_receiver.borrow().clone()
     }
+
+    pub fn resume_state(&self) -> Option<ResumeState> {
+        self.resume_state_receiver.borrow().clone()
+    }
 }
 
 pub struct DownloadStatusTrackerExtension {
@@ -77,6 +83,7 @@ impl DownloadStatusTrackerExtension {
 pub struct DownloadStatusDownloaderWrapper {
     pub status_sender: Arc<DownloadStatusSender>,
     status_receiver: sync::watch::Receiver<DownloaderStatus>,
+    resume_state_sender: Arc<sync::watch::Sender<Option<ResumeState>>>,
     downloading_state_receiver: Option<sync::oneshot::Receiver<Arc<DownloadingState>>>,
 }
 
@@ -92,6 +99,7 @@ impl DownloadExtensionBuilder for DownloadStatusTrackerExtension {
 
     fn build(self, _downloader: &mut HttpFileDownloader) -> (Self::Wrapper, Self::ExtensionState) where Self: Sized {
         let (status_sender, status_receiver) = sync::watch::channel(DownloaderStatus::NoStart);
+        let (resume_state_sender, resume_state_receiver) = sync::watch::channel(None);
         let status_sender = Arc::new(DownloadStatusSender {
             log: self.log,
             status_sender,
@@ -100,11 +108,13 @@ impl DownloadExtensionBuilder for DownloadStatusTrackerExtension {
             DownloadStatusDownloaderWrapper {
                 status_receiver: status_receiver.clone(),
                 status_sender: status_sender.clone(),
+                resume_state_sender: Arc::new(resume_state_sender),
                 downloading_state_receiver: None,
             },
             DownloadStatusTrackerState {
                 status_receiver,
                 status_sender,
+                resume_state_receiver,
             },
         )
     }
@@ -155,9 +165,13 @@ impl DownloaderWrapper for DownloadStatusDownloaderWrapper {
         let download_way_receiver = self.downloading_state_receiver.take().unwrap();
 
         let status_sender = self.status_sender.clone();
+        let resume_state_sender = self.resume_state_sender.clone();
         Ok(async move {
             select! {
-                _ = download_way_receiver => {
+                downloading_state = download_way_receiver => {
+                    if let Ok(downloading_state) = downloading_state {
+                        resume_state_sender.send_replace(Some(downloading_state.resume_state.clone()));
+                    }
                     status_sender.change_status(DownloaderStatus::Running);
                 },
                 r = (&mut download_future) =>{
@@ -0,0 +1,601 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Result;
+use futures_util::FutureExt;
+use md4::{Digest, Md4};
+use sha1::Sha1;
+use thiserror::Error;
+use url::Url;
+
+use crate::{ChunkData, ChunkRange, DownloadArchiveData, DownloadError, DownloaderWrapper, DownloadExtensionBuilder, DownloadFuture, DownloadingEndCause, DownloadStartError, HttpFileDownloader, RemainingChunks};
+use crate::exclusive::Exclusive;
+
+#[derive(Error, Debug)]
+pub enum ZsyncError {
+    #[error("http request failed，{:?}", .0)]
+    HttpRequestFailed(#[from] reqwest::Error),
+    #[error("invalid zsync control file: {}", .0)]
+    InvalidControlFile(String),
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ZsyncBlockChecksum {
+    // 只保留了 rsum_bytes 个低位字节
+    pub rsum: u32,
+    // 只有前 checksum_bytes 个字节有效
+    pub checksum: [u8; 16],
+}
+
+/// zsync 控制文件（`.zsync`）
+#[derive(Debug, Clone)]
+pub struct ZsyncControl {
+    pub file_name: Option<String>,
+    pub mtime: Option<String>,
+    pub block_size: usize,
+    pub length: u64,
+    pub seq_matches: u8,
+    pub rsum_bytes: u8,
+    pub checksum_bytes: u8,
+    pub urls: Vec<String>,
+    pub sha1: Option<String>,
+    pub blocks: Vec<ZsyncBlockChecksum>,
+}
+
+impl ZsyncControl {
+    pub async fn load(client: &reqwest::Client, url: Url) -> Result<Self, ZsyncError> {
+        let bytes = client.get(url).send().await?.error_for_status()?.bytes().await?;
+        Self::parse(&bytes)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, ZsyncError> {
+        let invalid = |msg: &str| ZsyncError::InvalidControlFile(msg.to_string());
+
+        let mut position = 0;
+        let mut headers = Vec::new();
+        loop {
+            let line_end = data[position..]
+                .iter()
+                .position(|n| *n == b'\n')
+                .ok_or_else(|| invalid("header is not terminated"))?;
+            let line = std::str::from_utf8(&data[position..position + line_end])
+                .map_err(|_| invalid("header is not utf-8"))?
+                .trim_end_matches('\r');
+            position += line_end + 1;
+            if line.is_empty() {
+                break;
+            }
+            let (key, value) = line.split_once(':').ok_or_else(|| invalid("malformed header line"))?;
+            headers.push((key.trim().to_string(), value.trim().to_string()));
+        }
+
+        let header = |name: &str| headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+        if header("Z-URL").is_some() && header("URL").is_none() {
+            return Err(invalid("compressed targets (Z-URL) are not supported"));
+        }
+        let block_size: usize = header("Blocksize")
+            .and_then(|n| n.parse().ok())
+            .filter(|n| *n > 0)
+            .ok_or_else(|| invalid("missing Blocksize"))?;
+        let length: u64 = header("Length")
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| invalid("missing Length"))?;
+        let (seq_matches, rsum_bytes, checksum_bytes) = match header("Hash-Lengths") {
+            None => (1, 4, 16),
+            Some(value) => {
+                let values: Vec<u8> = value
+                    .split(',')
+                    .map(|n| n.trim().parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| invalid("malformed Hash-Lengths"))?;
+                match values[..] {
+                    [seq_matches, rsum_bytes, checksum_bytes]
+                    if (1..=2).contains(&seq_matches) && (1..=4).contains(&rsum_bytes) && (3..=16).contains(&checksum_bytes) =>
+                        (seq_matches, rsum_bytes, checksum_bytes),
+                    _ => return Err(invalid("malformed Hash-Lengths")),
+                }
+            }
+        };
+
+        let stride = rsum_bytes as usize + checksum_bytes as usize;
+        let (block_count, checksums_len) = usize::try_from(length.div_ceil(block_size as u64))
+            .ok()
+            .and_then(|block_count| Some((block_count, block_count.checked_mul(stride)?)))
+            .ok_or_else(|| invalid("Length is too large"))?;
+        let body = &data[position..];
+        if body.len() < checksums_len {
+            return Err(invalid("block checksums are truncated"));
+        }
+        let blocks = body
+            .chunks_exact(stride)
+            .take(block_count)
+            .map(|item| {
+                let (rsum, checksum) = item.split_at(rsum_bytes as usize);
+                let mut rsum_be = [0; 4];
+                rsum_be[4 - rsum.len()..].copy_from_slice(rsum);
+                let mut checksum_bytes = [0; 16];
+                checksum_bytes[..checksum.len()].copy_from_slice(checksum);
+                ZsyncBlockChecksum {
+                    rsum: u32::from_be_bytes(rsum_be),
+                    checksum: checksum_bytes,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            file_name: header("Filename").map(|n| n.to_string()),
+            mtime: header("MTime").map(|n| n.to_string()),
+            block_size,
+            length,
+            seq_matches,
+            rsum_bytes,
+            checksum_bytes,
+            urls: headers.iter().filter(|(key, _)| key == "URL").map(|(_, value)| value.clone()).collect(),
+            sha1: header("SHA-1").map(|n| n.to_ascii_lowercase()),
+            blocks,
+        })
+    }
+
+    /// 目标文件地址，相对地址基于控制文件的地址
+    pub fn target_url(&self, zsync_url: &Url) -> Option<Url> {
+        self.urls.iter().find_map(|n| zsync_url.join(n).ok())
+    }
+
+    fn rsum_mask(&self) -> u32 {
+        match self.rsum_bytes {
+            4 => u32::MAX,
+            n => (1 << (8 * n as u32)) - 1,
+        }
+    }
+
+    fn block_len(&self, index: usize) -> usize {
+        (self.length - (index * self.block_size) as u64).min(self.block_size as u64) as usize
+    }
+
+    fn is_checksum_match(&self, index: usize, block: &[u8]) -> bool {
+        let checksum_bytes = self.checksum_bytes as usize;
+        Md4::digest(block)[..checksum_bytes] == self.blocks[index].checksum[..checksum_bytes]
+    }
+
+    /// 从旧文件中找出与目标文件相同的块并写入目标文件，返回每个块是否已找到
+    pub fn seed(&self, seed_file: &Path, target_file: &Path) -> io::Result<Vec<bool>> {
+        if seed_file.canonicalize().ok() == target_file.canonicalize().ok() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the seed file cannot be the target file"));
+        }
+        let block_size = self.block_size;
+        let rsum_mask = self.rsum_mask();
+        let mut found = vec![false; self.blocks.len()];
+        let mut block_index: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (index, block) in self.blocks.iter().enumerate() {
+            block_index.entry(block.rsum).or_default().push(index);
+        }
+
+        let mut target = fs::OpenOptions::new().create(true).write(true).truncate(false).open(target_file)?;
+        target.set_len(self.length)?;
+        let mut seed = match fs::File::open(seed_file) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(found),
+            Err(err) => return Err(err),
+        };
+
+        // 缓冲区中至少要有两个块，用于 seq_matches 检查，文件末尾补零，以便匹配最后一个不完整的块
+        let read_len = (block_size * 16).max(1024 * 1024);
+        let mut buffer: Vec<u8> = Vec::with_capacity(read_len + block_size * 2);
+        let mut is_eof = false;
+        let mut position = 0;
+        let mut rsum: Option<Rsum> = None;
+        loop {
+            if !is_eof && position + block_size * 2 > buffer.len() {
+                buffer.drain(..position);
+                position = 0;
+                let start = buffer.len();
+                buffer.resize(start + read_len, 0);
+                let mut len = 0;
+                while len < read_len {
+                    match seed.read(&mut buffer[start + len..])? {
+                        0 => {
+                            is_eof = true;
+                            break;
+                        }
+                        n => len += n,
+                    }
+                }
+                buffer.truncate(start + len);
+                if is_eof {
+                    buffer.resize(buffer.len() + block_size * 2, 0);
+                }
+                continue;
+            }
+            // 窗口起始位置不能在补齐的零中
+            let remaining = if is_eof { buffer.len() - block_size * 2 } else { buffer.len() };
+            if position >= remaining {
+                break;
+            }
+            let window = &buffer[position..position + block_size];
+            let current = *rsum.get_or_insert_with(|| Rsum::new(window));
+
+            let mut is_matched = false;
+            if let Some(candidates) = block_index.get(&(current.value() & rsum_mask)) {
+                let next_window = &buffer[position + block_size..position + block_size * 2];
+                for &index in candidates {
+                    if found[index] || !self.is_checksum_match(index, window) {
+                        continue;
+                    }
+                    // 要求后续的块也能匹配，以减少短校验和的误匹配
+                    let next_index = index + 1;
+                    let check_next = self.seq_matches > 1 && next_index < self.blocks.len();
+                    if check_next
+                        && (Rsum::new(next_window).value() & rsum_mask != self.blocks[next_index].rsum
+                        || !self.is_checksum_match(next_index, next_window))
+                    {
+                        continue;
+                    }
+                    is_matched = true;
+                    found[index] = true;
+                    target.seek(SeekFrom::Start((index * block_size) as u64))?;
+                    target.write_all(&window[..self.block_len(index)])?;
+                    if check_next && !found[next_index] {
+                        found[next_index] = true;
+                        target.write_all(&next_window[..self.block_len(next_index)])?;
+                    }
+                }
+            }
+
+            if is_matched {
+                position += block_size;
+                rsum = None;
+            } else {
+                let old = buffer[position];
+                let new = buffer[position + block_size];
+                position += 1;
+                rsum = Some(current.roll(old, new, block_size));
+            }
+        }
+        target.flush()?;
+        Ok(found)
+    }
+
+    /// 未找到的块合并后的范围
+    pub fn missing_ranges(&self, found: &[bool]) -> Vec<ChunkRange> {
+        let mut ranges: Vec<ChunkRange> = Vec::new();
+        for (index, _) in found.iter().enumerate().filter(|(_, found)| !**found) {
+            let start = (index * self.block_size) as u64;
+            let end = start + self.block_len(index) as u64 - 1;
+            match ranges.last_mut() {
+                Some(last) if last.end + 1 == start => last.end = end,
+                _ => ranges.push(ChunkRange::new(start, end)),
+            }
+        }
+        ranges
+    }
+}
+
+// zsync 的弱校验和，a 为字节和，b 为加权和
+#[derive(Debug, Copy, Clone)]
+struct Rsum {
+    a: u16,
+    b: u16,
+}
+
+impl Rsum {
+    fn new(data: &[u8]) -> Self {
+        let mut a: u16 = 0;
+        let mut b: u16 = 0;
+        for &n in data {
+            a = a.wrapping_add(n as u16);
+            b = b.wrapping_add(a);
+        }
+        Self { a, b }
+    }
+
+    fn roll(self, old: u8, new: u8, block_size: usize) -> Self {
+        let a = self.a.wrapping_sub(old as u16).wrapping_add(new as u16);
+        let b = self
+            .b
+            .wrapping_sub((old as u16).wrapping_mul(block_size as u16))
+            .wrapping_add(a);
+        Self { a, b }
+    }
+
+    fn value(&self) -> u32 {
+        ((self.a as u32) << 16) | self.b as u32
+    }
+}
+
+fn file_sha1(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha1::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        let len = file.read(&mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buffer[..len]);
+    }
+    Ok(hasher.finalize().iter().map(|n| format!("{:02x}", n)).collect())
+}
+
+/// zsync 增量下载，从旧文件中复用相同的块，只下载不同的部分，完成后校验 SHA-1
+///
+/// 与断点续传扩展一起使用时需要放在其后面，存在断点续传数据时不再从旧文件中复用
+pub struct DownloadZsyncExtension {
+    pub control: Arc<ZsyncControl>,
+    pub seed_file: PathBuf,
+}
+
+impl DownloadZsyncExtension {
+    pub fn new(control: Arc<ZsyncControl>, seed_file: PathBuf) -> Self {
+        Self {
+            control,
+            seed_file,
+        }
+    }
+}
+
+pub struct DownloadZsyncState {
+    // 从旧文件中复用的长度
+    pub reused_len: Arc<AtomicU64>,
+}
+
+pub struct DownloadZsyncDownloaderWrapper {
+    control: Arc<ZsyncControl>,
+    seed_file: PathBuf,
+    reused_len: Arc<AtomicU64>,
+}
+
+impl DownloadExtensionBuilder for DownloadZsyncExtension {
+    type Wrapper = DownloadZsyncDownloaderWrapper;
+    type ExtensionState = DownloadZsyncState;
+
+    fn build(self, _downloader: &mut HttpFileDownloader) -> (Self::Wrapper, Self::ExtensionState) where Self: Sized {
+        let reused_len = Arc::new(AtomicU64::new(0));
+        (
+            DownloadZsyncDownloaderWrapper {
+                control: self.control,
+                seed_file: self.seed_file,
+                reused_len: reused_len.clone(),
+            },
+            DownloadZsyncState {
+                reused_len,
+            },
+        )
+    }
+}
+
+impl DownloaderWrapper for DownloadZsyncDownloaderWrapper {
+    fn prepare_download(&mut self, downloader: &mut HttpFileDownloader) -> Result<(), DownloadStartError> {
+        let previous_archive_data_future = downloader.archive_data_future.take();
+        let control = self.control.clone();
+        let seed_file = self.seed_file.clone();
+        let target_file = downloader.config.file_path();
+        let chunk_size = downloader.config.chunk_size.get();
+        let reused_len = self.reused_len.clone();
+        downloader.archive_data_future = Some(Exclusive::new(async move {
+            if let Some(previous_archive_data_future) = previous_archive_data_future {
+                if let Some(archive_data) = previous_archive_data_future.await? {
+                    return Ok(Some(archive_data));
+                }
+            }
+            let found = {
+                let control = control.clone();
+                tokio::task::spawn_blocking(move || control.seed(&seed_file, &target_file)).await??
+            };
+            let ranges = control.missing_ranges(&found);
+            let missing_len: u64 = ranges.iter().map(|n| n.len()).sum();
+            reused_len.store(control.length - missing_len, Ordering::Relaxed);
+            #[cfg(feature = "tracing")]
+            tracing::info!("zsync reused {} bytes, {} bytes to download", control.length - missing_len, missing_len);
+            Ok(Some(Box::new(DownloadArchiveData {
+                etag: None,
+                downloaded_len: control.length - missing_len,
+                downloading_duration: 0,
+                chunk_data: Some(ChunkData {
+                    iter_count: 0,
+                    remaining: RemainingChunks {
+                        chunk_size,
+                        ranges,
+                    },
+                    last_incomplete_chunks: Default::default(),
+                    chunk_hashes: Default::default(),
+                }),
+                content_length: Some(control.length),
+            })))
+        }.boxed()));
+        Ok(())
+    }
+
+    fn download(&mut self, downloader: &mut HttpFileDownloader, download_future: DownloadFuture) -> Result<DownloadFuture, DownloadStartError> {
+        let control = self.control.clone();
+        let file_path = downloader.config.file_path();
+        Ok(async move {
+            let end_cause = download_future.await?;
+            if end_cause != DownloadingEndCause::DownloadFinished {
+                return Ok(end_cause);
+            }
+            if let Some(sha1) = control.sha1.clone() {
+                let file_sha1 = tokio::task::spawn_blocking(move || file_sha1(&file_path)).await??;
+                if file_sha1 != sha1 {
+                    return Err(DownloadError::Other(anyhow::anyhow!("zsync sha1 mismatch, expected {}, got {}", sha1, file_sha1)));
+                }
+            }
+            Ok(end_cause)
+        }.boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 按 zsyncmake 0.6.2 的生成逻辑（make.c）独立生成，块校验和由 OpenSSL 的 MD4 计算，目标文件为 `lcg_data(7, 5000)`
+    const ZSYNCMAKE_CONTROL: &str = concat!(
+        "7a73796e633a20302e362e320a46696c656e616d653a206e65772e62696e0a4d54696d653a205361742c203137204f63",
+        "7420323032362031323a30303a3030202b303030300a426c6f636b73697a653a20313032340a4c656e6774683a203530",
+        "30300a486173682d4c656e677468733a20322c322c330a55524c3a206e65772e62696e0a5348412d313a203939383834",
+        "34656633623234366662656366393264373330306438633035383232383437376630610a0a825ee4846ca41f0d95a299",
+        "8aead7fbd3c5ca7508ca1c6b1fa1",
+    );
+
+    fn lcg_data(mut seed: u32, len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn hex_decode(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn build_control(data: &[u8], block_size: usize) -> ZsyncControl {
+        let mut control = format!(
+            "zsync: 0.6.2\nFilename: new.bin\nBlocksize: {}\nLength: {}\nHash-Lengths: 2,2,5\nURL: new.bin\nSHA-1: {}\n\n",
+            block_size,
+            data.len(),
+            Sha1::digest(data).iter().map(|n| format!("{:02x}", n)).collect::<String>()
+        )
+            .into_bytes();
+        for block in data.chunks(block_size) {
+            let mut block = block.to_vec();
+            block.resize(block_size, 0);
+            control.extend(&Rsum::new(&block).value().to_be_bytes()[2..]);
+            control.extend(&Md4::digest(&block)[..5]);
+        }
+        ZsyncControl::parse(&control).unwrap()
+    }
+
+    #[test]
+    fn rolling_rsum() {
+        let data: Vec<u8> = (0..200u32).map(|n| (n * 7 % 251) as u8).collect();
+        let mut rsum = Rsum::new(&data[..64]);
+        for start in 1..=(data.len() - 64) {
+            rsum = rsum.roll(data[start - 1], data[start + 63], 64);
+            assert_eq!(rsum.value(), Rsum::new(&data[start..start + 64]).value());
+        }
+    }
+
+    #[test]
+    fn seed_from_shifted_file() {
+        let block_size = 64;
+        let new = lcg_data(1, 1000);
+        let control = build_control(&new, block_size);
+        assert_eq!(control.blocks.len(), 16);
+        assert_eq!(control.target_url(&Url::parse("http://localhost/a/new.bin.zsync").unwrap()).unwrap().as_str(), "http://localhost/a/new.bin");
+
+        // 旧文件在开头插入了数据，且最后 300 字节不同
+        let mut old = b"inserted".to_vec();
+        old.extend(&new[..700]);
+        old.extend(vec![0xAA; 300]);
+
+        let dir = std::env::temp_dir().join(format!("http-downloader-zsync-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (seed_file, target_file) = (dir.join("old.bin"), dir.join("new.bin"));
+        fs::write(&seed_file, &old).unwrap();
+        let _ = fs::remove_file(&target_file);
+
+        let found = control.seed(&seed_file, &target_file).unwrap();
+        // 700 / 64 = 10 个完整的块
+        assert_eq!(found.iter().filter(|n| **n).count(), 10);
+        let ranges = control.missing_ranges(&found);
+        assert_eq!(ranges.len(), 1);
+        assert_eq!((ranges[0].start, ranges[0].end), (640, 999));
+
+        let target = fs::read(&target_file).unwrap();
+        assert_eq!(target.len(), new.len());
+        assert_eq!(target[..640], new[..640]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn seed_with_zsyncmake_control_file() {
+        let control = ZsyncControl::parse(&hex_decode(ZSYNCMAKE_CONTROL)).unwrap();
+        assert_eq!((control.block_size, control.length), (1024, 5000));
+        assert_eq!((control.seq_matches, control.rsum_bytes, control.checksum_bytes), (2, 2, 3));
+        assert_eq!(control.blocks.len(), 5);
+        assert_eq!(control.mtime.as_deref(), Some("Sat, 17 Oct 2026 12:00:00 +0000"));
+
+        let new = lcg_data(7, 5000);
+        let mut old = new.clone();
+        old[..100].fill(0);
+
+        let dir = std::env::temp_dir().join(format!("http-downloader-zsyncmake-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (seed_file, target_file) = (dir.join("old.bin"), dir.join("new.bin"));
+        fs::write(&seed_file, &old).unwrap();
+        let _ = fs::remove_file(&target_file);
+
+        let found = control.seed(&seed_file, &target_file).unwrap();
+        assert_eq!(found, vec![false, true, true, true, true]);
+        let ranges = control.missing_ranges(&found);
+        assert_eq!(ranges.iter().map(|n| (n.start, n.end)).collect::<Vec<_>>(), vec![(0, 1023)]);
+
+        let mut target = fs::read(&target_file).unwrap();
+        target[..1024].copy_from_slice(&new[..1024]);
+        fs::write(&target_file, &target).unwrap();
+        assert_eq!(file_sha1(&target_file).unwrap(), control.sha1.clone().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn download_missing_ranges_from_server() {
+        use std::num::{NonZeroU8, NonZeroUsize};
+
+        use crate::{DownloadingEndCause, HttpDownloaderBuilder};
+        use crate::test_server::{TestResponse, TestServer};
+
+        let block_size = 1024;
+        let new = Arc::new(lcg_data(3, 64 * 1024 + 100));
+        let control = Arc::new(build_control(&new, block_size));
+        // 旧文件在开头插入了数据，第 10 块和第 40、41 块被修改，末尾不完整的块缺失
+        let mut old = b"inserted".to_vec();
+        old.extend(&new[..64 * 1024]);
+        old[8 + 10 * 1024..8 + 11 * 1024].fill(0x55);
+        old[8 + 40 * 1024..8 + 42 * 1024].fill(0x55);
+
+        let dir = std::env::temp_dir().join(format!("http-downloader-zsync-server-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let seed_file = dir.join("old.bin");
+        fs::write(&seed_file, &old).unwrap();
+
+        let server = TestServer::start({
+            let new = new.clone();
+            move |request| TestResponse::ranged(request, &new)
+        }).await;
+        let (mut downloader, (state, ..)) = HttpDownloaderBuilder::new(server.url("/new.bin"), dir.clone())
+            .chunk_size(NonZeroUsize::new(4096).unwrap())
+            .download_connection_count(NonZeroU8::new(2).unwrap())
+            .build((DownloadZsyncExtension::new(control.clone(), seed_file),));
+        let end_cause = downloader.prepare_download().unwrap().await.unwrap();
+        assert_eq!(end_cause, DownloadingEndCause::DownloadFinished);
+
+        // seq_matches 为 2，第 39 块后面的块不同，也需要下载
+        let missing: Vec<_> = ["bytes=10240-11263", "bytes=39936-43007", "bytes=65536-65635"].map(String::from).into();
+        let requests = server.requests();
+        let mut ranges: Vec<_> = requests.iter().filter_map(|n| n.header("range").map(String::from)).collect();
+        ranges.sort();
+        assert_eq!(ranges, missing);
+        // 除了获取文件信息的请求，没有其他请求
+        assert_eq!(requests.len(), missing.len() + 1);
+        assert_eq!(state.reused_len.load(Ordering::Relaxed), new.len() as u64 - 1024 - 3072 - 100);
+        let target_file = dir.join("new.bin");
+        assert_eq!(fs::read(&target_file).unwrap(), *new);
+        assert_eq!(file_sha1(&target_file).unwrap(), control.sha1.clone().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn oversized_length_is_rejected() {
+        let control = b"zsync: 0.6.2\nBlocksize: 1\nLength: 18446744073709551615\nHash-Lengths: 2,4,16\nURL: a\n\n";
+        assert!(matches!(ZsyncControl::parse(control), Err(ZsyncError::InvalidControlFile(_))));
+    }
+}
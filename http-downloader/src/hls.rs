@@ -0,0 +1,682 @@
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use aes::cipher::{BlockDecryptMut, KeyIvInit};
+use aes::cipher::block_padding::Pkcs7;
+use futures_util::future::{BoxFuture, OptionFuture};
+use futures_util::{StreamExt, TryStreamExt};
+use reqwest::header::HeaderMap;
+use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+use tokio::task::JoinError;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use crate::{ChunkRange, DownloadedLenChangeNotify, DownloadError, DownloadingEndCause, DownloadStartError, HttpDownloaderBuilder};
+
+// 嵌套的多码率列表最多解析几层
+const MAX_MASTER_PLAYLIST_DEPTH: usize = 4;
+
+#[derive(Error, Debug)]
+pub enum HlsError {
+    #[error("http request failed，{:?}", .0)]
+    HttpRequestFailed(#[from] reqwest::Error),
+    #[error("IoError，{:?}", .0)]
+    IoError(#[from] std::io::Error),
+    #[error("JoinError，{:?}", .0)]
+    JoinError(#[from] JoinError),
+    #[error("invalid playlist: {}", .0)]
+    InvalidPlaylist(String),
+    #[error("the master playlist has no variant")]
+    NoVariant,
+    #[error("unsupported encryption method {}", .0)]
+    UnsupportedEncryption(String),
+    #[error("invalid AES-128 key, {} bytes", .0)]
+    InvalidKey(usize),
+    #[error("decrypt segment {} failed", .0)]
+    DecryptFailed(usize),
+    #[error("{:?}", .0)]
+    DownloadStartError(#[from] DownloadStartError),
+    #[error("{:?}", .0)]
+    DownloadError(Box<DownloadError>),
+    #[error("download cancelled")]
+    Cancelled,
+}
+
+impl From<DownloadError> for HlsError {
+    fn from(value: DownloadError) -> Self {
+        HlsError::DownloadError(Box::new(value))
+    }
+}
+
+/// 多码率列表中的一个码率
+#[derive(Debug, Clone, PartialEq)]
+pub struct HlsVariant {
+    pub url: Url,
+    pub bandwidth: Option<u64>,
+    pub resolution: Option<(u32, u32)>,
+    pub codecs: Option<String>,
+}
+
+/// `METHOD=AES-128` 的密钥信息，未指定 IV 时使用分片序号
+#[derive(Debug, Clone, PartialEq)]
+pub struct HlsKey {
+    pub url: Url,
+    pub iv: Option<[u8; 16]>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HlsSegment {
+    pub url: Url,
+    pub duration: f64,
+    // 媒体序号，用作默认 IV
+    pub sequence: u64,
+    pub byte_range: Option<ChunkRange>,
+    pub key: Option<HlsKey>,
+    // fMP4 的初始化分片（EXT-X-MAP），不计入媒体序号
+    pub is_init: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HlsMediaPlaylist {
+    pub target_duration: Option<f64>,
+    pub media_sequence: u64,
+    pub segments: Vec<HlsSegment>,
+    // 没有 EXT-X-ENDLIST 的直播列表只下载当前列出的分片
+    pub end_list: bool,
+}
+
+impl HlsMediaPlaylist {
+    pub fn duration(&self) -> f64 {
+        self.segments.iter().map(|n| n.duration).sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HlsPlaylist {
+    Master(Vec<HlsVariant>),
+    Media(HlsMediaPlaylist),
+}
+
+impl HlsPlaylist {
+    /// 解析 m3u8 内容，相对地址基于 `url`
+    pub fn parse(url: &Url, content: &str) -> Result<Self, HlsError> {
+        let mut lines = content.lines().map(str::trim).filter(|n| !n.is_empty());
+        if lines.next().map(|n| n.trim_start_matches('\u{feff}')) != Some("#EXTM3U") {
+            return Err(HlsError::InvalidPlaylist("missing #EXTM3U".to_string()));
+        }
+        let join = |uri: &str| url.join(uri).map_err(|err| HlsError::InvalidPlaylist(format!("invalid uri {}, {}", uri, err)));
+
+        let mut variants = vec![];
+        let mut pending_variant: Option<HashMap<String, String>> = None;
+        let mut media = HlsMediaPlaylist {
+            target_duration: None,
+            media_sequence: 0,
+            segments: vec![],
+            end_list: false,
+        };
+        let mut is_media = false;
+        let mut key: Option<HlsKey> = None;
+        // 初始化分片使用声明它时的密钥，之后的 EXT-X-KEY 不作用于它
+        let mut map: Option<(Url, Option<ChunkRange>, Option<HlsKey>)> = None;
+        let mut last_map: Option<(Url, Option<ChunkRange>, Option<HlsKey>)> = None;
+        let mut duration: Option<f64> = None;
+        let mut byte_range: Option<ChunkRange> = None;
+        // 没有指定偏移的 EXT-X-BYTERANGE 接着上一个分片
+        let mut next_offset = 0;
+
+        for line in lines {
+            let Some(tag) = line.strip_prefix('#') else {
+                if let Some(attributes) = pending_variant.take() {
+                    variants.push(HlsVariant {
+                        url: join(line)?,
+                        bandwidth: attributes.get("BANDWIDTH").and_then(|n| n.parse().ok()),
+                        resolution: attributes.get("RESOLUTION").and_then(|n| {
+                            let (width, height) = n.split_once(['x', 'X'])?;
+                            Some((width.parse().ok()?, height.parse().ok()?))
+                        }),
+                        codecs: attributes.get("CODECS").cloned(),
+                    });
+                    continue;
+                }
+                let Some(segment_duration) = duration.take() else {
+                    return Err(HlsError::InvalidPlaylist(format!("uri without #EXTINF: {}", line)));
+                };
+                // 初始化分片变化时插入到这个分片前
+                if map.is_some() && map != last_map {
+                    let (url, byte_range, map_key) = map.clone().unwrap();
+                    media.segments.push(HlsSegment {
+                        url,
+                        duration: 0.0,
+                        sequence: media.media_sequence + media.segments.iter().filter(|n| !n.is_init).count() as u64,
+                        byte_range,
+                        key: map_key,
+                        is_init: true,
+                    });
+                    last_map = map.clone();
+                }
+                let sequence = media.media_sequence + media.segments.iter().filter(|n| !n.is_init).count() as u64;
+                media.segments.push(HlsSegment {
+                    url: join(line)?,
+                    duration: segment_duration,
+                    sequence,
+                    byte_range: byte_range.take(),
+                    key: key.clone(),
+                    is_init: false,
+                });
+                continue;
+            };
+            let (name, value) = tag.split_once(':').unwrap_or((tag, ""));
+            match name {
+                "EXT-X-STREAM-INF" => pending_variant = Some(parse_attributes(value)),
+                "EXT-X-TARGETDURATION" => {
+                    is_media = true;
+                    media.target_duration = value.parse().ok();
+                }
+                "EXT-X-MEDIA-SEQUENCE" => {
+                    media.media_sequence = value
+                        .parse()
+                        .map_err(|_| HlsError::InvalidPlaylist(format!("invalid media sequence {}", value)))?;
+                }
+                "EXTINF" => {
+                    is_media = true;
+                    let value = value.split(',').next().unwrap_or_default().trim();
+                    duration = Some(value.parse().map_err(|_| HlsError::InvalidPlaylist(format!("invalid duration {}", value)))?);
+                }
+                "EXT-X-BYTERANGE" => {
+                    let range = parse_byte_range(value, next_offset)?;
+                    next_offset = range.end + 1;
+                    byte_range = Some(range);
+                }
+                "EXT-X-KEY" => {
+                    let attributes = parse_attributes(value);
+                    key = match attributes.get("METHOD").map(String::as_str) {
+                        None | Some("NONE") => None,
+                        Some("AES-128") => {
+                            let uri = attributes
+                                .get("URI")
+                                .ok_or_else(|| HlsError::InvalidPlaylist("key without uri".to_string()))?;
+                            let iv = attributes.get("IV").map(|n| parse_iv(n)).transpose()?;
+                            Some(HlsKey { url: join(uri)?, iv })
+                        }
+                        Some(method) => return Err(HlsError::UnsupportedEncryption(method.to_string())),
+                    };
+                }
+                "EXT-X-MAP" => {
+                    let attributes = parse_attributes(value);
+                    let uri = attributes
+                        .get("URI")
+                        .ok_or_else(|| HlsError::InvalidPlaylist("map without uri".to_string()))?;
+                    let byte_range = attributes.get("BYTERANGE").map(|n| parse_byte_range(n, 0)).transpose()?;
+                    map = Some((join(uri)?, byte_range, key.clone()));
+                }
+                "EXT-X-ENDLIST" => media.end_list = true,
+                _ => {}
+            }
+        }
+
+        if !variants.is_empty() && !is_media {
+            return Ok(HlsPlaylist::Master(variants));
+        }
+        Ok(HlsPlaylist::Media(media))
+    }
+}
+
+/// 解析 `KEY=VALUE,KEY="quoted,value"` 形式的属性列表
+fn parse_attributes(value: &str) -> HashMap<String, String> {
+    let mut attributes = HashMap::new();
+    let mut rest = value;
+    while let Some((key, value)) = rest.split_once('=') {
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let (value, next) = quoted.split_once('"').unwrap_or((quoted, ""));
+                (value, next.split_once(',').map(|n| n.1).unwrap_or(""))
+            }
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        attributes.insert(key.trim().to_string(), value.to_string());
+        rest = next;
+    }
+    attributes
+}
+
+/// `<n>[@<o>]`，没有偏移时从 `default_offset` 开始
+fn parse_byte_range(value: &str, default_offset: u64) -> Result<ChunkRange, HlsError> {
+    let invalid = || HlsError::InvalidPlaylist(format!("invalid byte range {}", value));
+    let (len, offset) = match value.split_once('@') {
+        Some((len, offset)) => (len, offset.parse().map_err(|_| invalid())?),
+        None => (value, default_offset),
+    };
+    let len: u64 = len.parse().map_err(|_| invalid())?;
+    // 结束位置的下一个字节是下一个分片的默认偏移，也不能溢出
+    if len == 0 || offset.checked_add(len).is_none() {
+        return Err(invalid());
+    }
+    Ok(ChunkRange::from_len(offset, len))
+}
+
+fn parse_iv(value: &str) -> Result<[u8; 16], HlsError> {
+    let invalid = || HlsError::InvalidPlaylist(format!("invalid iv {}", value));
+    let hex = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")).ok_or_else(invalid)?;
+    // 先检查字符，非 ASCII 字符会使下面按字节切分字符串时 panic
+    if hex.is_empty() || hex.len() > 32 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let hex = format!("{:0>32}", hex);
+    let mut iv = [0u8; 16];
+    for (index, byte) in iv.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(iv)
+}
+
+/// 分片下载选项
+pub struct HlsDownloadOptions {
+    // 同时下载的分片数
+    pub concurrency: NonZeroUsize,
+    pub cancel_token: Option<CancellationToken>,
+    // 接收下载长度，可以传入 `DefaultSpeedLimiter` 限速
+    pub downloaded_len_change_notify: Option<Arc<dyn DownloadedLenChangeNotify>>,
+    // 配置每个分片的下载器，比如重试次数
+    pub configure: Option<Arc<dyn Fn(HttpDownloaderBuilder) -> HttpDownloaderBuilder + Send + Sync>>,
+}
+
+impl Default for HlsDownloadOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: NonZeroUsize::new(4).unwrap(),
+            cancel_token: None,
+            downloaded_len_change_notify: None,
+            configure: None,
+        }
+    }
+}
+
+/// HLS 下载进度
+#[derive(Debug, Default)]
+pub struct HlsProgress {
+    pub segment_count: AtomicUsize,
+    pub completed_segment_count: AtomicUsize,
+    // 本次接收的字节数，不包括之前已完成的分片
+    pub downloaded_len: AtomicU64,
+}
+
+struct SegmentLenNotify {
+    progress: Arc<HlsProgress>,
+    inner: Option<Arc<dyn DownloadedLenChangeNotify>>,
+}
+
+impl DownloadedLenChangeNotify for SegmentLenNotify {
+    fn receive_len(&self, len: usize) -> OptionFuture<BoxFuture<'_, ()>> {
+        self.progress.downloaded_len.fetch_add(len as u64, Ordering::Relaxed);
+        match self.inner.as_ref() {
+            None => None.into(),
+            Some(inner) => inner.receive_len(len),
+        }
+    }
+}
+
+/// 解析 m3u8 并下载全部分片，解密后按顺序合并为一个文件
+///
+/// 分片保存在 `<文件名>.segments` 目录中，重新下载时跳过已完成的分片，合并后删除
+pub struct HlsDownloader {
+    client: reqwest::Client,
+    header_map: HeaderMap,
+    url: Url,
+    variants: Vec<HlsVariant>,
+    playlist: HlsMediaPlaylist,
+    progress: Arc<HlsProgress>,
+}
+
+impl HlsDownloader {
+    /// 打开 m3u8，多码率列表选择带宽最高的码率
+    pub async fn open(client: reqwest::Client, url: Url, header_map: HeaderMap) -> Result<Self, HlsError> {
+        Self::open_with(client, url, header_map, |variants| {
+            variants
+                .iter()
+                .enumerate()
+                .max_by_key(|(_, n)| n.bandwidth.unwrap_or(0))
+                .map(|(index, _)| index)
+        })
+            .await
+    }
+
+    /// 同 `open`，由 `select_variant` 选择多码率列表中的码率
+    pub async fn open_with(
+        client: reqwest::Client,
+        url: Url,
+        header_map: HeaderMap,
+        mut select_variant: impl FnMut(&[HlsVariant]) -> Option<usize>,
+    ) -> Result<Self, HlsError> {
+        let mut url = url;
+        let mut variants = vec![];
+        for _ in 0..MAX_MASTER_PLAYLIST_DEPTH {
+            let response = client.get(url.clone()).headers(header_map.clone()).send().await?.error_for_status()?;
+            // 以重定向后的地址解析相对地址
+            let playlist_url = response.url().clone();
+            match HlsPlaylist::parse(&playlist_url, &response.text().await?)? {
+                HlsPlaylist::Master(master_variants) => {
+                    let index = select_variant(&master_variants).ok_or(HlsError::NoVariant)?;
+                    url = master_variants.get(index).ok_or(HlsError::NoVariant)?.url.clone();
+                    variants = master_variants;
+                }
+                HlsPlaylist::Media(playlist) => {
+                    let progress = HlsProgress::default();
+                    progress.segment_count.store(playlist.segments.len(), Ordering::Relaxed);
+                    return Ok(Self {
+                        client,
+                        header_map,
+                        url: playlist_url,
+                        variants,
+                        playlist,
+                        progress: Arc::new(progress),
+                    });
+                }
+            }
+        }
+        Err(HlsError::InvalidPlaylist("too many nested master playlists".to_string()))
+    }
+
+    /// 媒体列表地址
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// 多码率列表中的全部码率，直接打开媒体列表时为空
+    pub fn variants(&self) -> &[HlsVariant] {
+        &self.variants
+    }
+
+    pub fn playlist(&self) -> &HlsMediaPlaylist {
+        &self.playlist
+    }
+
+    pub fn progress(&self) -> Arc<HlsProgress> {
+        self.progress.clone()
+    }
+
+    /// 下载并合并到 `save_dir` 中的 `file_name`
+    pub async fn download(&self, save_dir: PathBuf, file_name: String, options: HlsDownloadOptions) -> Result<PathBuf, HlsError> {
+        let file_path = save_dir.join(&file_name);
+        let segments_dir = save_dir.join(format!("{}.segments", file_name));
+        tokio::fs::create_dir_all(&segments_dir).await?;
+
+        let keys = self.fetch_keys().await?;
+        let cancel_token = options.cancel_token.clone().unwrap_or_default();
+        let notify: Arc<dyn DownloadedLenChangeNotify> = Arc::new(SegmentLenNotify {
+            progress: self.progress.clone(),
+            inner: options.downloaded_len_change_notify.clone(),
+        });
+
+        futures_util::stream::iter(self.playlist.segments.iter().enumerate())
+            .map(|(index, segment)| {
+                let segments_dir = segments_dir.clone();
+                let cancel_token = cancel_token.clone();
+                let notify = notify.clone();
+                let options = &options;
+                let keys = &keys;
+                async move {
+                    self.download_segment(index, segment, &segments_dir, cancel_token, notify, options, keys).await?;
+                    self.progress.completed_segment_count.fetch_add(1, Ordering::Relaxed);
+                    Result::<(), HlsError>::Ok(())
+                }
+            })
+            .buffer_unordered(options.concurrency.get())
+            .try_collect::<()>()
+            .await?;
+
+        // 合并到临时文件，完成后再替换，避免中断后留下不完整的文件
+        let merging_file_path = save_dir.join(format!("{}.merging", file_name));
+        let mut file = tokio::fs::File::create(&merging_file_path).await?;
+        for index in 0..self.playlist.segments.len() {
+            let mut segment_file = tokio::fs::File::open(segment_file_path(&segments_dir, index)).await?;
+            tokio::io::copy(&mut segment_file, &mut file).await?;
+        }
+        file.flush().await?;
+        file.sync_all().await?;
+        drop(file);
+        tokio::fs::rename(&merging_file_path, &file_path).await?;
+        tokio::fs::remove_dir_all(&segments_dir).await?;
+        Ok(file_path)
+    }
+
+    async fn fetch_keys(&self) -> Result<HashMap<Url, [u8; 16]>, HlsError> {
+        let mut keys = HashMap::new();
+        for key in self.playlist.segments.iter().filter_map(|n| n.key.as_ref()) {
+            if keys.contains_key(&key.url) {
+                continue;
+            }
+            let bytes = self
+                .client
+                .get(key.url.clone())
+                .headers(self.header_map.clone())
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?;
+            let bytes: [u8; 16] = bytes.as_ref().try_into().map_err(|_| HlsError::InvalidKey(bytes.len()))?;
+            keys.insert(key.url.clone(), bytes);
+        }
+        Ok(keys)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn download_segment(
+        &self,
+        index: usize,
+        segment: &HlsSegment,
+        segments_dir: &Path,
+        cancel_token: CancellationToken,
+        notify: Arc<dyn DownloadedLenChangeNotify>,
+        options: &HlsDownloadOptions,
+        keys: &HashMap<Url, [u8; 16]>,
+    ) -> Result<(), HlsError> {
+        let file_path = segment_file_path(segments_dir, index);
+        // 已完成的分片（解密后重命名）
+        if tokio::fs::try_exists(&file_path).await? {
+            return Ok(());
+        }
+        let part_file_name = format!("{}.part", index);
+        let part_file_path = segments_dir.join(&part_file_name);
+        if tokio::fs::try_exists(&part_file_path).await? {
+            tokio::fs::remove_file(&part_file_path).await?;
+        }
+
+        let mut builder = HttpDownloaderBuilder::new(segment.url.clone(), segments_dir.to_path_buf())
+            .client(Some(self.client.clone()))
+            .header_map(self.header_map.clone())
+            .download_connection_count(std::num::NonZeroU8::new(1).unwrap())
+            .cancel_token(Some(cancel_token));
+        if let Some(configure) = options.configure.as_ref() {
+            builder = configure(builder);
+        }
+        if let Some(byte_range) = segment.byte_range {
+            builder = builder.byte_range(byte_range.start..=byte_range.end);
+        }
+        let (mut downloader, _) = builder.file_name(Some(part_file_name)).build(());
+        downloader.inner.downloaded_len_change_notify = Some(notify);
+        match downloader.prepare_download()?.await? {
+            DownloadingEndCause::DownloadFinished => {}
+            DownloadingEndCause::Cancelled => return Err(HlsError::Cancelled),
+        }
+
+        if let Some(key) = segment.key.as_ref() {
+            let key_bytes = keys[&key.url];
+            let iv = key.iv.unwrap_or_else(|| (segment.sequence as u128).to_be_bytes());
+            let part_file_path = part_file_path.clone();
+            tokio::task::spawn_blocking(move || {
+                let mut data = std::fs::read(&part_file_path)?;
+                let len = decrypt_aes_128(&key_bytes, &iv, &mut data).ok_or(HlsError::DecryptFailed(index))?;
+                std::fs::write(&part_file_path, &data[..len])?;
+                Result::<(), HlsError>::Ok(())
+            })
+                .await??;
+        }
+        tokio::fs::rename(&part_file_path, &file_path).await?;
+        Ok(())
+    }
+}
+
+fn segment_file_path(segments_dir: &Path, index: usize) -> PathBuf {
+    segments_dir.join(format!("{}.ts", index))
+}
+
+/// AES-128-CBC 解密并去掉 PKCS7 填充，返回明文长度
+fn decrypt_aes_128(key: &[u8; 16], iv: &[u8; 16], data: &mut [u8]) -> Option<usize> {
+    cbc::Decryptor::<aes::Aes128>::new(key.into(), iv.into())
+        .decrypt_padded_mut::<Pkcs7>(data)
+        .ok()
+        .map(|n| n.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_playlists() {
+        let url: Url = "https://example.com/video/master.m3u8".parse().unwrap();
+        let master = "#EXTM3U\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=1280000,RESOLUTION=640x360,CODECS=\"avc1.4d401e,mp4a.40.2\"\n\
+            low/index.m3u8\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=2560000,RESOLUTION=1280x720\n\
+            https://cdn.example.com/high/index.m3u8\n";
+        let HlsPlaylist::Master(variants) = HlsPlaylist::parse(&url, master).unwrap() else {
+            panic!("expect master playlist");
+        };
+        assert_eq!(variants.len(), 2);
+        assert_eq!(variants[0].url.as_str(), "https://example.com/video/low/index.m3u8");
+        assert_eq!(variants[0].resolution, Some((640, 360)));
+        assert_eq!(variants[0].codecs.as_deref(), Some("avc1.4d401e,mp4a.40.2"));
+        assert_eq!(variants[1].bandwidth, Some(2560000));
+
+        let media = "#EXTM3U\n\
+            #EXT-X-TARGETDURATION:10\n\
+            #EXT-X-MEDIA-SEQUENCE:7\n\
+            #EXT-X-MAP:URI=\"init.mp4\"\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"/keys/1\",IV=0x0102\n\
+            #EXTINF:9.5,\n\
+            a.ts\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"key2\"\n\
+            #EXT-X-BYTERANGE:100@50\n\
+            #EXTINF:10.0,title\n\
+            b.ts\n\
+            #EXT-X-KEY:METHOD=NONE\n\
+            #EXT-X-BYTERANGE:20\n\
+            #EXTINF:2,\n\
+            b.ts\n\
+            #EXT-X-ENDLIST\n";
+        let HlsPlaylist::Media(playlist) = HlsPlaylist::parse(&url, media).unwrap() else {
+            panic!("expect media playlist");
+        };
+        assert!(playlist.end_list);
+        assert_eq!(playlist.duration(), 21.5);
+        let segments = &playlist.segments;
+        assert_eq!(segments.len(), 4);
+        assert!(segments[0].is_init);
+        assert_eq!(segments[0].url.as_str(), "https://example.com/video/init.mp4");
+        // 初始化分片在 EXT-X-KEY 之前声明，不加密
+        assert_eq!(segments[0].key, None);
+        assert_eq!(segments[1].sequence, 7);
+        let key = segments[1].key.as_ref().unwrap();
+        assert_eq!(key.url.as_str(), "https://example.com/keys/1");
+        assert_eq!(key.iv.unwrap()[14..], [1, 2]);
+        assert_eq!(segments[2].sequence, 8);
+        assert_eq!(segments[2].key.as_ref().unwrap().iv, None);
+        assert_eq!(segments[2].byte_range.map(|n| (n.start, n.end)), Some((50, 149)));
+        assert_eq!(segments[3].byte_range.map(|n| (n.start, n.end)), Some((150, 169)));
+        assert_eq!(segments[3].key, None);
+
+        assert!(matches!(
+            HlsPlaylist::parse(&url, "#EXTM3U\n#EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"k\"\n"),
+            Err(HlsError::UnsupportedEncryption(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_attributes_are_rejected() {
+        assert_eq!(parse_iv("0X0102").unwrap()[14..], [1, 2]);
+        for iv in ["0x", "0xé", "0x0é", "0x+1", "0x123456789012345678901234567890123", "0102"] {
+            assert!(matches!(parse_iv(iv), Err(HlsError::InvalidPlaylist(_))), "{}", iv);
+        }
+
+        let max = u64::MAX;
+        assert_eq!(parse_byte_range(&format!("{}@0", max), 0).map(|n| (n.start, n.end)).unwrap(), (0, max - 1));
+        for byte_range in [format!("{}@1", max), format!("2@{}", max - 1), "0@0".to_string(), "a@0".to_string()] {
+            assert!(matches!(parse_byte_range(&byte_range, 0), Err(HlsError::InvalidPlaylist(_))), "{}", byte_range);
+        }
+        assert!(parse_byte_range("1", max).is_err());
+    }
+
+    #[tokio::test]
+    async fn download_encrypted_segments_and_resume() {
+        use std::sync::atomic::AtomicBool;
+
+        use aes::cipher::BlockEncryptMut;
+
+        use crate::test_server::{TestResponse, TestServer};
+
+        let key = *b"0123456789abcdef";
+        let encrypt = |iv: [u8; 16], data: &[u8]| cbc::Encryptor::<aes::Aes128>::new(&key.into(), &iv.into()).encrypt_padded_vec_mut::<Pkcs7>(data);
+        let playlist = "#EXTM3U\n\
+            #EXT-X-TARGETDURATION:10\n\
+            #EXT-X-MEDIA-SEQUENCE:5\n\
+            #EXT-X-MAP:URI=\"init.mp4\"\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"key\",IV=0x01\n\
+            #EXTINF:1,\n\
+            a.ts\n\
+            #EXT-X-KEY:METHOD=AES-128,URI=\"key\"\n\
+            #EXTINF:1,\n\
+            b.ts\n\
+            #EXT-X-KEY:METHOD=NONE\n\
+            #EXTINF:1,\n\
+            c.ts\n\
+            #EXT-X-ENDLIST\n";
+        let files: HashMap<&str, Vec<u8>> = HashMap::from([
+            ("/v/index.m3u8", playlist.as_bytes().to_vec()),
+            ("/v/key", key.to_vec()),
+            ("/v/init.mp4", b"init;".to_vec()),
+            ("/v/a.ts", encrypt(1u128.to_be_bytes(), b"segment a;")),
+            // 没有 IV 时使用媒体序号
+            ("/v/b.ts", encrypt(6u128.to_be_bytes(), b"segment b, longer than one block;")),
+            ("/v/c.ts", b"segment c".to_vec()),
+        ]);
+        let fail_last = Arc::new(AtomicBool::new(true));
+        let server = TestServer::start({
+            let fail_last = fail_last.clone();
+            move |request| match files.get(request.target.as_str()) {
+                Some(_) if request.target == "/v/c.ts" && fail_last.load(Ordering::SeqCst) => TestResponse::new(404),
+                Some(body) => TestResponse::new(200).header("Accept-Ranges", "none").body(body.clone()),
+                None => TestResponse::new(404),
+            }
+        }).await;
+
+        let save_dir = std::env::temp_dir().join(format!("http-downloader-hls-{}", std::process::id()));
+        let hls_downloader = HlsDownloader::open(reqwest::Client::new(), server.url("/v/index.m3u8"), HeaderMap::new()).await.unwrap();
+        assert_eq!(hls_downloader.playlist().segments.len(), 4);
+        // 依次下载，最后一个分片失败时前面的分片已经完成
+        let options = || HlsDownloadOptions {
+            concurrency: NonZeroUsize::new(1).unwrap(),
+            configure: Some(Arc::new(|builder: HttpDownloaderBuilder| builder.request_retry_count(0))),
+            ..Default::default()
+        };
+        assert!(hls_downloader.download(save_dir.clone(), "video.ts".to_string(), options()).await.is_err());
+        assert_eq!(hls_downloader.progress().completed_segment_count.load(Ordering::SeqCst), 3);
+
+        fail_last.store(false, Ordering::SeqCst);
+        let file_path = hls_downloader.download(save_dir.clone(), "video.ts".to_string(), options()).await.unwrap();
+        assert_eq!(std::fs::read(&file_path).unwrap(), b"init;segment a;segment b, longer than one block;segment c");
+        assert!(!save_dir.join("video.ts.segments").exists());
+        std::fs::remove_dir_all(&save_dir).unwrap();
+
+        // 已完成的分片不会重新下载
+        let requests = server.requests();
+        for path in ["/v/init.mp4", "/v/a.ts", "/v/b.ts"] {
+            assert_eq!(requests.iter().filter(|n| n.target == path).count(), 1, "{}", path);
+        }
+    }
+}
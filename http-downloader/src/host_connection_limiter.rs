@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use url::Url;
+
+/// 限制同一主机的并发连接数，可在多个下载器之间共享
+///
+/// 首次请求与 chunk 在发出请求前获取许可，直到这次请求的响应内容接收完毕才释放
+#[derive(Debug)]
+pub struct HostConnectionLimiter {
+    max_per_host: usize,
+    total: Option<Arc<Semaphore>>,
+    // 没有连接也没有等待者的主机会被移除，避免访问过的主机一直占用内存
+    hosts: Arc<parking_lot::Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+/// 连接许可，释放后其他等待的连接才能继续
+#[derive(Debug)]
+pub struct HostConnectionPermit {
+    host: Option<OwnedSemaphorePermit>,
+    _total: Option<OwnedSemaphorePermit>,
+    host_key: String,
+    hosts: Arc<parking_lot::Mutex<HashMap<String, Arc<Semaphore>>>>,
+}
+
+impl Drop for HostConnectionPermit {
+    fn drop(&mut self) {
+        self.host.take();
+        let mut hosts = self.hosts.lock();
+        // 只剩表中的引用，说明既没有其他许可也没有等待者
+        if hosts.get(&self.host_key).is_some_and(|n| Arc::strong_count(n) == 1) {
+            hosts.remove(&self.host_key);
+        }
+    }
+}
+
+impl HostConnectionLimiter {
+    /// 每个主机最多 `max_per_host` 个连接，不限制总连接数
+    pub fn new(max_per_host: NonZeroUsize) -> Self {
+        Self {
+            max_per_host: max_per_host.get(),
+            total: None,
+            hosts: Default::default(),
+        }
+    }
+
+    /// 所有主机加起来最多 `max_total` 个连接
+    pub fn with_total_limit(mut self, max_total: NonZeroUsize) -> Self {
+        self.total = Some(Arc::new(Semaphore::new(max_total.get())));
+        self
+    }
+
+    pub fn max_per_host(&self) -> usize {
+        self.max_per_host
+    }
+
+    /// 当前可用的总连接数，不限制总连接数时返回 None
+    pub fn available_total(&self) -> Option<usize> {
+        self.total.as_ref().map(|n| n.available_permits())
+    }
+
+    /// 主机当前可用的连接数
+    pub fn available_for(&self, url: &Url) -> usize {
+        self.hosts
+            .lock()
+            .get(&Self::host_key(url))
+            .map(|n| n.available_permits())
+            .unwrap_or(self.max_per_host)
+    }
+
+    /// 等待获取 `url` 所在主机的连接许可
+    pub async fn acquire(&self, url: &Url) -> HostConnectionPermit {
+        let host_key = Self::host_key(url);
+        let host = self
+            .hosts
+            .lock()
+            .entry(host_key.clone())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max_per_host)))
+            .clone();
+        // 先主机后总数，所有连接按相同的顺序获取，不会互相等待
+        let host = host.acquire_owned().await.expect("semaphore is never closed");
+        let total = match self.total.clone() {
+            None => None,
+            Some(total) => Some(total.acquire_owned().await.expect("semaphore is never closed")),
+        };
+        HostConnectionPermit {
+            host: Some(host),
+            _total: total,
+            host_key,
+            hosts: self.hosts.clone(),
+        }
+    }
+
+    fn host_key(url: &Url) -> String {
+        format!(
+            "{}:{}",
+            url.host_str().unwrap_or_default(),
+            url.port_or_known_default().unwrap_or_default()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn permits_per_host_and_total() {
+        let limiter = HostConnectionLimiter::new(NonZeroUsize::new(2).unwrap())
+            .with_total_limit(NonZeroUsize::new(3).unwrap());
+        let a = Url::parse("http://a.example/file").unwrap();
+        let a_other_port = Url::parse("http://a.example:8080/file").unwrap();
+        let b = Url::parse("https://b.example/file").unwrap();
+
+        let a1 = limiter.acquire(&a).await;
+        let _a2 = limiter.acquire(&a).await;
+        assert_eq!(limiter.available_for(&a), 0);
+        assert_eq!(limiter.available_for(&a_other_port), 2);
+        // 同一主机的许可用完后需要等待
+        assert!(tokio::time::timeout(Duration::from_millis(50), limiter.acquire(&a)).await.is_err());
+
+        let _b1 = limiter.acquire(&b).await;
+        assert_eq!(limiter.available_total(), Some(0));
+        // 其他主机还有许可，但总数已经用完
+        assert!(tokio::time::timeout(Duration::from_millis(50), limiter.acquire(&a_other_port)).await.is_err());
+
+        drop(a1);
+        assert_eq!(limiter.available_for(&a), 1);
+        assert_eq!(limiter.available_total(), Some(1));
+        let _a3 = limiter.acquire(&a).await;
+    }
+
+    #[tokio::test]
+    async fn released_hosts_are_removed() {
+        let limiter = Arc::new(HostConnectionLimiter::new(NonZeroUsize::new(1).unwrap()));
+        let a = Url::parse("http://a.example/file").unwrap();
+
+        let a1 = limiter.acquire(&a).await;
+        let waiter = tokio::spawn({
+            let limiter = limiter.clone();
+            let a = a.clone();
+            async move { limiter.acquire(&a).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // 还有等待者，释放后主机不能被移除
+        drop(a1);
+        let a2 = waiter.await.unwrap();
+        assert_eq!(limiter.hosts.lock().len(), 1);
+        assert_eq!(limiter.available_for(&a), 0);
+
+        drop(a2);
+        assert!(limiter.hosts.lock().is_empty());
+        assert_eq!(limiter.available_for(&a), 1);
+    }
+}
@@ -3,16 +3,51 @@ extern crate core;
 pub use chunk_item::*;
 pub use chunk_iterator::*;
 pub use chunk_manager::*;
+pub use client_config::*;
+#[cfg(feature = "decompress")]
+pub use decompress::*;
+#[cfg(feature = "serde")]
+pub use download_spec::*;
 pub use download_way::*;
 pub use downloader::*;
 pub use downloader_builder::*;
 pub use extensions::*;
+#[cfg(feature = "hls")]
+pub use hls::*;
+pub use host_connection_limiter::*;
+pub use progress_map::*;
+pub use request_interceptor::RequestInterceptor;
+pub use sequential_reader::*;
+pub use url_refresher::{RefreshedUrl, UrlRefresher};
+#[cfg(feature = "remote-zip")]
+pub use remote_zip::*;
 
+#[cfg(feature = "blocking")]
+pub mod blocking;
 mod chunk_item;
 mod chunk_iterator;
 mod chunk_manager;
+mod client_config;
+#[cfg(feature = "decompress")]
+mod decompress;
+#[cfg(feature = "serde")]
+mod download_spec;
 mod download_way;
 mod downloader;
 mod downloader_builder;
 mod extensions;
+#[cfg(feature = "ftp")]
+mod ftp;
+#[cfg(feature = "hls")]
+mod hls;
+mod host_connection_limiter;
+mod local_source;
+mod progress_map;
+mod request_interceptor;
+mod sequential_reader;
+mod url_refresher;
+#[cfg(feature = "remote-zip")]
+mod remote_zip;
 mod exclusive;
+#[cfg(test)]
+mod test_server;
@@ -0,0 +1,307 @@
+use std::io::{self, SeekFrom};
+use std::ops::Bound;
+use std::time::UNIX_EPOCH;
+
+use bytes::Bytes;
+use headers::{AcceptRanges, ContentLength, ContentRange, ETag, HeaderMapExt, LastModified};
+use reqwest::{Method, ResponseBuilderExt, StatusCode};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use url::Url;
+
+use crate::ClientConfig;
+
+pub(crate) const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// 发送请求，`file://` 与 `data:` 地址在本地生成与 HTTP 服务器相同的响应（支持 Range），
+/// 开启 `ftp` 功能时 `ftp://` 与 `ftps://` 地址同样如此（按 `client_config` 连接），其他地址由 `client` 发送
+pub(crate) async fn execute(client: &reqwest::Client, _client_config: &ClientConfig, request: reqwest::Request) -> reqwest::Result<reqwest::Response> {
+    match request.url().scheme() {
+        "file" => Ok(file_response(&request).await),
+        "data" => Ok(data_response(&request)),
+        #[cfg(feature = "ftp")]
+        "ftp" | "ftps" => Ok(crate::ftp::response(_client_config, &request).await),
+        _ => client.execute(request).await,
+    }
+}
+
+pub(crate) fn status_response(url: &Url, status: StatusCode) -> reqwest::Response {
+    http::Response::builder()
+        .status(status)
+        .url(url.clone())
+        .header(reqwest::header::CONTENT_LENGTH, 0)
+        .body(Bytes::new())
+        .unwrap()
+        .into()
+}
+
+/// 请求的范围，不满足时返回 None
+fn requested_range(request: &reqwest::Request, total_len: u64) -> Option<Option<(u64, u64)>> {
+    let Some(range) = request.headers().typed_get::<headers::Range>() else {
+        return Some(None);
+    };
+    let (start, end) = range.iter().next()?;
+    let (start, end) = match (start, end) {
+        (Bound::Included(start), Bound::Included(end)) => (start, end.min(total_len.saturating_sub(1))),
+        (Bound::Included(start), Bound::Unbounded) => (start, total_len.saturating_sub(1)),
+        (Bound::Unbounded, Bound::Included(len)) if len > 0 => (total_len.saturating_sub(len), total_len.saturating_sub(1)),
+        _ => return None,
+    };
+    (start <= end && end < total_len).then_some(Some((start, end)))
+}
+
+pub(crate) fn range_not_satisfiable_response(url: &Url, total_len: u64) -> reqwest::Response {
+    let mut response = status_response(url, StatusCode::RANGE_NOT_SATISFIABLE);
+    response.headers_mut().typed_insert(ContentRange::unsatisfied_bytes(total_len));
+    response
+}
+
+/// 根据请求的范围生成响应头与数据的起始位置、长度，范围不满足时返回 None
+pub(crate) fn response_builder(request: &reqwest::Request, total_len: u64) -> Option<(http::response::Builder, u64, u64)> {
+    let mut builder = http::Response::builder().url(request.url().clone());
+    let headers = builder.headers_mut().unwrap();
+    headers.typed_insert(AcceptRanges::bytes());
+    let (start, len) = match requested_range(request, total_len)? {
+        None => {
+            builder = builder.status(StatusCode::OK);
+            (0, total_len)
+        }
+        Some((start, end)) => {
+            builder.headers_mut().unwrap().typed_insert(ContentRange::bytes(start..=end, total_len).unwrap());
+            builder = builder.status(StatusCode::PARTIAL_CONTENT);
+            (start, end - start + 1)
+        }
+    };
+    builder.headers_mut().unwrap().typed_insert(ContentLength(len));
+    Some((builder, start, len))
+}
+
+async fn file_response(request: &reqwest::Request) -> reqwest::Response {
+    let url = request.url();
+    if request.method() != Method::GET && request.method() != Method::HEAD {
+        return status_response(url, StatusCode::METHOD_NOT_ALLOWED);
+    }
+    let Ok(path) = url.to_file_path() else {
+        return status_response(url, StatusCode::BAD_REQUEST);
+    };
+    let io_error_response = |err: io::Error| {
+        status_response(url, match err.kind() {
+            io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        })
+    };
+    let mut file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(err) => return io_error_response(err),
+    };
+    let metadata = match file.metadata().await {
+        Ok(metadata) if metadata.is_file() => metadata,
+        Ok(_) => return status_response(url, StatusCode::NOT_FOUND),
+        Err(err) => return io_error_response(err),
+    };
+    let Some((mut builder, start, len)) = response_builder(request, metadata.len()) else {
+        return range_not_satisfiable_response(url, metadata.len());
+    };
+    // 文件被修改后 ETag 改变，断点续传时可以发现
+    if let Ok(modified) = metadata.modified() {
+        let headers = builder.headers_mut().unwrap();
+        headers.typed_insert(LastModified::from(modified));
+        let modified = modified.duration_since(UNIX_EPOCH).map(|n| n.as_nanos()).unwrap_or(0);
+        if let Ok(etag) = format!("\"{:x}-{:x}\"", metadata.len(), modified).parse::<ETag>() {
+            headers.typed_insert(etag);
+        }
+    }
+    if request.method() == Method::HEAD {
+        return builder.body(Bytes::new()).unwrap().into();
+    }
+    if let Err(err) = file.seek(SeekFrom::Start(start)).await {
+        return io_error_response(err);
+    }
+    let stream = futures_util::stream::try_unfold(file.take(len), |mut reader| async move {
+        let mut buffer = vec![0; READ_BUFFER_SIZE];
+        let read_len = reader.read(&mut buffer).await?;
+        if read_len == 0 {
+            return Ok(None);
+        }
+        buffer.truncate(read_len);
+        io::Result::Ok(Some((Bytes::from(buffer), reader)))
+    });
+    builder.body(reqwest::Body::wrap_stream(stream)).unwrap().into()
+}
+
+fn data_response(request: &reqwest::Request) -> reqwest::Response {
+    let url = request.url();
+    let Some((media_type, data)) = parse_data_url(url) else {
+        return status_response(url, StatusCode::BAD_REQUEST);
+    };
+    let Some((mut builder, start, len)) = response_builder(request, data.len() as u64) else {
+        return range_not_satisfiable_response(url, data.len() as u64);
+    };
+    if let Ok(content_type) = reqwest::header::HeaderValue::from_str(&media_type) {
+        builder.headers_mut().unwrap().insert(reqwest::header::CONTENT_TYPE, content_type);
+    }
+    let body = if request.method() == Method::HEAD {
+        Bytes::new()
+    } else {
+        Bytes::from(data).slice(start as usize..(start + len) as usize)
+    };
+    builder.body(body).unwrap().into()
+}
+
+/// 解析 `data:[<mediatype>][;base64],<data>`，返回媒体类型与数据
+fn parse_data_url(url: &Url) -> Option<(String, Vec<u8>)> {
+    let content = url.as_str().strip_prefix("data:")?;
+    let content = content.split('#').next().unwrap_or_default();
+    let (meta, data) = content.split_once(',')?;
+    let data = percent_decode(data);
+    let (media_type, is_base64) = match meta.len().checked_sub(7) {
+        Some(index) if meta[index..].eq_ignore_ascii_case(";base64") => (&meta[..index], true),
+        _ => (meta, false),
+    };
+    let data = if is_base64 { base64_decode(&data)? } else { data };
+    let media_type = if media_type.is_empty() || media_type.starts_with(';') {
+        format!("text/plain{}", if media_type.is_empty() { ";charset=US-ASCII" } else { media_type })
+    } else {
+        percent_decode_str(media_type)
+    };
+    Some((media_type, data))
+}
+
+fn percent_decode_str(value: &str) -> String {
+    String::from_utf8_lossy(&percent_decode(value)).to_string()
+}
+
+pub(crate) fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            if let Some(byte) = value.get(index + 1..index + 3).and_then(|n| u8::from_str_radix(n, 16).ok()) {
+                decoded.push(byte);
+                index += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[index]);
+        index += 1;
+    }
+    decoded
+}
+
+/// 标准与 URL 安全的 base64 都可以解码，忽略空白与末尾的填充
+fn base64_decode(data: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(data.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &byte in data.iter().filter(|n| !n.is_ascii_whitespace()) {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_data_urls() {
+        let parse = |url: &str| parse_data_url(&url.parse().unwrap()).unwrap();
+        assert_eq!(parse("data:,Hello%2C%20World%21"), ("text/plain;charset=US-ASCII".to_string(), b"Hello, World!".to_vec()));
+        assert_eq!(parse("data:text/plain;base64,SGVsbG8sIFdvcmxkIQ=="), ("text/plain".to_string(), b"Hello, World!".to_vec()));
+        assert_eq!(parse("data:;base64,SGVsbG8#fragment").1, b"Hello".to_vec());
+        assert_eq!(parse("data:application/octet-stream;BASE64,-_8").1, vec![0xfb, 0xff]);
+        assert!(parse_data_url(&"data:;base64,SGV*".parse().unwrap()).is_none());
+    }
+
+    #[tokio::test]
+    async fn data_url_range() {
+        let url: Url = "data:text/plain;base64,SGVsbG8sIFdvcmxkIQ==".parse().unwrap();
+        let (client, client_config) = (reqwest::Client::new(), ClientConfig::default());
+        let request = |range: &str| {
+            let mut request = reqwest::Request::new(Method::GET, url.clone());
+            request.headers_mut().insert(reqwest::header::RANGE, range.parse().unwrap());
+            execute(&client, &client_config, request)
+        };
+        let response = request("bytes=7-11").await.unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers().typed_get::<ContentRange>().and_then(|n| n.bytes_range()), Some((7, 11)));
+        assert_eq!(response.headers().get(reqwest::header::CONTENT_TYPE).unwrap(), "text/plain");
+        assert_eq!(response.bytes().await.unwrap(), "World");
+        assert_eq!(request("bytes=-6").await.unwrap().bytes().await.unwrap(), "World!");
+        let response = request("bytes=13-").await.unwrap();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers().typed_get::<ContentRange>(), Some(ContentRange::unsatisfied_bytes(13)));
+    }
+
+    #[cfg(all(feature = "bson-file-archiver", feature = "speed-limiter"))]
+    #[tokio::test]
+    async fn download_file_url_and_resume() {
+        use std::num::{NonZeroU8, NonZeroUsize};
+        use std::time::{Duration, SystemTime};
+
+        use crate::breakpoint_resume::DownloadBreakpointResumeExtension;
+        use crate::bson_file_archiver::{ArchiveFilePath, BsonFileArchiverBuilder};
+        use crate::speed_limiter::DownloadSpeedLimiterExtension;
+        use crate::{DownloadingEndCause, HttpDownloaderBuilder, ResumeInvalidCause, ResumeState};
+
+        let dir = std::env::temp_dir().join(format!("http-downloader-file-url-{}", std::process::id()));
+        let source = dir.join("source.bin");
+        std::fs::create_dir_all(&dir).unwrap();
+        let data: Vec<u8> = (0..512 * 1024u32).map(|n| (n % 227) as u8).collect();
+        std::fs::write(&source, &data).unwrap();
+        let save_dir = dir.join("save");
+
+        // 限速时收到部分数据后取消，不限速时下载到结束
+        let download = |limit: bool| {
+            let (mut downloader, _) = HttpDownloaderBuilder::new(Url::from_file_path(&source).unwrap(), save_dir.clone())
+                .chunk_size(NonZeroUsize::new(16 * 1024).unwrap())
+                .download_connection_count(NonZeroU8::new(4).unwrap())
+                .build((
+                    DownloadBreakpointResumeExtension::new(BsonFileArchiverBuilder::new(ArchiveFilePath::Suffix("bson".to_string()))),
+                    DownloadSpeedLimiterExtension::new(limit.then_some(256 * 1024)),
+                ));
+            let downloading_state_receiver = downloader.downloading_state_receiver();
+            let mut downloaded_len_receiver = downloader.downloaded_len_receiver().clone();
+            async move {
+                let download_future = tokio::spawn(downloader.prepare_download().unwrap());
+                if limit {
+                    let _ = downloaded_len_receiver.wait_for(|n| *n >= 64 * 1024).await;
+                    downloader.cancel().await;
+                }
+                let end_cause = download_future.await.unwrap().unwrap();
+                let resume_state = downloading_state_receiver.await.unwrap().resume_state.clone();
+                (end_cause, resume_state, downloader.downloaded_len(), downloader.get_file_path())
+            }
+        };
+
+        let (end_cause, resume_state, downloaded_len, _) = download(true).await;
+        assert_eq!((end_cause, resume_state), (DownloadingEndCause::Cancelled, ResumeState::NotResumed));
+        assert!(downloaded_len < data.len() as u64);
+        let (end_cause, resume_state, _, _) = download(true).await;
+        assert_eq!((end_cause, resume_state), (DownloadingEndCause::Cancelled, ResumeState::Resumed));
+
+        // 源文件的修改时间改变后 ETag 改变，不能继续下载
+        std::fs::File::options().write(true).open(&source).unwrap().set_modified(SystemTime::now() - Duration::from_secs(3600)).unwrap();
+        let (end_cause, resume_state, downloaded_len, file_path) = download(false).await;
+        assert_eq!(end_cause, DownloadingEndCause::DownloadFinished);
+        assert!(matches!(resume_state, ResumeState::Restarted(ResumeInvalidCause::EtagMismatch { current: Some(_), .. })));
+        assert_eq!(downloaded_len, data.len() as u64);
+        assert_eq!(std::fs::read(&file_path).unwrap(), data);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
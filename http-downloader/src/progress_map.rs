@@ -0,0 +1,203 @@
+use std::num::NonZeroUsize;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{ChunkData, ChunkRange};
+
+/// 默认将文件划分为多少块
+pub const DEFAULT_PROGRESS_MAP_PIECES: usize = 256;
+
+/// 固定分辨率的下载进度图：将文件等分为若干块，记录每块已接收的字节数
+///
+/// 由 `ChunkManager` 在接收到数据时更新，适合 UI 绘制下载进度条
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ProgressMap {
+    pub content_length: u64,
+    pub piece_size: u64,
+    // 每块已接收的字节数
+    pub pieces: Vec<u64>,
+}
+
+/// 一块的进度，用于增量同步 [`ProgressMap`]
+#[cfg_attr(feature = "async-graphql", derive(async_graphql::SimpleObject))]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PieceProgress {
+    pub index: usize,
+    pub downloaded_len: u64,
+}
+
+impl ProgressMap {
+    /// 将 `content_length` 划分为最多 `piece_count` 块，文件较小时块数会相应减少
+    pub fn new(content_length: u64, piece_count: NonZeroUsize) -> Self {
+        let piece_size = content_length.div_ceil(piece_count.get() as u64).max(1);
+        let piece_count = content_length.div_ceil(piece_size) as usize;
+        Self {
+            content_length,
+            piece_size,
+            pieces: vec![0; piece_count],
+        }
+    }
+
+    /// 根据 chunk 数据建立进度图，不在剩余部分中的范围视为已完成
+    pub fn from_chunk_data(content_length: u64, piece_count: NonZeroUsize, data: &ChunkData) -> Self {
+        let mut progress_map = Self::new(content_length, piece_count);
+        let pending = data
+            .remaining
+            .ranges
+            .iter()
+            .copied()
+            .chain(data.last_incomplete_chunks.iter().map(|n| n.range));
+        for range in complement_ranges(content_length, pending) {
+            progress_map.add_range(range);
+        }
+        progress_map
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.pieces.len()
+    }
+
+    /// 第 `index` 块在文件中的范围
+    pub fn piece_range(&self, index: usize) -> ChunkRange {
+        let start = index as u64 * self.piece_size;
+        ChunkRange::new(start, (start + self.piece_size).min(self.content_length) - 1)
+    }
+
+    pub fn is_piece_complete(&self, index: usize) -> bool {
+        self.pieces[index] == self.piece_range(index).len()
+    }
+
+    pub fn completed_piece_count(&self) -> usize {
+        (0..self.piece_count()).filter(|&n| self.is_piece_complete(n)).count()
+    }
+
+    pub fn downloaded_len(&self) -> u64 {
+        self.pieces.iter().sum()
+    }
+
+    /// 记录 `range` 范围内的数据已接收，同一范围不应重复记录
+    pub fn add_range(&mut self, range: ChunkRange) {
+        if self.pieces.is_empty() || range.start >= self.content_length {
+            return;
+        }
+        let end = range.end.min(self.content_length - 1);
+        let mut start = range.start;
+        while start <= end {
+            let index = (start / self.piece_size) as usize;
+            let piece_end = self.piece_range(index).end.min(end);
+            let piece_len = self.piece_range(index).len();
+            self.pieces[index] = (self.pieces[index] + piece_end - start + 1).min(piece_len);
+            start = piece_end + 1;
+        }
+    }
+
+    /// 已完成块的位图，高位在前，第 0 块对应第一个字节的最高位
+    pub fn bitfield(&self) -> Vec<u8> {
+        let mut bitfield = vec![0u8; self.piece_count().div_ceil(8)];
+        for index in (0..self.piece_count()).filter(|&n| self.is_piece_complete(n)) {
+            bitfield[index / 8] |= 0x80 >> (index % 8);
+        }
+        bitfield
+    }
+
+    /// 已完成的块合并后的范围
+    pub fn finished_ranges(&self) -> Vec<ChunkRange> {
+        let mut ranges: Vec<ChunkRange> = vec![];
+        for index in (0..self.piece_count()).filter(|&n| self.is_piece_complete(n)) {
+            let range = self.piece_range(index);
+            match ranges.last_mut() {
+                Some(last) if last.end + 1 == range.start => last.end = range.end,
+                _ => ranges.push(range),
+            }
+        }
+        ranges
+    }
+
+    /// 与 `previous` 相比发生变化的块，划分方式不同时返回所有块
+    pub fn diff(&self, previous: &ProgressMap) -> Vec<PieceProgress> {
+        let same_layout = self.content_length == previous.content_length
+            && self.piece_size == previous.piece_size
+            && self.pieces.len() == previous.pieces.len();
+        self.pieces
+            .iter()
+            .enumerate()
+            .filter(|(index, downloaded_len)| !same_layout || previous.pieces[*index] != **downloaded_len)
+            .map(|(index, downloaded_len)| PieceProgress {
+                index,
+                downloaded_len: *downloaded_len,
+            })
+            .collect()
+    }
+
+    /// 应用 [`ProgressMap::diff`] 的结果，超出范围的块会被忽略
+    pub fn apply_diff(&mut self, diff: &[PieceProgress]) {
+        for piece in diff {
+            if let Some(downloaded_len) = self.pieces.get_mut(piece.index) {
+                *downloaded_len = piece.downloaded_len;
+            }
+        }
+    }
+}
+
+/// `[0, content_length)` 中不被 `ranges` 覆盖的部分
+pub(crate) fn complement_ranges(content_length: u64, ranges: impl Iterator<Item = ChunkRange>) -> Vec<ChunkRange> {
+    let mut ranges: Vec<ChunkRange> = ranges.collect();
+    ranges.sort_by_key(|n| n.start);
+    let mut complement = vec![];
+    let mut start = 0;
+    for range in ranges {
+        if range.start > start {
+            complement.push(ChunkRange::new(start, range.start - 1));
+        }
+        start = start.max(range.end + 1);
+    }
+    if start < content_length {
+        complement.push(ChunkRange::new(start, content_length - 1));
+    }
+    complement
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_range_and_diff() {
+        let mut progress_map = ProgressMap::new(1000, NonZeroUsize::new(8).unwrap());
+        assert_eq!(progress_map.piece_size, 125);
+        assert_eq!(progress_map.piece_count(), 8);
+
+        let previous = progress_map.clone();
+        progress_map.add_range(ChunkRange::new(100, 399));
+        progress_map.add_range(ChunkRange::new(875, 999));
+        assert_eq!(progress_map.pieces, vec![25, 125, 125, 25, 0, 0, 0, 125]);
+        assert_eq!(progress_map.downloaded_len(), 425);
+        assert_eq!(progress_map.bitfield(), vec![0b0110_0001]);
+        assert_eq!(
+            progress_map.finished_ranges().iter().map(|n| (n.start, n.end)).collect::<Vec<_>>(),
+            vec![(125, 374), (875, 999)]
+        );
+
+        let diff = progress_map.diff(&previous);
+        assert_eq!(diff.len(), 5);
+        let mut restored = previous;
+        restored.apply_diff(&diff);
+        assert_eq!(restored, progress_map);
+    }
+
+    #[test]
+    fn complement_of_pending_ranges() {
+        let complement = complement_ranges(
+            100,
+            [ChunkRange::new(50, 59), ChunkRange::new(10, 19), ChunkRange::new(15, 29)].into_iter(),
+        );
+        assert_eq!(
+            complement.iter().map(|n| (n.start, n.end)).collect::<Vec<_>>(),
+            vec![(0, 9), (30, 49), (60, 99)]
+        );
+    }
+}
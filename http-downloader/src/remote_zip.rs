@@ -0,0 +1,740 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::ops::{Range, RangeInclusive};
+use std::path::{Path, PathBuf};
+
+use headers::{ContentRange, HeaderMapExt};
+use reqwest::header::HeaderMap;
+use thiserror::Error;
+use tokio::task::JoinError;
+use url::Url;
+
+use crate::{DownloadError, DownloadingEndCause, DownloadStartError, HttpDownloaderBuilder};
+
+const EOCD_SIGNATURE: u32 = 0x06054b50;
+const EOCD_LEN: usize = 22;
+const ZIP64_EOCD_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const ZIP64_EOCD_LOCATOR_LEN: usize = 20;
+const ZIP64_EOCD_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_EOCD_LEN: usize = 56;
+const CENTRAL_DIRECTORY_HEADER_SIGNATURE: u32 = 0x02014b50;
+const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x04034b50;
+const LOCAL_FILE_HEADER_LEN: usize = 30;
+const ZIP64_EXTRA_FIELD_ID: u16 = 0x0001;
+// EOCD 加上最长的注释
+const MAX_EOCD_SEARCH_LEN: u64 = EOCD_LEN as u64 + u16::MAX as u64;
+
+#[derive(Error, Debug)]
+pub enum RemoteZipError {
+    #[error("http request failed，{:?}", .0)]
+    HttpRequestFailed(#[from] reqwest::Error),
+    #[error("the server does not support range requests")]
+    RangeNotSupported,
+    #[error("IoError，{:?}", .0)]
+    IoError(#[from] std::io::Error),
+    #[error("JoinError，{:?}", .0)]
+    JoinError(#[from] JoinError),
+    #[error("invalid zip archive: {}", .0)]
+    InvalidArchive(&'static str),
+    #[error("unsupported compression method {}", .0)]
+    UnsupportedCompressionMethod(u16),
+    #[error("encrypted entries are not supported")]
+    EncryptedEntry,
+    #[error("the entry is a directory")]
+    DirectoryEntry,
+    #[error("invalid entry name {}", .0)]
+    InvalidEntryName(String),
+    #[error("crc32 mismatch")]
+    CrcMismatch,
+    #[error("{:?}", .0)]
+    DownloadStartError(#[from] DownloadStartError),
+    #[error("{:?}", .0)]
+    DownloadError(Box<DownloadError>),
+    #[error("download cancelled")]
+    Cancelled,
+}
+
+impl From<DownloadError> for RemoteZipError {
+    fn from(value: DownloadError) -> Self {
+        RemoteZipError::DownloadError(Box::new(value))
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ZipCompressionMethod {
+    Stored,
+    Deflated,
+    Other(u16),
+}
+
+impl From<u16> for ZipCompressionMethod {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => ZipCompressionMethod::Stored,
+            8 => ZipCompressionMethod::Deflated,
+            n => ZipCompressionMethod::Other(n),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    pub name: String,
+    pub compression_method: ZipCompressionMethod,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub crc32: u32,
+    pub local_header_offset: u64,
+    pub is_encrypted: bool,
+}
+
+impl ZipEntry {
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+
+    /// 去掉目录部分的文件名，条目名中包含 `\`、`..` 或盘符等可能写到保存目录之外的部分时返回 None
+    pub fn file_name(&self) -> Option<&str> {
+        if self.name.contains(['\\', ':']) || self.name.split('/').any(|n| n == "..") {
+            return None;
+        }
+        let file_name = self.name.rsplit('/').next()?;
+        Path::new(file_name)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .filter(|n| *n == file_name)
+    }
+}
+
+/// 通过 Range 请求读取远程 ZIP 的中央目录，列出条目并单独下载、解压其中一个条目
+pub struct RemoteZip {
+    client: reqwest::Client,
+    url: Url,
+    header_map: HeaderMap,
+    content_length: u64,
+    entries: Vec<ZipEntry>,
+}
+
+impl RemoteZip {
+    pub async fn open(client: reqwest::Client, url: Url, header_map: HeaderMap) -> Result<Self, RemoteZipError> {
+        // 先取末尾部分，其中包含 EOCD，同时得到文件总大小
+        let (tail, content_length) = fetch_suffix(&client, &url, &header_map, MAX_EOCD_SEARCH_LEN).await?;
+        let tail_offset = content_length
+            .checked_sub(tail.len() as u64)
+            .ok_or(RemoteZipError::InvalidArchive("archive size is smaller than the received data"))?;
+        let eocd_index = find_eocd(&tail).ok_or(RemoteZipError::InvalidArchive("end of central directory not found"))?;
+        let mut eocd = parse_eocd(&tail[eocd_index..])?;
+
+        if eocd.is_zip64() {
+            let locator_index = eocd_index
+                .checked_sub(ZIP64_EOCD_LOCATOR_LEN)
+                .ok_or(RemoteZipError::InvalidArchive("zip64 locator not found"))?;
+            let zip64_eocd_offset = parse_zip64_eocd_locator(&tail[locator_index..])?;
+            let zip64_eocd = if zip64_eocd_offset >= tail_offset {
+                let index = (zip64_eocd_offset - tail_offset) as usize;
+                tail.get(index..).ok_or(RemoteZipError::InvalidArchive("zip64 end of central directory out of range"))?.to_vec()
+            } else {
+                fetch_range(&client, &url, &header_map, zip64_eocd_offset, ZIP64_EOCD_LEN as u64).await?
+            };
+            eocd = parse_zip64_eocd(&zip64_eocd)?;
+        }
+
+        let central_directory = match central_directory_in_tail(&eocd, content_length, tail_offset, tail.len())? {
+            Some(range) => tail[range].to_vec(),
+            None => fetch_range(&client, &url, &header_map, eocd.central_directory_offset, eocd.central_directory_size).await?,
+        };
+        let entries = parse_central_directory(&central_directory, eocd.entry_count)?;
+
+        Ok(Self {
+            client,
+            url,
+            header_map,
+            content_length,
+            entries,
+        })
+    }
+
+    pub fn content_length(&self) -> u64 {
+        self.content_length
+    }
+
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    pub fn find_entry(&self, name: &str) -> Option<&ZipEntry> {
+        self.entries.iter().find(|n| n.name == name)
+    }
+
+    /// 下载并解压条目到 `save_dir` 中，文件名为条目的文件名
+    pub async fn extract(&self, entry: &ZipEntry, save_dir: PathBuf) -> Result<PathBuf, RemoteZipError> {
+        self.extract_with(entry, save_dir, |builder| builder).await
+    }
+
+    /// 同 `extract`，可以通过 `configure` 配置下载器，比如连接数、chunk 大小
+    pub async fn extract_with(
+        &self,
+        entry: &ZipEntry,
+        save_dir: PathBuf,
+        configure: impl FnOnce(HttpDownloaderBuilder) -> HttpDownloaderBuilder,
+    ) -> Result<PathBuf, RemoteZipError> {
+        if entry.is_dir() {
+            return Err(RemoteZipError::DirectoryEntry);
+        }
+        if entry.is_encrypted {
+            return Err(RemoteZipError::EncryptedEntry);
+        }
+        if let ZipCompressionMethod::Other(method) = entry.compression_method {
+            return Err(RemoteZipError::UnsupportedCompressionMethod(method));
+        }
+
+        let file_name = entry.file_name().ok_or_else(|| RemoteZipError::InvalidEntryName(entry.name.clone()))?;
+        let file_path = save_dir.join(file_name);
+        let local_header = fetch_range(&self.client, &self.url, &self.header_map, entry.local_header_offset, LOCAL_FILE_HEADER_LEN as u64).await?;
+        let local_header_len = parse_local_header_len(&local_header)?;
+
+        if entry.compressed_size == 0 {
+            tokio::fs::create_dir_all(&save_dir).await?;
+            tokio::fs::write(&file_path, []).await?;
+            return if entry.crc32 == 0 { Ok(file_path) } else { Err(RemoteZipError::CrcMismatch) };
+        }
+        let data_range = entry_data_range(entry, local_header_len, self.content_length)?;
+
+        // 校验通过前只写入临时文件，不会覆盖同名文件或留下内容错误的文件
+        let download_file_name = format!("{}.zipdata", file_name);
+        let download_file_path = save_dir.join(&download_file_name);
+        if tokio::fs::try_exists(&download_file_path).await? {
+            tokio::fs::remove_file(&download_file_path).await?;
+        }
+
+        let builder = HttpDownloaderBuilder::new(self.url.clone(), save_dir)
+            .client(Some(self.client.clone()))
+            .header_map(self.header_map.clone());
+        let (mut downloader, _) = configure(builder)
+            .file_name(Some(download_file_name))
+            .byte_range(data_range)
+            .build(());
+        match downloader.prepare_download()?.await? {
+            DownloadingEndCause::DownloadFinished => {}
+            DownloadingEndCause::Cancelled => return Err(RemoteZipError::Cancelled),
+        }
+
+        let entry = entry.clone();
+        let target_file_path = file_path.clone();
+        tokio::task::spawn_blocking(move || decompress_entry(&entry, &download_file_path, &target_file_path)).await??;
+        Ok(file_path)
+    }
+}
+
+/// 中央目录在末尾数据中的位置，不在其中时返回 None，需要另外获取
+fn central_directory_in_tail(
+    eocd: &EndOfCentralDirectory,
+    content_length: u64,
+    tail_offset: u64,
+    tail_len: usize,
+) -> Result<Option<Range<usize>>, RemoteZipError> {
+    let out_of_range = || RemoteZipError::InvalidArchive("central directory out of range");
+    let end = eocd.central_directory_offset
+        .checked_add(eocd.central_directory_size)
+        .filter(|n| *n <= content_length)
+        .ok_or_else(out_of_range)?;
+    if eocd.central_directory_offset < tail_offset {
+        return Ok(None);
+    }
+    let range = (eocd.central_directory_offset - tail_offset) as usize..(end - tail_offset) as usize;
+    if range.end > tail_len {
+        return Err(out_of_range());
+    }
+    Ok(Some(range))
+}
+
+/// 条目压缩数据在文件中的范围（包含两端），压缩后的大小不能为 0
+fn entry_data_range(entry: &ZipEntry, local_header_len: u64, content_length: u64) -> Result<RangeInclusive<u64>, RemoteZipError> {
+    let data_offset = entry.local_header_offset.checked_add(local_header_len);
+    let data_end = data_offset.and_then(|n| n.checked_add(entry.compressed_size));
+    match (data_offset, data_end) {
+        (Some(data_offset), Some(data_end)) if data_offset < data_end && data_end <= content_length => Ok(data_offset..=data_end - 1),
+        _ => Err(RemoteZipError::InvalidArchive("entry data out of range")),
+    }
+}
+
+/// 校验下载的数据，解压后的内容在 CRC 校验通过后才重命名为 `file_path`
+fn decompress_entry(entry: &ZipEntry, download_file_path: &Path, file_path: &Path) -> Result<(), RemoteZipError> {
+    let mut crc = flate2::Crc::new();
+    let mut buffer = vec![0; 64 * 1024];
+    let temp_file_path = match entry.compression_method {
+        ZipCompressionMethod::Stored => {
+            let mut file = fs::File::open(download_file_path)?;
+            loop {
+                let len = file.read(&mut buffer)?;
+                if len == 0 {
+                    break;
+                }
+                crc.update(&buffer[..len]);
+            }
+            download_file_path.to_path_buf()
+        }
+        _ => {
+            let temp_file_path = download_file_path.with_extension("zipentry");
+            let mut decoder = flate2::read::DeflateDecoder::new(std::io::BufReader::new(fs::File::open(download_file_path)?));
+            let mut file = fs::File::create(&temp_file_path)?;
+            loop {
+                let len = decoder.read(&mut buffer)?;
+                if len == 0 {
+                    break;
+                }
+                crc.update(&buffer[..len]);
+                file.write_all(&buffer[..len])?;
+            }
+            file.flush()?;
+            fs::remove_file(download_file_path)?;
+            temp_file_path
+        }
+    };
+    if crc.sum() != entry.crc32 || crc.amount() as u64 != entry.uncompressed_size & u32::MAX as u64 {
+        fs::remove_file(&temp_file_path)?;
+        return Err(RemoteZipError::CrcMismatch);
+    }
+    fs::rename(&temp_file_path, file_path)?;
+    Ok(())
+}
+
+/// 返回响应内容与 Content-Range 中的总大小
+async fn send_range_request(
+    client: &reqwest::Client,
+    url: &Url,
+    header_map: &HeaderMap,
+    range: String,
+) -> Result<(Vec<u8>, Option<u64>), RemoteZipError> {
+    let response = client
+        .get(url.clone())
+        .headers(header_map.clone())
+        .header(reqwest::header::RANGE, range)
+        .send()
+        .await?
+        .error_for_status()?;
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return Err(RemoteZipError::RangeNotSupported);
+    }
+    let complete_length = response
+        .headers()
+        .typed_get::<ContentRange>()
+        .and_then(|n| n.bytes_len());
+    Ok((response.bytes().await?.to_vec(), complete_length))
+}
+
+/// 获取末尾 `len` 个字节（文件更小时为整个文件）与文件总大小
+async fn fetch_suffix(
+    client: &reqwest::Client,
+    url: &Url,
+    header_map: &HeaderMap,
+    len: u64,
+) -> Result<(Vec<u8>, u64), RemoteZipError> {
+    let (bytes, complete_length) = send_range_request(client, url, header_map, format!("bytes=-{}", len)).await?;
+    let complete_length = complete_length.ok_or(RemoteZipError::InvalidArchive("unknown archive size"))?;
+    Ok((bytes, complete_length))
+}
+
+async fn fetch_range(
+    client: &reqwest::Client,
+    url: &Url,
+    header_map: &HeaderMap,
+    start: u64,
+    len: u64,
+) -> Result<Vec<u8>, RemoteZipError> {
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    let end = start
+        .checked_add(len - 1)
+        .ok_or(RemoteZipError::InvalidArchive("range out of bounds"))?;
+    let range = format!("bytes={}-{}", start, end);
+    let (bytes, _) = send_range_request(client, url, header_map, range).await?;
+    if (bytes.len() as u64) < len {
+        return Err(RemoteZipError::InvalidArchive("unexpected end of data"));
+    }
+    Ok(bytes)
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct EndOfCentralDirectory {
+    entry_count: u64,
+    central_directory_size: u64,
+    central_directory_offset: u64,
+}
+
+impl EndOfCentralDirectory {
+    fn is_zip64(&self) -> bool {
+        self.entry_count == u16::MAX as u64
+            || self.central_directory_size == u32::MAX as u64
+            || self.central_directory_offset == u32::MAX as u64
+    }
+}
+
+struct ByteReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], RemoteZipError> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + len)
+            .ok_or(RemoteZipError::InvalidArchive("unexpected end of data"))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<(), RemoteZipError> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u16(&mut self) -> Result<u16, RemoteZipError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, RemoteZipError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, RemoteZipError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+fn find_eocd(tail: &[u8]) -> Option<usize> {
+    let signature = EOCD_SIGNATURE.to_le_bytes();
+    (0..=tail.len().checked_sub(EOCD_LEN)?)
+        .rev()
+        .find(|&index| tail[index..index + 4] == signature)
+}
+
+fn parse_eocd(data: &[u8]) -> Result<EndOfCentralDirectory, RemoteZipError> {
+    let mut reader = ByteReader::new(data);
+    if reader.u32()? != EOCD_SIGNATURE {
+        return Err(RemoteZipError::InvalidArchive("invalid end of central directory signature"));
+    }
+    // 磁盘编号、中央目录起始磁盘编号、本磁盘条目数
+    reader.skip(6)?;
+    let entry_count = reader.u16()? as u64;
+    let central_directory_size = reader.u32()? as u64;
+    let central_directory_offset = reader.u32()? as u64;
+    Ok(EndOfCentralDirectory {
+        entry_count,
+        central_directory_size,
+        central_directory_offset,
+    })
+}
+
+fn parse_zip64_eocd_locator(data: &[u8]) -> Result<u64, RemoteZipError> {
+    let mut reader = ByteReader::new(data);
+    if reader.u32()? != ZIP64_EOCD_LOCATOR_SIGNATURE {
+        return Err(RemoteZipError::InvalidArchive("zip64 locator not found"));
+    }
+    reader.skip(4)?;
+    reader.u64()
+}
+
+fn parse_zip64_eocd(data: &[u8]) -> Result<EndOfCentralDirectory, RemoteZipError> {
+    let mut reader = ByteReader::new(data);
+    if reader.u32()? != ZIP64_EOCD_SIGNATURE {
+        return Err(RemoteZipError::InvalidArchive("invalid zip64 end of central directory signature"));
+    }
+    // 记录大小、创建版本、所需版本、磁盘编号、中央目录起始磁盘编号、本磁盘条目数
+    reader.skip(8 + 2 + 2 + 4 + 4 + 8)?;
+    let entry_count = reader.u64()?;
+    let central_directory_size = reader.u64()?;
+    let central_directory_offset = reader.u64()?;
+    Ok(EndOfCentralDirectory {
+        entry_count,
+        central_directory_size,
+        central_directory_offset,
+    })
+}
+
+fn parse_central_directory(data: &[u8], entry_count: u64) -> Result<Vec<ZipEntry>, RemoteZipError> {
+    let mut reader = ByteReader::new(data);
+    let mut entries = Vec::with_capacity(entry_count.min(u16::MAX as u64) as usize);
+    for _ in 0..entry_count {
+        if reader.u32()? != CENTRAL_DIRECTORY_HEADER_SIGNATURE {
+            return Err(RemoteZipError::InvalidArchive("invalid central directory header signature"));
+        }
+        // 创建版本、所需版本
+        reader.skip(4)?;
+        let flags = reader.u16()?;
+        let compression_method = reader.u16()?;
+        // 修改时间、修改日期
+        reader.skip(4)?;
+        let crc32 = reader.u32()?;
+        let mut compressed_size = reader.u32()? as u64;
+        let mut uncompressed_size = reader.u32()? as u64;
+        let name_len = reader.u16()? as usize;
+        let extra_len = reader.u16()? as usize;
+        let comment_len = reader.u16()? as usize;
+        // 起始磁盘编号、内部属性、外部属性
+        reader.skip(2 + 2 + 4)?;
+        let mut local_header_offset = reader.u32()? as u64;
+        let name = String::from_utf8_lossy(reader.take(name_len)?).into_owned();
+        let extra = reader.take(extra_len)?;
+        reader.skip(comment_len)?;
+
+        // ZIP64 扩展字段中只包含值为 0xFFFFFFFF 的字段，且顺序固定
+        let mut extra_reader = ByteReader::new(extra);
+        while let (Ok(id), Ok(len)) = (extra_reader.u16(), extra_reader.u16()) {
+            let field = extra_reader.take(len as usize)?;
+            if id != ZIP64_EXTRA_FIELD_ID {
+                continue;
+            }
+            let mut field_reader = ByteReader::new(field);
+            if uncompressed_size == u32::MAX as u64 {
+                uncompressed_size = field_reader.u64()?;
+            }
+            if compressed_size == u32::MAX as u64 {
+                compressed_size = field_reader.u64()?;
+            }
+            if local_header_offset == u32::MAX as u64 {
+                local_header_offset = field_reader.u64()?;
+            }
+        }
+
+        entries.push(ZipEntry {
+            name,
+            compression_method: compression_method.into(),
+            compressed_size,
+            uncompressed_size,
+            crc32,
+            local_header_offset,
+            is_encrypted: flags & 1 != 0,
+        });
+    }
+    Ok(entries)
+}
+
+fn parse_local_header_len(data: &[u8]) -> Result<u64, RemoteZipError> {
+    let mut reader = ByteReader::new(data);
+    if reader.u32()? != LOCAL_FILE_HEADER_SIGNATURE {
+        return Err(RemoteZipError::InvalidArchive("invalid local file header signature"));
+    }
+    reader.skip(22)?;
+    let name_len = reader.u16()? as u64;
+    let extra_len = reader.u16()? as u64;
+    Ok(LOCAL_FILE_HEADER_LEN as u64 + name_len + extra_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 只含一个 deflate 压缩条目 a.txt（内容为 "hello world\n"）的 zip
+    const SMALL_ZIP: &str = "504b03041400000008001595525d2d3b08af0e0000000c00000005000000612e747874cb48cdc9c95728cf2fca49e10200504b010214031400000008001595525d2d3b08af0e0000000c000000050000000000000000000000800100000000612e747874504b0506000000000100010033000000310000000000";
+
+    fn hex_decode(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn parse_small_zip() {
+        let data = hex_decode(SMALL_ZIP);
+        let eocd_index = find_eocd(&data).unwrap();
+        let eocd = parse_eocd(&data[eocd_index..]).unwrap();
+        assert!(!eocd.is_zip64());
+        let start = eocd.central_directory_offset as usize;
+        let end = start + eocd.central_directory_size as usize;
+        let entries = parse_central_directory(&data[start..end], eocd.entry_count).unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.name, "a.txt");
+        assert_eq!(entry.compression_method, ZipCompressionMethod::Deflated);
+        assert_eq!(entry.uncompressed_size, 12);
+        assert_eq!(entry.compressed_size, 14);
+        assert_eq!(entry.local_header_offset, 0);
+        assert_eq!(parse_local_header_len(&data).unwrap(), 35);
+    }
+
+    #[test]
+    fn parse_zip64_central_directory() {
+        let mut data = Vec::new();
+        data.extend(CENTRAL_DIRECTORY_HEADER_SIGNATURE.to_le_bytes());
+        data.extend([0; 4]);
+        data.extend(0u16.to_le_bytes());
+        data.extend(0u16.to_le_bytes());
+        data.extend([0; 4]);
+        data.extend(0x1234u32.to_le_bytes());
+        data.extend(u32::MAX.to_le_bytes());
+        data.extend(u32::MAX.to_le_bytes());
+        data.extend(5u16.to_le_bytes());
+        data.extend(28u16.to_le_bytes());
+        data.extend(0u16.to_le_bytes());
+        data.extend([0; 8]);
+        data.extend(u32::MAX.to_le_bytes());
+        data.extend(b"a.bin");
+        data.extend(ZIP64_EXTRA_FIELD_ID.to_le_bytes());
+        data.extend(24u16.to_le_bytes());
+        data.extend(6_000_000_000u64.to_le_bytes());
+        data.extend(5_000_000_000u64.to_le_bytes());
+        data.extend(7_000_000_000u64.to_le_bytes());
+
+        let entries = parse_central_directory(&data, 1).unwrap();
+        let entry = &entries[0];
+        assert_eq!(entry.compression_method, ZipCompressionMethod::Stored);
+        assert_eq!(entry.crc32, 0x1234);
+        assert_eq!(entry.uncompressed_size, 6_000_000_000);
+        assert_eq!(entry.compressed_size, 5_000_000_000);
+        assert_eq!(entry.local_header_offset, 7_000_000_000);
+    }
+
+    fn entry(name: &str, local_header_offset: u64, compressed_size: u64) -> ZipEntry {
+        ZipEntry {
+            name: name.to_string(),
+            compression_method: ZipCompressionMethod::Stored,
+            compressed_size,
+            uncompressed_size: compressed_size,
+            crc32: 0,
+            local_header_offset,
+            is_encrypted: false,
+        }
+    }
+
+    #[test]
+    fn untrusted_offsets_do_not_overflow() {
+        let eocd = |central_directory_offset, central_directory_size| EndOfCentralDirectory {
+            entry_count: 1,
+            central_directory_size,
+            central_directory_offset,
+        };
+        assert_eq!(central_directory_in_tail(&eocd(900, 50), 1000, 800, 200).unwrap(), Some(100..150));
+        assert_eq!(central_directory_in_tail(&eocd(100, 50), 1000, 800, 200).unwrap(), None);
+        assert!(central_directory_in_tail(&eocd(u64::MAX - 10, 100), 1000, 800, 200).is_err());
+        assert!(central_directory_in_tail(&eocd(900, 200), 1000, 800, 200).is_err());
+        // 服务器返回的末尾数据比声明的短
+        assert!(central_directory_in_tail(&eocd(900, 50), 1000, 800, 120).is_err());
+
+        assert_eq!(entry_data_range(&entry("a", 100, 10), 30, 1000).unwrap(), 130..=139);
+        assert!(entry_data_range(&entry("a", u64::MAX - 10, 10), 30, u64::MAX).is_err());
+        assert!(entry_data_range(&entry("a", 10, u64::MAX), 30, u64::MAX).is_err());
+        assert!(entry_data_range(&entry("a", 990, 10), 30, 1000).is_err());
+        assert!(entry_data_range(&entry("a", 0, 0), 0, 1000).is_err());
+    }
+
+    #[test]
+    fn entry_file_name_stays_in_save_dir() {
+        assert_eq!(entry("dir/sub/a.txt", 0, 1).file_name(), Some("a.txt"));
+        assert_eq!(entry("a.txt", 0, 1).file_name(), Some("a.txt"));
+        for name in ["..\\..\\x.exe", "dir/../a.txt", "..", "C:x.exe", "dir/", "dir/.", "a\\b"] {
+            assert_eq!(entry(name, 0, 1).file_name(), None, "{}", name);
+        }
+    }
+
+    // 按顺序写入条目，`comment_len` 较长时中央目录不在首次获取的末尾数据中
+    fn build_zip(entries: &[(&str, bool, &[u8])], comment_len: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut central_directory = Vec::new();
+        for (name, deflate, content) in entries {
+            let mut crc = flate2::Crc::new();
+            crc.update(content);
+            let (method, compressed) = if *deflate {
+                let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(content).unwrap();
+                (8u16, encoder.finish().unwrap())
+            } else {
+                (0u16, content.to_vec())
+            };
+            let fields = |header: &mut Vec<u8>| {
+                header.extend(0u16.to_le_bytes());
+                header.extend(method.to_le_bytes());
+                header.extend([0; 4]);
+                header.extend(crc.sum().to_le_bytes());
+                header.extend((compressed.len() as u32).to_le_bytes());
+                header.extend((content.len() as u32).to_le_bytes());
+                header.extend((name.len() as u16).to_le_bytes());
+                header.extend(0u16.to_le_bytes());
+            };
+            central_directory.extend(CENTRAL_DIRECTORY_HEADER_SIGNATURE.to_le_bytes());
+            central_directory.extend(20u16.to_le_bytes());
+            central_directory.extend(20u16.to_le_bytes());
+            fields(&mut central_directory);
+            central_directory.extend(0u16.to_le_bytes());
+            central_directory.extend([0; 8]);
+            central_directory.extend((data.len() as u32).to_le_bytes());
+            central_directory.extend(name.as_bytes());
+
+            data.extend(LOCAL_FILE_HEADER_SIGNATURE.to_le_bytes());
+            data.extend(20u16.to_le_bytes());
+            fields(&mut data);
+            data.extend(name.as_bytes());
+            data.extend(compressed);
+        }
+        let central_directory_offset = data.len() as u32;
+        data.extend(&central_directory);
+        data.extend(EOCD_SIGNATURE.to_le_bytes());
+        data.extend([0; 4]);
+        data.extend((entries.len() as u16).to_le_bytes());
+        data.extend((entries.len() as u16).to_le_bytes());
+        data.extend((central_directory.len() as u32).to_le_bytes());
+        data.extend(central_directory_offset.to_le_bytes());
+        data.extend((comment_len as u16).to_le_bytes());
+        data.extend(vec![b'#'; comment_len]);
+        data
+    }
+
+    #[tokio::test]
+    async fn extract_stored_and_deflated_entries() {
+        use std::num::{NonZeroU8, NonZeroUsize};
+        use std::sync::Arc;
+
+        use crate::test_server::{TestResponse, TestServer};
+
+        let stored: Vec<u8> = (0..100 * 1024u32).map(|n| (n % 251) as u8).collect();
+        let deflated: Vec<u8> = (0..200 * 1024u32).map(|n| (n / 100 % 7) as u8).collect();
+        let save_dir = std::env::temp_dir().join(format!("http-downloader-remote-zip-{}", std::process::id()));
+        let configure = |builder: HttpDownloaderBuilder| builder
+            .chunk_size(NonZeroUsize::new(16 * 1024).unwrap())
+            .download_connection_count(NonZeroU8::new(2).unwrap());
+
+        for comment_len in [0, u16::MAX as usize] {
+            let zip = Arc::new(build_zip(&[("dir/stored.bin", false, &stored), ("deflated.bin", true, &deflated)], comment_len));
+            let server = TestServer::start({
+                let zip = zip.clone();
+                move |request| TestResponse::ranged(request, &zip)
+            }).await;
+            let remote_zip = RemoteZip::open(reqwest::Client::new(), server.url("/a.zip"), HeaderMap::new()).await.unwrap();
+            assert_eq!(remote_zip.content_length(), zip.len() as u64);
+            assert_eq!(remote_zip.entries().len(), 2);
+            // 先获取末尾部分，注释较长时再单独获取中央目录
+            let ranges: Vec<_> = server.requests().iter().map(|n| n.header("range").unwrap().to_string()).collect();
+            assert_eq!(ranges[0], format!("bytes=-{}", MAX_EOCD_SEARCH_LEN));
+            assert_eq!(ranges.len(), if comment_len == 0 { 1 } else { 2 });
+
+            let entry = remote_zip.find_entry("dir/stored.bin").unwrap();
+            assert_eq!(entry.compression_method, ZipCompressionMethod::Stored);
+            let file_path = remote_zip.extract_with(entry, save_dir.clone(), configure).await.unwrap();
+            assert_eq!(file_path, save_dir.join("stored.bin"));
+            assert_eq!(std::fs::read(&file_path).unwrap(), stored);
+
+            let entry = remote_zip.find_entry("deflated.bin").unwrap();
+            assert_eq!(entry.compression_method, ZipCompressionMethod::Deflated);
+            assert!(entry.compressed_size < deflated.len() as u64);
+            let file_path = remote_zip.extract_with(entry, save_dir.clone(), configure).await.unwrap();
+            assert_eq!(std::fs::read(&file_path).unwrap(), deflated);
+            // 条目数据分多个 chunk 下载
+            assert!(server.requests().len() > 6);
+            assert_eq!(std::fs::read_dir(&save_dir).unwrap().count(), 2);
+
+            // CRC 不匹配时不留下目标文件
+            let mut entry = remote_zip.find_entry("dir/stored.bin").unwrap().clone();
+            entry.crc32 ^= 1;
+            std::fs::remove_dir_all(&save_dir).unwrap();
+            assert!(matches!(remote_zip.extract_with(&entry, save_dir.clone(), configure).await, Err(RemoteZipError::CrcMismatch)));
+            assert_eq!(std::fs::read_dir(&save_dir).unwrap().count(), 0);
+            std::fs::remove_dir_all(&save_dir).unwrap();
+        }
+    }
+}
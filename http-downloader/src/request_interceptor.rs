@@ -0,0 +1,32 @@
+use std::sync::Arc;
+
+/// 收到响应后要求重试的最大次数，避免认证失败时无限重试
+pub(crate) const MAX_INTERCEPTOR_RETRY_TIMES: usize = 3;
+
+/// 请求拦截器，作用于首个请求、重定向请求以及所有的 chunk 请求
+///
+/// 由扩展在 `build` 时添加到 `HttpFileDownloader::request_interceptors` 中
+pub trait RequestInterceptor: Send + Sync {
+    /// 请求发出前调用，可以添加认证、Cookie 等请求头
+    fn intercept_request(&self, request: &mut reqwest::Request);
+
+    /// 收到响应后调用，返回 `true` 表示需要重新发出请求，比如收到了认证质询
+    fn intercept_response(&self, _response: &reqwest::Response) -> bool {
+        false
+    }
+}
+
+pub(crate) fn intercept_request(interceptors: &[Arc<dyn RequestInterceptor>], request: &mut reqwest::Request) {
+    for interceptor in interceptors {
+        interceptor.intercept_request(request);
+    }
+}
+
+/// 每个拦截器都会收到响应，任意一个要求重试时返回 `true`
+pub(crate) fn intercept_response(interceptors: &[Arc<dyn RequestInterceptor>], response: &reqwest::Response) -> bool {
+    let mut retry = false;
+    for interceptor in interceptors {
+        retry |= interceptor.intercept_response(response);
+    }
+    retry
+}
@@ -0,0 +1,238 @@
+use std::io::SeekFrom;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use tokio::fs::File;
+use tokio::io::{self, AsyncRead, AsyncSeekExt, ReadBuf};
+use tokio::sync;
+
+enum ReaderFile {
+    None,
+    Opening(BoxFuture<'static, io::Result<File>>),
+    Ready(File),
+}
+
+/// 边下载边按顺序读取已落盘的内容，读到尚未下载的位置时会等待
+///
+/// 配合 `HttpDownloaderBuilder::sequential(true)` 使用，否则可能要等到下载快结束才有数据可读
+pub struct SequentialReader {
+    file_path: PathBuf,
+    file: ReaderFile,
+    position: u64,
+    content_length: Option<u64>,
+    contiguous_len: u64,
+    contiguous_len_receiver: Option<sync::watch::Receiver<u64>>,
+    changed_future: Option<BoxFuture<'static, (sync::watch::Receiver<u64>, bool)>>,
+    download_ended: bool,
+}
+
+impl SequentialReader {
+    pub fn new(
+        file_path: PathBuf,
+        content_length: Option<u64>,
+        mut contiguous_len_receiver: sync::watch::Receiver<u64>,
+    ) -> Self {
+        let contiguous_len = *contiguous_len_receiver.borrow_and_update();
+        Self {
+            file_path,
+            file: ReaderFile::None,
+            position: 0,
+            content_length,
+            contiguous_len,
+            contiguous_len_receiver: Some(contiguous_len_receiver),
+            changed_future: None,
+            download_ended: false,
+        }
+    }
+
+    /// 当前读取位置
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// 可以立即读取的长度
+    pub fn contiguous_len(&self) -> u64 {
+        self.contiguous_len
+    }
+
+    fn poll_file(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&mut File>> {
+        if let ReaderFile::None = self.file {
+            let file_path = self.file_path.clone();
+            let position = self.position;
+            self.file = ReaderFile::Opening(
+                async move {
+                    let mut file = File::open(file_path).await?;
+                    file.seek(SeekFrom::Start(position)).await?;
+                    Ok(file)
+                }
+                .boxed(),
+            );
+        }
+        if let ReaderFile::Opening(future) = &mut self.file {
+            match futures_util::ready!(future.poll_unpin(cx)) {
+                Ok(file) => self.file = ReaderFile::Ready(file),
+                Err(err) => {
+                    self.file = ReaderFile::None;
+                    return Poll::Ready(Err(err));
+                }
+            }
+        }
+        match &mut self.file {
+            ReaderFile::Ready(file) => Poll::Ready(Ok(file)),
+            _ => unreachable!(),
+        }
+    }
+
+    fn poll_contiguous_len_changed(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut future = match self.changed_future.take() {
+            Some(future) => future,
+            None => {
+                let mut receiver = self.contiguous_len_receiver.take().unwrap();
+                async move {
+                    let is_ok = receiver.changed().await.is_ok();
+                    (receiver, is_ok)
+                }
+                .boxed()
+            }
+        };
+        match future.poll_unpin(cx) {
+            Poll::Pending => {
+                self.changed_future = Some(future);
+                Poll::Pending
+            }
+            Poll::Ready((mut receiver, is_ok)) => {
+                self.contiguous_len = *receiver.borrow_and_update();
+                self.contiguous_len_receiver = Some(receiver);
+                if !is_ok {
+                    self.download_ended = true;
+                }
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+impl AsyncRead for SequentialReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this
+                .content_length
+                .is_some_and(|content_length| this.position >= content_length)
+            {
+                return Poll::Ready(Ok(()));
+            }
+            if this.position < this.contiguous_len {
+                let max_len = (this.contiguous_len - this.position).min(buf.remaining() as u64) as usize;
+                let file = futures_util::ready!(this.poll_file(cx))?;
+                let mut limited_buf = ReadBuf::new(buf.initialize_unfilled_to(max_len));
+                futures_util::ready!(Pin::new(file).poll_read(cx, &mut limited_buf))?;
+                let len = limited_buf.filled().len();
+                if len == 0 && max_len != 0 {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "file is shorter than the downloaded length",
+                    )));
+                }
+                buf.advance(len);
+                this.position += len as u64;
+                return Poll::Ready(Ok(()));
+            }
+            if this.download_ended {
+                return if this.content_length.is_none() {
+                    Poll::Ready(Ok(()))
+                } else {
+                    Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "download ended before the data was available",
+                    )))
+                };
+            }
+            futures_util::ready!(this.poll_contiguous_len_changed(cx));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::{NonZeroU8, NonZeroUsize};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::io::AsyncReadExt;
+
+    use crate::{DownloadingEndCause, ExtendedHttpFileDownloader, HttpDownloaderBuilder};
+    use crate::test_server::{TestResponse, TestServer};
+
+    use super::*;
+
+    // 每个 chunk 的响应都有延迟，读取时下载还没有结束
+    async fn start_server(data: Arc<Vec<u8>>) -> TestServer {
+        TestServer::start(move |request| {
+            if request.header("range").is_some() {
+                std::thread::sleep(Duration::from_millis(30));
+            }
+            TestResponse::ranged(request, &data)
+        }).await
+    }
+
+    async fn start_download(server: &TestServer, save_dir: &std::path::Path) -> (Arc<ExtendedHttpFileDownloader>, tokio::task::JoinHandle<DownloadingEndCause>) {
+        let (mut downloader, _) = HttpDownloaderBuilder::new(server.url("/file.bin"), save_dir.to_path_buf())
+            .chunk_size(NonZeroUsize::new(16 * 1024).unwrap())
+            .download_connection_count(NonZeroU8::new(2).unwrap())
+            .sequential(true)
+            .build(());
+        let downloading_state_receiver = downloader.downloading_state_receiver();
+        let download_future = downloader.prepare_download().unwrap();
+        let handle = tokio::spawn(async move { download_future.await.unwrap() });
+        downloading_state_receiver.await.unwrap();
+        (Arc::new(downloader), handle)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn read_while_downloading() {
+        let data: Arc<Vec<u8>> = Arc::new((0..256 * 1024u32).map(|n| (n % 229) as u8).collect());
+        let server = start_server(data.clone()).await;
+        let save_dir = std::env::temp_dir().join(format!("http-downloader-sequential-{}", std::process::id()));
+
+        let (downloader, handle) = start_download(&server, &save_dir).await;
+        let mut reader = downloader.sequential_reader().unwrap();
+        let mut buffer = vec![0; 1024];
+        let len = reader.read(&mut buffer).await.unwrap();
+        assert!(len > 0);
+        assert!(!handle.is_finished());
+        buffer.truncate(len);
+        reader.read_to_end(&mut buffer).await.unwrap();
+        assert_eq!(buffer, *data);
+        assert_eq!(reader.position(), data.len() as u64);
+        assert_eq!(handle.await.unwrap(), DownloadingEndCause::DownloadFinished);
+        std::fs::remove_dir_all(&save_dir).unwrap();
+    }
+
+    // 下载被取消时，读到未下载的位置返回错误
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cancel_while_reading() {
+        let data: Arc<Vec<u8>> = Arc::new((0..256 * 1024u32).map(|n| (n % 227) as u8).collect());
+        let server = start_server(data.clone()).await;
+        let save_dir = std::env::temp_dir().join(format!("http-downloader-sequential-cancel-{}", std::process::id()));
+
+        let (downloader, handle) = start_download(&server, &save_dir).await;
+        let mut reader = downloader.sequential_reader().unwrap();
+        let mut buffer = vec![0; 32 * 1024];
+        reader.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(buffer, data[..buffer.len()]);
+        downloader.cancel().await;
+        assert_eq!(handle.await.unwrap(), DownloadingEndCause::Cancelled);
+        let err = reader.read_to_end(&mut Vec::new()).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(reader.position() < data.len() as u64);
+        std::fs::remove_dir_all(&save_dir).unwrap();
+    }
+}
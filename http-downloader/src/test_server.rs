@@ -0,0 +1,138 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+/// 测试服务器收到的请求
+#[derive(Debug, Clone)]
+pub(crate) struct TestRequest {
+    pub method: String,
+    // 包含查询参数
+    pub target: String,
+    // 请求头名称为小写
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl TestRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+/// 测试服务器的响应
+pub(crate) struct TestResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub fn new(status: u16) -> Self {
+        Self { status, headers: vec![], body: vec![] }
+    }
+
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.to_string(), value.into()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// 按请求的 Range（`a-b`、`a-`、`-n`）返回 `data` 的一部分，没有 Range 时返回全部
+    pub fn ranged(request: &TestRequest, data: &[u8]) -> Self {
+        let len = data.len() as u64;
+        let range = request.header("range").and_then(|n| n.strip_prefix("bytes=")).and_then(|n| n.split_once('-'));
+        let (start, end) = match range {
+            None => return Self::new(200).header("Accept-Ranges", "bytes").body(data),
+            Some(("", suffix)) => (len.saturating_sub(suffix.parse().unwrap()), len.saturating_sub(1)),
+            Some((start, "")) => (start.parse().unwrap(), len.saturating_sub(1)),
+            Some((start, end)) => (start.parse().unwrap(), end.parse::<u64>().unwrap().min(len.saturating_sub(1))),
+        };
+        if start > end || start >= len {
+            return Self::new(416).header("Content-Range", format!("bytes */{}", len));
+        }
+        Self::new(206)
+            .header("Accept-Ranges", "bytes")
+            .header("Content-Range", format!("bytes {}-{}/{}", start, end, len))
+            .body(&data[start as usize..=end as usize])
+    }
+}
+
+/// 只处理测试需要的 HTTP/1.1 子集：请求体按 Content-Length 读取，响应总是带 Content-Length
+pub(crate) struct TestServer {
+    pub addr: SocketAddr,
+    requests: Arc<parking_lot::Mutex<Vec<TestRequest>>>,
+}
+
+impl TestServer {
+    pub async fn start(handler: impl Fn(&TestRequest) -> TestResponse + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests: Arc<parking_lot::Mutex<Vec<TestRequest>>> = Default::default();
+        let handler = Arc::new(handler);
+        tokio::spawn({
+            let requests = requests.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let requests = requests.clone();
+                    let handler = handler.clone();
+                    tokio::spawn(async move {
+                        let (reader, mut writer) = stream.into_split();
+                        let mut reader = BufReader::new(reader);
+                        while let Some(request) = read_request(&mut reader).await {
+                            let response = handler(&request);
+                            requests.lock().push(request);
+                            let mut head = format!("HTTP/1.1 {} X\r\nContent-Length: {}\r\n", response.status, response.body.len());
+                            for (name, value) in response.headers.iter() {
+                                head.push_str(&format!("{}: {}\r\n", name, value));
+                            }
+                            head.push_str("\r\n");
+                            if writer.write_all(head.as_bytes()).await.is_err() || writer.write_all(&response.body).await.is_err() {
+                                break;
+                            }
+                        }
+                    });
+                }
+            }
+        });
+        Self { addr, requests }
+    }
+
+    pub fn url(&self, path: &str) -> url::Url {
+        format!("http://{}{}", self.addr, path).parse().unwrap()
+    }
+
+    pub fn requests(&self) -> Vec<TestRequest> {
+        self.requests.lock().clone()
+    }
+}
+
+async fn read_request(reader: &mut (impl AsyncBufReadExt + Unpin)) -> Option<TestRequest> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?.to_string();
+    let mut headers = vec![];
+    loop {
+        line.clear();
+        reader.read_line(&mut line).await.ok()?;
+        let Some((name, value)) = line.trim_end().split_once(':') else {
+            break;
+        };
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+    let content_length = headers
+        .iter()
+        .find(|(n, _)| n == "content-length")
+        .and_then(|(_, v)| v.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await.ok()?;
+    Some(TestRequest { method, target, headers, body })
+}
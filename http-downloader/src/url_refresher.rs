@@ -0,0 +1,179 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+
+use futures_util::future::BoxFuture;
+use headers::{ContentRange, ETag, HeaderMap, HeaderMapExt};
+use reqwest::{Request, Response, StatusCode};
+use url::Url;
+
+use crate::{DownloadError, HttpDownloadConfig};
+
+/// 一次请求连续刷新地址的最大次数，避免新地址依然无效时无限刷新
+pub(crate) const MAX_URL_REFRESH_TIMES: usize = 3;
+
+/// 刷新后的地址与请求头
+#[derive(Debug, Clone)]
+pub struct RefreshedUrl {
+    pub url: Url,
+    // 覆盖同名的请求头
+    pub header_map: HeaderMap,
+}
+
+impl RefreshedUrl {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            header_map: Default::default(),
+        }
+    }
+
+    pub(crate) fn apply(&self, request: &mut Request) {
+        *request.url_mut() = self.url.clone();
+        for (header_name, header_value) in self.header_map.iter() {
+            request.headers_mut().insert(header_name, header_value.clone());
+        }
+    }
+}
+
+/// 签名地址过期时获取新的地址，请求返回 401、403、410 时调用
+pub trait UrlRefresher: Send + Sync {
+    /// `expired_url` 为当前失效的地址
+    fn refresh(&self, expired_url: &Url) -> BoxFuture<'static, anyhow::Result<RefreshedUrl>>;
+}
+
+/// 一次下载中所有请求共享，同一时间只刷新一次，其他请求等待刷新结果
+pub struct UrlRefresh {
+    refresher: Arc<dyn UrlRefresher>,
+    config: Arc<HttpDownloadConfig>,
+    // 每刷新一次加一，请求记录自己使用的版本，版本已经变化时直接使用新的地址
+    generation: AtomicUsize,
+    lock: tokio::sync::Mutex<()>,
+    // 首个请求得到的远程文件大小与 ETag，刷新后的响应必须与之一致
+    expected: OnceLock<(Option<u64>, Option<ETag>)>,
+}
+
+impl UrlRefresh {
+    pub(crate) fn new(refresher: Arc<dyn UrlRefresher>, config: Arc<HttpDownloadConfig>) -> Self {
+        Self {
+            refresher,
+            config,
+            generation: AtomicUsize::new(0),
+            lock: Default::default(),
+            expected: OnceLock::new(),
+        }
+    }
+
+    pub(crate) fn is_expired_status(status: StatusCode) -> bool {
+        matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::GONE)
+    }
+
+    pub(crate) fn generation(&self) -> usize {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// 当前的版本与地址，没有刷新过时地址为 None
+    pub(crate) fn current(&self) -> (usize, Option<RefreshedUrl>) {
+        (self.generation(), self.config.refreshed_url.read().clone())
+    }
+
+    /// 记录首个请求响应中的远程文件大小与 ETag
+    pub(crate) fn set_expected(&self, response: &Response) {
+        let headers = response.headers();
+        let content_length = match headers.typed_get::<ContentRange>() {
+            Some(content_range) => content_range.bytes_len(),
+            None => headers.typed_get::<headers::ContentLength>().map(|n| n.0),
+        };
+        let _ = self.expected.set((content_length, headers.typed_get::<ETag>()));
+    }
+
+    /// 使用版本为 `generation` 的地址请求失败后调用，返回新的版本与地址
+    pub(crate) async fn refresh(&self, expired_url: &Url, generation: usize) -> Result<(usize, RefreshedUrl), DownloadError> {
+        let _guard = self.lock.lock().await;
+        let current_generation = self.generation();
+        if current_generation != generation {
+            if let Some(refreshed_url) = self.config.refreshed_url.read().clone() {
+                return Ok((current_generation, refreshed_url));
+            }
+        }
+        #[cfg(feature = "tracing")]
+        tracing::info!("refresh expired url: {}", expired_url);
+        let refreshed_url = self
+            .refresher
+            .refresh(expired_url)
+            .await
+            .map_err(DownloadError::UrlRefreshFailed)?;
+        *self.config.refreshed_url.write() = Some(refreshed_url.clone());
+        self.generation.store(current_generation + 1, Ordering::SeqCst);
+        Ok((current_generation + 1, refreshed_url))
+    }
+
+    /// 刷新后的地址指向的文件是否与之前相同
+    pub(crate) fn is_same_file(&self, response: &Response) -> bool {
+        let Some((content_length, etag)) = self.expected.get() else {
+            return true;
+        };
+        let size_matched = match (content_length, response.headers().typed_get::<ContentRange>().and_then(|n| n.bytes_len())) {
+            (Some(content_length), Some(cur_content_length)) => *content_length == cur_content_length,
+            _ => true,
+        };
+        let etag_matched = match (etag, response.headers().typed_get::<ETag>()) {
+            (Some(etag), Some(cur_etag)) => *etag == cur_etag,
+            _ => true,
+        };
+        size_matched && etag_matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use futures_util::FutureExt;
+
+    use crate::HttpDownloaderBuilder;
+
+    use super::*;
+
+    struct CountingRefresher {
+        count: Arc<AtomicUsize>,
+    }
+
+    impl UrlRefresher for CountingRefresher {
+        fn refresh(&self, _expired_url: &Url) -> BoxFuture<'static, anyhow::Result<RefreshedUrl>> {
+            let count = self.count.fetch_add(1, Ordering::SeqCst) + 1;
+            async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                Ok(RefreshedUrl::new(format!("https://example.com/a.bin?sig={}", count).parse().unwrap()))
+            }.boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn refresh_once_per_generation() {
+        let url: Url = "https://example.com/a.bin?sig=0".parse().unwrap();
+        let (downloader, _) = HttpDownloaderBuilder::new(url.clone(), PathBuf::from("/tmp")).build(());
+        let count = Arc::new(AtomicUsize::new(0));
+        let url_refresh = UrlRefresh::new(Arc::new(CountingRefresher { count: count.clone() }), downloader.inner.config.clone());
+        assert_eq!(url_refresh.current().1.map(|n| n.url), None);
+
+        // 同一版本的请求同时失效，只刷新一次
+        let (a, b) = tokio::join!(url_refresh.refresh(&url, 0), url_refresh.refresh(&url, 0));
+        let (a, b) = (a.unwrap(), b.unwrap());
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        assert_eq!((a.0, b.0), (1, 1));
+        assert_eq!(a.1.url, b.1.url);
+
+        // 使用旧版本的请求直接得到新地址
+        let (generation, refreshed_url) = url_refresh.refresh(&url, 0).await.unwrap();
+        assert_eq!((generation, count.load(Ordering::SeqCst)), (1, 1));
+        assert_eq!(refreshed_url.url.as_str(), "https://example.com/a.bin?sig=1");
+
+        // 新地址也失效后再次刷新
+        let (generation, refreshed_url) = url_refresh.refresh(&refreshed_url.url, 1).await.unwrap();
+        assert_eq!((generation, count.load(Ordering::SeqCst)), (2, 2));
+        assert_eq!(refreshed_url.url.as_str(), "https://example.com/a.bin?sig=2");
+        assert_eq!(url_refresh.current().0, 2);
+        assert_eq!(downloader.inner.config.create_http_request(None).url(), &refreshed_url.url);
+    }
+}
@@ -8,6 +8,7 @@ use crate::{
     },
     utils::{
         global_wrappers::{self, get_wrapper_by_id},
+        host_connection_limiter::GLOBAL_HOST_CONNECTION_LIMITER,
         status_conversion::{self, DownloaderStatusWrapper},
     },
 };
@@ -18,7 +19,7 @@ use http_downloader::{
     speed_limiter::DownloadSpeedLimiterExtension,
     speed_tracker::DownloadSpeedTrackerExtension,
     status_tracker::{DownloadStatusTrackerExtension, DownloaderStatus},
-    HttpDownloaderBuilder,
+    ClientConfig, DownloadSpec, HttpDownloaderBuilder,
 };
 use salvo::prelude::*;
 use serde_json::{json, to_value, Value};
@@ -45,20 +46,43 @@ pub async fn start_download_api(req: &mut Request, res: &mut Response) {
     let headers = String::from_utf8(headers_utf8).unwrap_or_default();
     let headers: HashMap<String, String> = serde_json::from_str(&headers).unwrap();
     info!("headers is {:?}", headers.clone());
+    // 代理、证书等客户端配置，与 headers 一样是 base64 编码的 JSON
+    let client_config = match req.query::<String>("client_config") {
+        None => ClientConfig::default(),
+        Some(raw) => match parse_client_config(&raw) {
+            Ok(client_config) => client_config,
+            Err(err) => {
+                let msg = format!("Invalid client_config: {}", err);
+                let result = NalaiResult::new(StatusCode::BAD_REQUEST, Some(&msg), Value::Null);
+                res.status_code(StatusCode::BAD_REQUEST);
+                res.render(Json(result));
+                return;
+            }
+        },
+    };
 
-    let id = start_download(&url, &save_dir, file_name, pre_id, Some(headers)).await;
+    let builder = new_download_builder(&url, &save_dir, file_name, Some(headers), client_config);
+    let id = start_download(builder, pre_id).await;
 
     let result = NalaiResult::new(StatusCode::OK, None, json!({"id": &id}));
     res.render(Json(result));
 }
 
-async fn start_download(
+fn parse_client_config(raw: &str) -> Result<ClientConfig, String> {
+    let json = general_purpose::STANDARD
+        .decode(raw.as_bytes())
+        .map_err(|err| err.to_string())?;
+    serde_json::from_slice::<ClientConfig>(&json).map_err(|err| err.to_string())
+}
+
+/// 新任务的下载选项
+fn new_download_builder(
     url: &Url,
     save_dir: &PathBuf,
     file_name: Option<String>,
-    mut id: Option<String>,
     headers: Option<HashMap<String, String>>,
-) -> String {
+    client_config: ClientConfig,
+) -> HttpDownloaderBuilder {
     let mut headers_map = headers::HeaderMap::new();
     if let Some(new_headers) = headers {
         for (key, value) in new_headers {
@@ -69,13 +93,38 @@ async fn start_download(
             }
         }
     }
+    HttpDownloaderBuilder::new(url.clone(), save_dir.clone())
+        .chunk_size(NonZeroUsize::new(1024 * 1024 * 10).unwrap()) // 块大小
+        .download_connection_count(NonZeroU8::new(8).unwrap())
+        .downloaded_len_send_interval(Some(Duration::from_millis(100)))
+        .file_name(file_name)
+        .header_map(headers_map)
+        .client_config(client_config)
+}
+
+/// 旧数据中没有 `spec`，按保存的地址、请求头等重新创建
+fn saved_download_builder(info: &NalaiDownloadInfo) -> Result<HttpDownloaderBuilder, String> {
+    match info.spec.clone() {
+        Some(spec) => HttpDownloaderBuilder::try_from(spec).map_err(|err| err.to_string()),
+        None => {
+            let url = Url::parse(&info.url).map_err(|err| err.to_string())?;
+            Ok(new_download_builder(
+                &url,
+                &PathBuf::from(&info.save_dir),
+                Some(info.file_name.clone()),
+                Some(info.headers.clone()),
+                info.client_config.clone().unwrap_or_default(),
+            ))
+        }
+    }
+}
+
+async fn start_download(builder: HttpDownloaderBuilder, mut id: Option<String>) -> String {
+    // 持久化后可以重建完全相同的下载器，共享的连接数限制是运行时对象，不在其中
+    let spec = DownloadSpec::from(&builder);
     let (downloader, (mut status_state, mut speed_state, _speed_limiter, ..)) =
-        HttpDownloaderBuilder::new(url.clone(), save_dir.clone())
-            .chunk_size(NonZeroUsize::new(1024 * 1024 * 10).unwrap()) // 块大小
-            .download_connection_count(NonZeroU8::new(8).unwrap())
-            .downloaded_len_send_interval(Some(Duration::from_millis(100)))
-            .file_name(file_name)
-            .header_map(headers_map)
+        builder
+            .host_connection_limiter(GLOBAL_HOST_CONNECTION_LIMITER.clone()) // 所有任务共享的连接数限制
             .build((
                 // 下载状态追踪扩展
                 // by cargo feature "status-tracker" enable
@@ -234,6 +283,10 @@ async fn start_download(
                                     create_time: original_info.create_time,
                                     chunks: chunks,
                                     headers: original_headers,
+                                    progress_map: d.progress_map(),
+                                    client_config: None,
+                                    spec: Some(spec.clone()),
+                                    resume_state: status_state.resume_state(),
                                 },
                             };
 
@@ -281,6 +334,10 @@ async fn start_download(
                                     create_time: original_info.create_time,
                                     chunks: chunks,
                                     headers: original_headers,
+                                    progress_map: d.progress_map(),
+                                    client_config: None,
+                                    spec: Some(spec.clone()),
+                                    resume_state: status_state.resume_state(),
                                 },
                             };
 
@@ -384,19 +441,8 @@ async fn cancel_or_start_download(id: &str) -> Result<(bool, bool), String> {
     match status.kind {
         StatusWrapperKind::NoStart => {
             // 未开始下载，直接开始下载
-            let url = Url::parse(&wrapper.info.url).unwrap();
-            let save_dir = PathBuf::from(&wrapper.info.save_dir);
-            let file_name = Some(wrapper.info.file_name.clone());
-            let headers = wrapper.info.headers.clone();
-
-            start_download(
-                &url,
-                &save_dir,
-                file_name,
-                Some(id.to_string()),
-                Some(headers),
-            )
-            .await;
+            let builder = saved_download_builder(&wrapper.info)?;
+            start_download(builder, Some(id.to_string())).await;
 
             Ok((true, true))
         }
@@ -412,19 +458,8 @@ async fn cancel_or_start_download(id: &str) -> Result<(bool, bool), String> {
         }
         StatusWrapperKind::Error => {
             // 下载出错，重新开始下载
-            let url = Url::parse(&wrapper.info.url).unwrap();
-            let save_dir = PathBuf::from(&wrapper.info.save_dir);
-            let file_name = Some(wrapper.info.file_name.clone());
-            let headers = wrapper.info.headers.clone();
-
-            start_download(
-                &url,
-                &save_dir,
-                file_name,
-                Some(id.to_string()),
-                Some(headers),
-            )
-            .await;
+            let builder = saved_download_builder(&wrapper.info)?;
+            start_download(builder, Some(id.to_string())).await;
 
             Ok((true, true))
         }
@@ -37,7 +37,7 @@ pub async fn get_info_api(req: &mut Request, res: &mut Response) {
                 }
             };
 
-            let result = NalaiResult::new(StatusCode::OK,Some("Info found") ,to_value(info).unwrap());
+            let result = NalaiResult::new(StatusCode::OK,Some("Info found") ,to_value(info.without_secrets()).unwrap());
 
             res.render(Json(result));
         }
@@ -53,7 +53,11 @@ pub async fn get_info_api(req: &mut Request, res: &mut Response) {
 pub async fn get_all_info_api(_req: &mut Request, res: &mut Response) {
     // global_wrappers::save_all_to_file().await.unwrap();
     global_wrappers::save_all_to_sled(false).await.unwrap();
-    let all_info = get_all_info().await;
+    let all_info: HashMap<String, NalaiDownloadInfo> = get_all_info()
+        .await
+        .into_iter()
+        .map(|(id, info)| (id, info.without_secrets()))
+        .collect();
     let result = NalaiResult::new( StatusCode::OK,Some("All info found"), to_value(all_info).unwrap());
     res.render(Json(result));
 }
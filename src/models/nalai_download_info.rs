@@ -1,5 +1,6 @@
 use std::{collections::HashMap, num::NonZero, time::SystemTime};
 
+use http_downloader::{ClientConfig, DownloadSpec, ProgressMap, ResumeState};
 use serde::{Deserialize, Serialize};
 use crate::models::chunk_wrapper::ChunkWrapper;
 use super::status_wrapper::StatusWrapper;
@@ -16,6 +17,30 @@ pub(crate) struct NalaiDownloadInfo {
     pub(crate) create_time: SystemTime,
     pub(crate) chunks: Vec<ChunkWrapper>,
     pub(crate) headers: HashMap<String, String>,
+    // 各块的下载进度，用于绘制进度条
+    #[serde(default)]
+    pub(crate) progress_map: Option<ProgressMap>,
+    // 旧数据中的客户端配置，只在没有 `spec` 时用于重新开始下载，新任务的客户端配置保存在 `spec` 中
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) client_config: Option<ClientConfig>,
+    // 创建下载器的全部选项，重新开始下载时按它重建下载器，旧数据中没有
+    #[serde(default)]
+    pub(crate) spec: Option<DownloadSpec>,
+    // 最近一次开始下载时是否从断点继续，重新开始时带有原因
+    #[serde(default)]
+    pub(crate) resume_state: Option<ResumeState>,
+}
+
+impl NalaiDownloadInfo {
+    /// 去掉代理密码等凭据，用于写入数据库与接口返回，内存中的任务保留完整的配置
+    pub(crate) fn without_secrets(&self) -> Self {
+        let mut info = self.clone();
+        info.client_config = info.client_config.as_ref().map(ClientConfig::without_secrets);
+        if let Some(spec) = info.spec.as_mut() {
+            spec.client_config = spec.client_config.without_secrets();
+        }
+        info
+    }
 }
 
 impl Default for NalaiDownloadInfo {
@@ -31,6 +56,10 @@ impl Default for NalaiDownloadInfo {
             create_time: SystemTime::now(),
             chunks: Default::default(),
             headers: Default::default(),
+            progress_map: Default::default(),
+            client_config: Default::default(),
+            spec: Default::default(),
+            resume_state: Default::default(),
         }
     }
 }
@@ -97,7 +97,7 @@ pub async fn save_all_to_sled(should_flush: bool) -> anyhow::Result<()> {
     let all_info = info::get_all_info().await;
     info!("获取数据成功");
     for (id, info) in all_info.iter() {
-        let info_bytes = serde_json::to_vec(&info)?;
+        let info_bytes = serde_json::to_vec(&info.without_secrets())?;
         db.insert(id.as_bytes(), info_bytes)?;
     }
     info!("数据已序列化");
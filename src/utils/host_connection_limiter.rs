@@ -0,0 +1,12 @@
+use std::{num::NonZeroUsize, sync::Arc};
+
+use http_downloader::HostConnectionLimiter;
+use once_cell::sync::Lazy;
+
+/// 所有下载任务共享的连接数限制，避免同时下载多个文件时对同一主机打开过多连接
+pub(crate) static GLOBAL_HOST_CONNECTION_LIMITER: Lazy<Arc<HostConnectionLimiter>> = Lazy::new(|| {
+    Arc::new(
+        HostConnectionLimiter::new(NonZeroUsize::new(16).unwrap())
+            .with_total_limit(NonZeroUsize::new(64).unwrap()),
+    )
+});
@@ -1,2 +1,3 @@
 pub mod global_wrappers;
+pub mod host_connection_limiter;
 pub mod status_conversion;
\ No newline at end of file
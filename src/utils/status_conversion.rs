@@ -75,6 +75,9 @@ impl IntoStatusWrapper for DownloadError {
                 .with_message("Server file already changed"),
             DownloadError::RedirectionTimesTooMany => StatusWrapper::new(StatusWrapperKind::Error)
                 .with_message("Redirection times too many"),
+            DownloadError::UrlRefreshFailed(error) => {
+                StatusWrapper::new(StatusWrapperKind::Error).with_message(error.to_string())
+            }
         }
     }
 }